@@ -0,0 +1,128 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AttractorKind {
+    Attractor,
+    Repeller,
+}
+
+impl Display for AttractorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Attractor => write!(f, "Attractor"),
+            Self::Repeller => write!(f, "Repeller"),
+        }
+    }
+}
+
+/// A point that pulls particles toward it, or pushes them away, on top of the flow field
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attractor {
+    pub kind: AttractorKind,
+    pub xy: Vec2,
+    pub strength: f32,
+    /// Distance in pixels at which the pull has dropped to half strength
+    pub falloff: f32,
+}
+
+impl Attractor {
+    pub fn new(kind: AttractorKind, xy: Vec2, strength: f32, falloff: f32) -> Self {
+        Self {
+            kind,
+            xy,
+            strength,
+            falloff,
+        }
+    }
+
+    /// The steering this attractor applies to a particle at `point`
+    pub fn steering_at(&self, point: Vec2) -> Vec2 {
+        let offset = self.xy - point;
+        let distance = offset.length();
+        if distance < f32::EPSILON {
+            return Vec2::ZERO;
+        }
+
+        let falloff = self.falloff.max(1.0);
+        let weight = self.strength / (1.0 + (distance / falloff).powi(2));
+        let direction = offset / distance;
+        match self.kind {
+            AttractorKind::Attractor => direction * weight,
+            AttractorKind::Repeller => -direction * weight,
+        }
+    }
+}
+
+/// Blend a field angle (in degrees) with the steering from every attractor
+pub fn steer<'a>(field_angle: f32, point: Vec2, attractors: impl Iterator<Item = &'a Attractor>) -> f32 {
+    let steering = attractors.fold(Vec2::ZERO, |acc, attractor| acc + attractor.steering_at(point));
+    if steering == Vec2::ZERO {
+        return field_angle;
+    }
+
+    let field_direction = Vec2::new(field_angle.to_radians().cos(), field_angle.to_radians().sin());
+    let heading = field_direction + steering;
+    heading.y.atan2(heading.x).to_degrees()
+}
+
+/// Find the attractor closest to `point`, if any are within its own falloff distance
+pub fn nearest_attractor(attractors: &[Attractor], point: Vec2) -> Option<usize> {
+    attractors
+        .iter()
+        .enumerate()
+        .map(|(index, attractor)| (index, attractor.xy.distance(point), attractor.falloff))
+        .filter(|(_, distance, falloff)| distance <= falloff)
+        .min_by(|(_, a, _), (_, b, _)| a.total_cmp(b))
+        .map(|(index, _, _)| index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attractor(kind: AttractorKind) -> Attractor {
+        Attractor::new(kind, Vec2::new(10.0, 20.0), 2.0, 100.0)
+    }
+
+    #[test]
+    fn attractors_pull_and_repellers_push() {
+        let point = Vec2::new(60.0, 20.0);
+        let pull = attractor(AttractorKind::Attractor).steering_at(point);
+        let push = attractor(AttractorKind::Repeller).steering_at(point);
+        assert!(pull.x < 0.0 && pull.y == 0.0, "{}", pull);
+        assert_eq!(push, -pull);
+    }
+
+    #[test]
+    fn strength_halves_at_the_falloff_distance() {
+        let attractor = attractor(AttractorKind::Attractor);
+        let at = |distance: f32| attractor.steering_at(attractor.xy + Vec2::new(0.0, distance)).length();
+        assert!((at(100.0) - 1.0).abs() < 1e-5);
+        assert!((at(0.001) - 2.0).abs() < 1e-5);
+        let strengths: Vec<f32> = (1..20).map(|i| at(i as f32 * 50.0)).collect();
+        assert!(strengths.windows(2).all(|pair| pair[0] > pair[1]), "{:?}", strengths);
+        // No pull in any direction from right on top of it
+        assert_eq!(attractor.steering_at(attractor.xy), Vec2::ZERO);
+    }
+
+    #[test]
+    fn steering_bends_the_field_toward_the_attractor() {
+        let attractors = [attractor(AttractorKind::Attractor)];
+        assert_eq!(steer(30.0, Vec2::new(10.0, -1000.0), [].iter()), 30.0);
+        // Right below the attractor, a field pointing along +x turns up toward it
+        let angle = steer(0.0, Vec2::new(10.0, -80.0), attractors.iter());
+        assert!(angle > 0.0 && angle < 90.0, "{}", angle);
+    }
+
+    #[test]
+    fn nearest_attractor_only_reaches_its_falloff() {
+        let mut far = attractor(AttractorKind::Repeller);
+        far.xy = Vec2::new(300.0, 20.0);
+        let attractors = [attractor(AttractorKind::Attractor), far];
+        assert_eq!(nearest_attractor(&attractors, Vec2::new(40.0, 20.0)), Some(0));
+        assert_eq!(nearest_attractor(&attractors, Vec2::new(250.0, 20.0)), Some(1));
+        assert_eq!(nearest_attractor(&attractors, Vec2::new(150.0, 500.0)), None);
+    }
+}
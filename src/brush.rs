@@ -0,0 +1,201 @@
+use egui_wgpu::wgpu;
+use image::RgbaImage;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Side of the built-in soft dot in pixels
+const SOFT_DOT_SIZE: u32 = 64;
+// Longest side of a brush in pixels. Every device can make textures this large, and sprites are
+// rarely drawn anywhere near it.
+const MAX_BRUSH_SIZE: u32 = 2048;
+
+/// A texture that sprite particles are stamped with, tinted by the particle color. Brushes
+/// either carry their shape in alpha, or are opaque and dark where they should paint, like a
+/// scan of a brush stroke on paper.
+#[derive(Clone)]
+pub struct Brush {
+    // None for the built-in soft dot
+    path: Option<PathBuf>,
+    image: RgbaImage,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self::soft_dot()
+    }
+}
+
+impl Brush {
+    /// A round dot that fades out toward its edge
+    pub fn soft_dot() -> Self {
+        let image = RgbaImage::from_fn(SOFT_DOT_SIZE, SOFT_DOT_SIZE, |x, y| {
+            let half = SOFT_DOT_SIZE as f32 / 2.0;
+            let dx = (x as f32 + 0.5 - half) / half;
+            let dy = (y as f32 + 0.5 - half) / half;
+            let falloff = (1.0 - (dx * dx + dy * dy)).max(0.0);
+            image::Rgba([255, 255, 255, (falloff * falloff * 255.0).round() as u8])
+        });
+        Self { path: None, image }
+    }
+
+    /// Load a brush image, shrunk to fit within `MAX_BRUSH_SIZE` if it's any larger
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut image = image::open(path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            .into_rgba8();
+        let (width, height) = image.dimensions();
+        if width.max(height) > MAX_BRUSH_SIZE {
+            let shrink = MAX_BRUSH_SIZE as f32 / width.max(height) as f32;
+            image = image::imageops::resize(
+                &image,
+                ((width as f32 * shrink).round() as u32).max(1),
+                ((height as f32 * shrink).round() as u32).max(1),
+                image::imageops::FilterType::Triangle,
+            );
+        }
+
+        // Without any transparency, paint where the image is dark
+        if image.pixels().all(|pixel| pixel[3] == 255) {
+            for pixel in image.pixels_mut() {
+                let luma = 0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32;
+                *pixel = image::Rgba([255, 255, 255, (255.0 - luma).round() as u8]);
+            }
+        }
+
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            image,
+        })
+    }
+
+    /// Where the brush was loaded from, or None for the soft dot
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    // The image followed by ever smaller copies down to 1x1, so small sprites don't shimmer
+    fn mip_chain(&self) -> Vec<RgbaImage> {
+        let mut levels = vec![self.image.clone()];
+        loop {
+            let (width, height) = levels.last().unwrap().dimensions();
+            if width == 1 && height == 1 {
+                return levels;
+            }
+            let next = image::imageops::resize(
+                levels.last().unwrap(),
+                (width / 2).max(1),
+                (height / 2).max(1),
+                image::imageops::FilterType::Triangle,
+            );
+            levels.push(next);
+        }
+    }
+}
+
+/// The brush on the GPU, bound as group 1 of the geometry pipelines
+pub struct BrushTexture {
+    bind_group: wgpu::BindGroup,
+}
+
+impl BrushTexture {
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Brush Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, layout: &wgpu::BindGroupLayout, brush: &Brush) -> Self {
+        let levels = brush.mip_chain();
+        let (width, height) = brush.dimensions();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Brush Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: levels.len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        for (level, image) in levels.iter().enumerate() {
+            let (width, height) = image.dimensions();
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                image.as_raw(),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(width * 4),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Brush Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Brush Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        Self { bind_group }
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+}
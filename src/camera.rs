@@ -0,0 +1,70 @@
+use glam::{Mat4, Vec2, Vec3};
+
+/// Zoom and pan over the canvas. The layers are always drawn at canvas size, and the camera only
+/// changes how the finished canvas is fitted into the window, letterboxed along whichever side
+/// has room to spare at a zoom of 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub zoom: f32,
+    /// The canvas position shown at the center of the view
+    pub pan: Vec2,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            zoom: 1.0,
+            pan: Vec2::ZERO,
+        }
+    }
+}
+
+impl Camera {
+    pub const MIN_ZOOM: f32 = 0.1;
+    pub const MAX_ZOOM: f32 = 64.0;
+
+    /// Window pixels per canvas pixel
+    pub fn scale(&self, canvas_size: Vec2, window_size: Vec2) -> f32 {
+        fit_scale(canvas_size, window_size) * self.zoom
+    }
+
+    /// Map a canvas position to a window position, both centered with y pointing up
+    pub fn canvas_to_window(&self, xy: Vec2, canvas_size: Vec2, window_size: Vec2) -> Vec2 {
+        (xy - self.pan) * self.scale(canvas_size, window_size)
+    }
+
+    /// Map a window position back to the canvas, both centered with y pointing up
+    pub fn window_to_canvas(&self, xy: Vec2, canvas_size: Vec2, window_size: Vec2) -> Vec2 {
+        xy / self.scale(canvas_size, window_size) + self.pan
+    }
+
+    /// The uniform for the present pass, which maps normalized device coordinates to canvas
+    /// coordinates normalized to -0.5..0.5: `ndc * scale + offset`
+    pub fn present_transform(&self, canvas_size: Vec2, window_size: Vec2) -> [f32; 4] {
+        let scale = window_size / 2.0 / self.scale(canvas_size, window_size) / canvas_size;
+        let offset = self.pan / canvas_size;
+        [scale.x, scale.y, offset.x, offset.y]
+    }
+
+    /// Zoom by `factor` while keeping the canvas position under `window_xy` in place
+    pub fn zoom_at(&mut self, factor: f32, window_xy: Vec2, canvas_size: Vec2, window_size: Vec2) {
+        let anchor = self.window_to_canvas(window_xy, canvas_size, window_size);
+        self.zoom = (self.zoom * factor).clamp(Self::MIN_ZOOM, Self::MAX_ZOOM);
+        self.pan = anchor - window_xy / self.scale(canvas_size, window_size);
+    }
+
+    /// Drag the canvas along with the cursor as it moves `window_delta` pixels
+    pub fn pan_by(&mut self, window_delta: Vec2, canvas_size: Vec2, window_size: Vec2) {
+        self.pan -= window_delta / self.scale(canvas_size, window_size);
+    }
+}
+
+/// Window pixels per canvas pixel when the whole canvas fits the window
+pub fn fit_scale(canvas_size: Vec2, window_size: Vec2) -> f32 {
+    (window_size.x / canvas_size.x).min(window_size.y / canvas_size.y)
+}
+
+/// The transform from canvas positions to clip space for drawing into the layers
+pub fn canvas_projection(canvas_size: Vec2) -> Mat4 {
+    Mat4::from_scale(Vec3::new(2.0 / canvas_size.x, 2.0 / canvas_size.y, 1.0))
+}
@@ -0,0 +1,415 @@
+use crate::lut_manager::LutData;
+use egui_wgpu::wgpu;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+/// Holds the particle density. Half floats blend on every backend and count far past 8 bits.
+pub const DENSITY_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+// Adds each fragment's alpha to the density and its premultiplied color to the color sum
+pub const DENSITY_BLENDING: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+/// How the density, scaled by the exposure, maps to brightness
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum ToneMap {
+    /// Brightness grows with the log of the density, reaching full at 255 hits
+    #[default]
+    Log,
+    /// Brightness approaches full as the density grows, like film exposure
+    Exposure,
+}
+
+impl ToneMap {
+    pub const ALL: [Self; 2] = [Self::Log, Self::Exposure];
+}
+
+impl Display for ToneMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Log => write!(f, "Log"),
+            Self::Exposure => write!(f, "Exposure"),
+        }
+    }
+}
+
+/// Where the tone mapped density gets its color
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum DensityColor {
+    /// The average of the particle colors that hit each pixel
+    #[default]
+    Particles,
+    /// Plain hit density in white
+    White,
+    /// The active LUT, indexed by brightness. Every pixel hit at all is opaque.
+    Lut,
+}
+
+impl DensityColor {
+    pub const ALL: [Self; 3] = [Self::Particles, Self::White, Self::Lut];
+}
+
+impl Display for DensityColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Particles => write!(f, "Particle Colors"),
+            Self::White => write!(f, "White"),
+            Self::Lut => write!(f, "Color LUT"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DensitySettings {
+    /// Accumulate the particles as density instead of painting them over each other
+    pub enabled: bool,
+    pub tone_map: ToneMap,
+    /// Hits are multiplied by this before tone mapping
+    pub exposure: f32,
+    pub gamma: f32,
+    pub color: DensityColor,
+}
+
+impl Default for DensitySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            tone_map: ToneMap::default(),
+            exposure: 1.0,
+            gamma: 2.2,
+            color: DensityColor::default(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToneMapUniforms {
+    exposure: f32,
+    gamma: f32,
+    tone_map: u32,
+    color: u32,
+}
+
+/// A floating point buffer that particles are accumulated into, and the pass that tone maps it
+/// onto a layer
+pub struct DensityBuffer {
+    accumulate_pipeline: wgpu::RenderPipeline,
+    tone_map_pipeline: wgpu::RenderPipeline,
+    fade_pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    uniform_buffer: wgpu::Buffer,
+    lut_view: wgpu::TextureView,
+    lut_texture: wgpu::Texture,
+    lut_sampler: wgpu::Sampler,
+    density_view: wgpu::TextureView,
+}
+
+impl DensityBuffer {
+    /// `geometry_layout` and `geometry_shader` draw particles as in `create_particle_pipeline`,
+    /// and the tone mapped density is blended onto layers of `target_format`
+    pub fn new(
+        device: &wgpu::Device,
+        size: (u32, u32),
+        geometry_layout: &wgpu::PipelineLayout,
+        geometry_shader: &wgpu::ShaderModule,
+        target_format: wgpu::TextureFormat,
+    ) -> Self {
+        let accumulate_pipeline = crate::renderer::create_particle_pipeline(
+            device,
+            "Density Pipeline",
+            geometry_layout,
+            geometry_shader,
+            DENSITY_FORMAT,
+            DENSITY_BLENDING,
+        );
+        let fade_pipeline = crate::renderer::create_fade_pipeline(device, DENSITY_FORMAT, false);
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tone Map Buffer"),
+            size: std::mem::size_of::<ToneMapUniforms>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let lut_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Density LUT Texture"),
+            size: wgpu::Extent3d {
+                width: 256,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // Not sRGB, so LUT colors come out just as they do on particles
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let lut_view = lut_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let lut_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Density LUT Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tone Map Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Tone Map Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/density.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tone Map Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let tone_map_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tone Map Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let density_view = Self::create_density_view(device, size);
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &density_view, &lut_view, &lut_sampler, &uniform_buffer);
+
+        Self {
+            accumulate_pipeline,
+            tone_map_pipeline,
+            fade_pipeline,
+            bind_group_layout,
+            bind_group,
+            uniform_buffer,
+            lut_view,
+            lut_texture,
+            lut_sampler,
+            density_view,
+        }
+    }
+
+    fn create_density_view(device: &wgpu::Device, size: (u32, u32)) -> wgpu::TextureView {
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Density Texture"),
+                size: wgpu::Extent3d {
+                    width: size.0,
+                    height: size.1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: DENSITY_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        density_view: &wgpu::TextureView,
+        lut_view: &wgpu::TextureView,
+        lut_sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tone Map Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(density_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(lut_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(lut_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: (u32, u32)) {
+        self.density_view = Self::create_density_view(device, size);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.density_view,
+            &self.lut_view,
+            &self.lut_sampler,
+            &self.uniform_buffer,
+        );
+    }
+
+    /// The view particles are drawn into with `accumulate_pipeline`
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.density_view
+    }
+
+    /// Draws particle instances additively into the density
+    pub fn accumulate_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.accumulate_pipeline
+    }
+
+    /// Fades the density like `create_fade_pipeline` fades a layer
+    pub fn fade_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.fade_pipeline
+    }
+
+    pub fn set_settings(&self, queue: &wgpu::Queue, settings: &DensitySettings) {
+        let uniforms = ToneMapUniforms {
+            exposure: settings.exposure,
+            gamma: settings.gamma.max(0.01),
+            tone_map: settings.tone_map as u32,
+            color: settings.color as u32,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+    }
+
+    pub fn set_lut(&self, queue: &wgpu::Queue, lut: &LutData) {
+        let texels: Vec<u8> = (0..256)
+            .flat_map(|index| [lut.red[index], lut.green[index], lut.blue[index], 255])
+            .collect();
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.lut_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &texels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(256 * 4),
+                rows_per_image: Some(1),
+            },
+            wgpu::Extent3d {
+                width: 256,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    pub fn clear(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Clear Density"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.density_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+    }
+
+    /// Blend the tone mapped density over `target`, which must be the density's size. `load`
+    /// decides what it's blended over.
+    pub fn tone_map(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView, load: wgpu::LoadOp<wgpu::Color>) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tone Map Density"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        rpass.set_pipeline(&self.tone_map_pipeline);
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
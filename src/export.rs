@@ -0,0 +1,301 @@
+use crate::{
+    model::constants::DEFAULT_TILE_SIZE,
+    poster::TiledRasterizer,
+    recording::Recording,
+    renderer::{Layer, Renderer},
+};
+use egui_wgpu::wgpu;
+use std::fmt::{self, Display};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tiff::encoder::{colortype, TiffEncoder, TiffKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    Tiff,
+}
+
+impl ImageFormat {
+    pub const ALL: [Self; 2] = [Self::Png, Self::Tiff];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Tiff => "tiff",
+        }
+    }
+}
+
+impl Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Png => write!(f, "PNG"),
+            Self::Tiff => write!(f, "TIFF"),
+        }
+    }
+}
+
+/// A path like `directory/poster-20240101-120000.png` that doesn't exist yet, creating
+/// `directory` if needed
+pub fn timestamped_path(directory: &Path, prefix: &str, extension: &str) -> io::Result<PathBuf> {
+    fs::create_dir_all(directory)?;
+    let stem = format!("{}-{}", prefix, timestamp());
+    let mut path = directory.join(&stem).with_extension(extension);
+    // Several exports can land in the same second
+    let mut count = 1;
+    while path.exists() {
+        count += 1;
+        path = directory.join(format!("{}-{}", stem, count)).with_extension(extension);
+    }
+    Ok(path)
+}
+
+// The current UTC time as YYYYMMDD-HHMMSS
+fn timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    format_timestamp(seconds)
+}
+
+// `seconds` since the Unix epoch as YYYYMMDD-HHMMSS
+fn format_timestamp(seconds: u64) -> String {
+    let (days, time_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // Days since the epoch to a civil date, from Howard Hinnant's `civil_from_days`
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScreenshotKind {
+    /// The layers composited as they're shown
+    Composite,
+    /// Each layer in its own file, with transparency
+    Layers,
+    /// Only the particles, on a transparent background
+    Particles,
+}
+
+/// Save the canvas to PNGs in `directory` without the UI, returning their paths. At 1× the layers
+/// are read back as they are, while larger scales redraw the recorded geometry. Layers are always
+/// saved at 1×.
+pub fn save_screenshot(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    renderer: &Renderer,
+    recording: &Recording,
+    kind: ScreenshotKind,
+    scale: u32,
+    directory: &Path,
+) -> io::Result<Vec<PathBuf>> {
+    let canvas_size = (recording.canvas_size.x as u32, recording.canvas_size.y as u32);
+    if scale > 1 && kind != ScreenshotKind::Layers && recording.is_truncated() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "too much has been drawn since the screen was cleared to redraw it at a higher resolution",
+        ));
+    }
+    match kind {
+        ScreenshotKind::Composite => {
+            let path = timestamped_path(directory, "flow", "png")?;
+            if scale <= 1 {
+                write_png(&path, canvas_size, renderer.read_composite(device, queue)?)?;
+            } else {
+                let image_size = (canvas_size.0 * scale, canvas_size.1 * scale);
+                let rasterizer = TiledRasterizer::new(device, queue, recording, image_size, DEFAULT_TILE_SIZE);
+                write_rgba_strips(&path, ImageFormat::Png, image_size, rasterizer.tile_size, |strip| {
+                    rasterizer.render_strip(strip)
+                })?;
+            }
+            Ok(vec![path])
+        }
+        ScreenshotKind::Layers => Layer::ALL
+            .iter()
+            .map(|layer| {
+                let path = timestamped_path(directory, &format!("flow-{}", layer.name()), "png")?;
+                let mut pixels = renderer.read_layer(device, queue, *layer)?;
+                unpremultiply(&mut pixels);
+                write_png(&path, canvas_size, pixels)?;
+                Ok(path)
+            })
+            .collect(),
+        ScreenshotKind::Particles => {
+            let path = timestamped_path(directory, "flow-particles-transparent", "png")?;
+            if scale <= 1 {
+                let mut pixels = renderer.read_layer(device, queue, Layer::Particles)?;
+                unpremultiply(&mut pixels);
+                write_png(&path, canvas_size, pixels)?;
+            } else {
+                let image_size = (canvas_size.0 * scale, canvas_size.1 * scale);
+                let rasterizer =
+                    TiledRasterizer::new(device, queue, recording, image_size, DEFAULT_TILE_SIZE).particles_only();
+                write_rgba_strips(&path, ImageFormat::Png, image_size, rasterizer.tile_size, |strip| {
+                    let mut pixels = rasterizer.render_strip(strip)?;
+                    unpremultiply(&mut pixels);
+                    Ok(pixels)
+                })?;
+            }
+            Ok(vec![path])
+        }
+    }
+}
+
+pub fn write_png(path: &Path, size: (u32, u32), mut pixels: Vec<u8>) -> io::Result<()> {
+    write_rgba_strips(path, ImageFormat::Png, size, size.1, |_| Ok(std::mem::take(&mut pixels)))
+}
+
+// The layers hold sRGB encoded premultiplied color, while PNGs expect straight alpha. Divide by
+// alpha in linear space, where the blending happened.
+fn unpremultiply(pixels: &mut [u8]) {
+    let to_linear: Vec<f32> = (0..=255u8)
+        .map(|value| {
+            let value = value as f32 / 255.0;
+            if value <= 0.04045 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            }
+        })
+        .collect();
+    let to_srgb = |value: f32| {
+        let value = if value <= 0.003_130_8 {
+            value * 12.92
+        } else {
+            1.055 * value.powf(1.0 / 2.4) - 0.055
+        };
+        (value * 255.0).round() as u8
+    };
+
+    for pixel in pixels.chunks_exact_mut(4) {
+        if pixel[3] == 0 || pixel[3] == 255 {
+            continue;
+        }
+        let alpha = pixel[3] as f32 / 255.0;
+        for channel in &mut pixel[..3] {
+            *channel = to_srgb((to_linear[*channel as usize] / alpha).min(1.0));
+        }
+    }
+}
+
+/// Write an 8-bit RGBA image to `path` a strip of rows at a time, so the whole image never has
+/// to be in memory. `next_strip` is called with each strip's index in order and returns its
+/// rows, `strip_height` of them except possibly for the last strip.
+pub fn write_rgba_strips(
+    path: &Path,
+    format: ImageFormat,
+    (width, height): (u32, u32),
+    strip_height: u32,
+    mut next_strip: impl FnMut(u32) -> io::Result<Vec<u8>>,
+) -> io::Result<()> {
+    let strip_count = height.div_ceil(strip_height);
+    let file = BufWriter::new(File::create(path)?);
+    match format {
+        ImageFormat::Png => {
+            let mut encoder = png::Encoder::new(file, width, height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut stream = encoder
+                .write_header()
+                .and_then(|writer| writer.into_stream_writer())
+                .map_err(io::Error::other)?;
+            for strip in 0..strip_count {
+                stream.write_all(&next_strip(strip)?)?;
+            }
+            stream.finish().map_err(io::Error::other)
+        }
+        ImageFormat::Tiff => {
+            // Classic TIFF offsets are 32 bits so bigger images need BigTIFF
+            let byte_count = width as u64 * height as u64 * 4;
+            let result = if byte_count < u32::MAX as u64 / 2 {
+                TiffEncoder::new(file).and_then(|encoder| {
+                    write_tiff_strips(encoder, (width, height), strip_height, strip_count, &mut next_strip)
+                })
+            } else {
+                TiffEncoder::new_big(file).and_then(|encoder| {
+                    write_tiff_strips(encoder, (width, height), strip_height, strip_count, &mut next_strip)
+                })
+            };
+            result.map_err(|e| match e {
+                tiff::TiffError::IoError(e) => e,
+                e => io::Error::other(e),
+            })
+        }
+    }
+}
+
+fn write_tiff_strips<W: Write + io::Seek, K: TiffKind>(
+    mut encoder: TiffEncoder<W, K>,
+    (width, height): (u32, u32),
+    strip_height: u32,
+    strip_count: u32,
+    next_strip: &mut impl FnMut(u32) -> io::Result<Vec<u8>>,
+) -> tiff::TiffResult<()> {
+    let mut image = encoder.new_image::<colortype::RGBA8>(width, height)?;
+    image.rows_per_strip(strip_height)?;
+    for strip in 0..strip_count {
+        image.write_strip(&next_strip(strip)?)?;
+    }
+    image.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_are_utc_calendar_dates() {
+        assert_eq!(format_timestamp(0), "19700101-000000");
+        assert_eq!(format_timestamp(86_399), "19700101-235959");
+        // Leap days, and the century that isn't a leap year next to the one that is
+        assert_eq!(format_timestamp(951_782_400), "20000229-000000");
+        assert_eq!(format_timestamp(1_709_210_096), "20240229-123456");
+        assert_eq!(format_timestamp(4_107_542_400), "21000301-000000");
+        assert_eq!(format_timestamp(1_767_225_599), "20251231-235959");
+    }
+
+    #[test]
+    fn unpremultiply_leaves_opaque_and_clear_pixels_alone() {
+        let mut pixels = vec![10, 200, 30, 255, 0, 0, 0, 0, 5, 6, 7, 0];
+        let original = pixels.clone();
+        unpremultiply(&mut pixels);
+        assert_eq!(pixels, original);
+    }
+
+    #[test]
+    fn unpremultiply_divides_by_alpha_in_linear_space() {
+        // Opaque white at half coverage is linear 0.5, which is 188 in sRGB
+        let mut pixels = vec![188, 188, 188, 128, 0, 94, 188, 128];
+        unpremultiply(&mut pixels);
+        assert_eq!(pixels, [255, 255, 255, 128, 0, 130, 255, 128]);
+    }
+
+    #[test]
+    fn unpremultiply_clamps_color_brighter_than_its_alpha() {
+        let mut pixels = vec![255, 255, 255, 64];
+        unpremultiply(&mut pixels);
+        assert_eq!(pixels, [255, 255, 255, 64]);
+    }
+}
@@ -0,0 +1,689 @@
+use noise::{NoiseFn, OpenSimplex};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+/// Values an expression can read while it's evaluated
+pub struct Variables<'a> {
+    pub x: f64,
+    pub y: f64,
+    pub t: f64,
+    pub seed: f64,
+    pub noise: &'a OpenSimplex,
+}
+
+impl Variables<'_> {
+    fn get(&self, variable: Variable) -> f64 {
+        match variable {
+            Variable::X => self.x,
+            Variable::Y => self.y,
+            Variable::R => self.x.hypot(self.y),
+            Variable::Theta => self.y.atan2(self.x),
+            Variable::T => self.t,
+            Variable::Seed => self.seed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Variable {
+    X,
+    Y,
+    R,
+    Theta,
+    T,
+    Seed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Atan2,
+    Sqrt,
+    Abs,
+    Exp,
+    Ln,
+    Floor,
+    Ceil,
+    Fract,
+    Sign,
+    Min,
+    Max,
+    Pow,
+    Clamp,
+    Noise,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "sin" => Self::Sin,
+            "cos" => Self::Cos,
+            "tan" => Self::Tan,
+            "asin" => Self::Asin,
+            "acos" => Self::Acos,
+            "atan" => Self::Atan,
+            "atan2" => Self::Atan2,
+            "sqrt" => Self::Sqrt,
+            "abs" => Self::Abs,
+            "exp" => Self::Exp,
+            "ln" | "log" => Self::Ln,
+            "floor" => Self::Floor,
+            "ceil" => Self::Ceil,
+            "fract" => Self::Fract,
+            "sign" => Self::Sign,
+            "min" => Self::Min,
+            "max" => Self::Max,
+            "pow" => Self::Pow,
+            "clamp" => Self::Clamp,
+            "noise" => Self::Noise,
+            _ => return None,
+        })
+    }
+
+    fn arity(&self) -> usize {
+        match self {
+            Self::Atan2 | Self::Min | Self::Max | Self::Pow | Self::Noise => 2,
+            Self::Clamp => 3,
+            _ => 1,
+        }
+    }
+
+    fn call(&self, args: &[f64], variables: &Variables) -> f64 {
+        match self {
+            Self::Sin => args[0].sin(),
+            Self::Cos => args[0].cos(),
+            Self::Tan => args[0].tan(),
+            Self::Asin => args[0].asin(),
+            Self::Acos => args[0].acos(),
+            Self::Atan => args[0].atan(),
+            Self::Atan2 => args[0].atan2(args[1]),
+            Self::Sqrt => args[0].sqrt(),
+            Self::Abs => args[0].abs(),
+            Self::Exp => args[0].exp(),
+            Self::Ln => args[0].ln(),
+            Self::Floor => args[0].floor(),
+            Self::Ceil => args[0].ceil(),
+            Self::Fract => args[0].fract(),
+            Self::Sign => args[0].signum(),
+            Self::Min => args[0].min(args[1]),
+            Self::Max => args[0].max(args[1]),
+            Self::Pow => args[0].powf(args[1]),
+            Self::Clamp => args[0].max(args[1]).min(args[2]),
+            Self::Noise => variables.noise.get([args[0], args[1]]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Power,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(f64),
+    Variable(Variable),
+    Negate(Box<Node>),
+    Binary(BinaryOperator, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+impl Node {
+    fn evaluate(&self, variables: &Variables) -> f64 {
+        match self {
+            Self::Number(value) => *value,
+            Self::Variable(variable) => variables.get(*variable),
+            Self::Negate(node) => -node.evaluate(variables),
+            Self::Binary(operator, lhs, rhs) => {
+                let lhs = lhs.evaluate(variables);
+                let rhs = rhs.evaluate(variables);
+                match operator {
+                    BinaryOperator::Add => lhs + rhs,
+                    BinaryOperator::Subtract => lhs - rhs,
+                    BinaryOperator::Multiply => lhs * rhs,
+                    BinaryOperator::Divide => lhs / rhs,
+                    BinaryOperator::Remainder => lhs.rem_euclid(rhs),
+                    BinaryOperator::Power => lhs.powf(rhs),
+                }
+            }
+            Self::Call(function, args) => {
+                let args: Vec<f64> = args.iter().map(|arg| arg.evaluate(variables)).collect();
+                function.call(&args, variables)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    /// Character offset into the source where the error was found
+    pub position: usize,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at column {}", self.message, self.position + 1)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Identifier(String),
+    Operator(char),
+    LeftParen,
+    RightParen,
+    Comma,
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+
+    while position < chars.len() {
+        let c = chars[position];
+        let start = position;
+        if c.is_whitespace() {
+            position += 1;
+            continue;
+        }
+
+        let token = if c.is_ascii_digit() || c == '.' {
+            while position < chars.len() && (chars[position].is_ascii_digit() || chars[position] == '.') {
+                position += 1;
+            }
+            let text: String = chars[start..position].iter().collect();
+            let value = text.parse().map_err(|_| ParseError {
+                message: format!("invalid number \"{}\"", text),
+                position: start,
+            })?;
+            tokens.push((Token::Number(value), start));
+            continue;
+        } else if c.is_alphabetic() || c == '_' {
+            while position < chars.len() && (chars[position].is_alphanumeric() || chars[position] == '_') {
+                position += 1;
+            }
+            tokens.push((Token::Identifier(chars[start..position].iter().collect()), start));
+            continue;
+        } else {
+            match c {
+                '+' | '-' | '*' | '/' | '%' | '^' => Token::Operator(c),
+                '(' => Token::LeftParen,
+                ')' => Token::RightParen,
+                ',' => Token::Comma,
+                _ => {
+                    return Err(ParseError {
+                        message: format!("unexpected character '{}'", c),
+                        position: start,
+                    })
+                }
+            }
+        };
+        tokens.push((token, start));
+        position += 1;
+    }
+
+    Ok(tokens)
+}
+
+// Deepest an expression's tree may get. Evaluating and dropping the tree recurse once per level,
+// so without a limit a pasted wall of parentheses or minus signs would overflow the stack.
+const MAX_DEPTH: usize = 256;
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    index: usize,
+    end: usize,
+    // How deep in the tree the node being parsed will sit
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(token, _)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.index).map_or(self.end, |(_, position)| *position)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseError> {
+        Err(ParseError {
+            message: message.into(),
+            position: self.position(),
+        })
+    }
+
+    fn expect(&mut self, expected: Token, description: &str) -> Result<(), ParseError> {
+        if self.peek() == Some(&expected) {
+            self.index += 1;
+            Ok(())
+        } else {
+            self.error(format!("expected {}", description))
+        }
+    }
+
+    // Go a level deeper into the tree, unless that's too deep
+    fn deepen(&mut self) -> Result<(), ParseError> {
+        if self.depth == MAX_DEPTH {
+            return self.error("the expression is nested too deeply");
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    // Parse with `parse` a level deeper into the tree
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Node, ParseError>) -> Result<Node, ParseError> {
+        self.deepen()?;
+        let node = parse(self);
+        self.depth -= 1;
+        node
+    }
+
+    // expression := term (('+' | '-') term)*
+    fn expression(&mut self) -> Result<Node, ParseError> {
+        let depth = self.depth;
+        let mut node = self.term()?;
+        while let Some(Token::Operator(c @ ('+' | '-'))) = self.peek() {
+            let operator = if *c == '+' { BinaryOperator::Add } else { BinaryOperator::Subtract };
+            self.index += 1;
+            // Every link of a chain pushes the ones before it a level down
+            self.deepen()?;
+            node = Node::Binary(operator, Box::new(node), Box::new(self.term()?));
+        }
+        self.depth = depth;
+        Ok(node)
+    }
+
+    // term := unary (('*' | '/' | '%') unary)*
+    fn term(&mut self) -> Result<Node, ParseError> {
+        let depth = self.depth;
+        let mut node = self.unary()?;
+        while let Some(Token::Operator(c @ ('*' | '/' | '%'))) = self.peek() {
+            let operator = match c {
+                '*' => BinaryOperator::Multiply,
+                '/' => BinaryOperator::Divide,
+                _ => BinaryOperator::Remainder,
+            };
+            self.index += 1;
+            self.deepen()?;
+            node = Node::Binary(operator, Box::new(node), Box::new(self.unary()?));
+        }
+        self.depth = depth;
+        Ok(node)
+    }
+
+    // unary := ('-' | '+') unary | power
+    fn unary(&mut self) -> Result<Node, ParseError> {
+        match self.peek() {
+            Some(Token::Operator('-')) => {
+                self.index += 1;
+                Ok(Node::Negate(Box::new(self.nested(Self::unary)?)))
+            }
+            Some(Token::Operator('+')) => {
+                self.index += 1;
+                self.nested(Self::unary)
+            }
+            _ => self.power(),
+        }
+    }
+
+    // power := primary ('^' unary)?, which makes '^' right associative
+    fn power(&mut self) -> Result<Node, ParseError> {
+        let base = self.primary()?;
+        if let Some(Token::Operator('^')) = self.peek() {
+            self.index += 1;
+            let exponent = self.nested(Self::unary)?;
+            return Ok(Node::Binary(BinaryOperator::Power, Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    // primary := number | variable | constant | function '(' arguments ')' | '(' expression ')'
+    fn primary(&mut self) -> Result<Node, ParseError> {
+        let position = self.position();
+        match self.peek().cloned() {
+            Some(Token::Number(value)) => {
+                self.index += 1;
+                Ok(Node::Number(value))
+            }
+            Some(Token::LeftParen) => {
+                self.index += 1;
+                let node = self.nested(Self::expression)?;
+                self.expect(Token::RightParen, "')'")?;
+                Ok(node)
+            }
+            Some(Token::Identifier(name)) => {
+                self.index += 1;
+                if let Some(function) = Function::from_name(&name) {
+                    return self.call(function, &name, position);
+                }
+
+                let node = match name.as_str() {
+                    "x" => Node::Variable(Variable::X),
+                    "y" => Node::Variable(Variable::Y),
+                    "r" => Node::Variable(Variable::R),
+                    "theta" => Node::Variable(Variable::Theta),
+                    "t" => Node::Variable(Variable::T),
+                    "seed" => Node::Variable(Variable::Seed),
+                    "pi" => Node::Number(std::f64::consts::PI),
+                    "tau" => Node::Number(std::f64::consts::TAU),
+                    "e" => Node::Number(std::f64::consts::E),
+                    _ => {
+                        return Err(ParseError {
+                            message: format!("unknown name \"{}\"", name),
+                            position,
+                        })
+                    }
+                };
+                Ok(node)
+            }
+            Some(_) => self.error("expected a number, name or '('"),
+            None => self.error("unexpected end of expression"),
+        }
+    }
+
+    fn call(&mut self, function: Function, name: &str, position: usize) -> Result<Node, ParseError> {
+        self.expect(Token::LeftParen, &format!("'(' after \"{}\"", name))?;
+        let mut args = Vec::new();
+        if self.peek() != Some(&Token::RightParen) {
+            args.push(self.nested(Self::expression)?);
+            while self.peek() == Some(&Token::Comma) {
+                self.index += 1;
+                args.push(self.nested(Self::expression)?);
+            }
+        }
+        self.expect(Token::RightParen, "')'")?;
+
+        if args.len() != function.arity() {
+            return Err(ParseError {
+                message: format!(
+                    "\"{}\" takes {} argument{} but was given {}",
+                    name,
+                    function.arity(),
+                    if function.arity() == 1 { "" } else { "s" },
+                    args.len()
+                ),
+                position,
+            });
+        }
+        Ok(Node::Call(function, args))
+    }
+}
+
+/// A parsed formula that's safe to evaluate: it can only do arithmetic on known variables
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    root: Node,
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            index: 0,
+            end: source.chars().count(),
+            depth: 0,
+        };
+        let root = parser.expression()?;
+        if parser.index < parser.tokens.len() {
+            return parser.error("unexpected input after the end of the expression");
+        }
+        Ok(Self { root })
+    }
+
+    pub fn evaluate(&self, variables: &Variables) -> f64 {
+        self.root.evaluate(variables)
+    }
+
+    pub fn uses_time(&self) -> bool {
+        fn visit(node: &Node) -> bool {
+            match node {
+                Node::Variable(Variable::T) => true,
+                Node::Number(_) | Node::Variable(_) => false,
+                Node::Negate(node) => visit(node),
+                Node::Binary(_, lhs, rhs) => visit(lhs) || visit(rhs),
+                Node::Call(_, args) => args.iter().any(visit),
+            }
+        }
+        visit(&self.root)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum ExpressionMode {
+    /// One formula giving the heading in radians
+    #[default]
+    Angle,
+    /// Two formulas giving the x and y components of the flow
+    Components,
+}
+
+impl Display for ExpressionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Angle => write!(f, "Angle"),
+            Self::Components => write!(f, "Components (u, v)"),
+        }
+    }
+}
+
+/// The user typed formulas behind `FlowVectorFieldBuilder::Expression`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldExpression {
+    pub mode: ExpressionMode,
+    pub angle: String,
+    pub u: String,
+    pub v: String,
+    /// Rebuild the field every frame so formulas using `t` animate
+    pub animate: bool,
+}
+
+impl Default for FieldExpression {
+    fn default() -> Self {
+        Self {
+            mode: ExpressionMode::Angle,
+            angle: "theta + pi / 2 + noise(x * 2, y * 2)".to_string(),
+            u: "-y".to_string(),
+            v: "x".to_string(),
+            animate: false,
+        }
+    }
+}
+
+/// The parsed formulas for a `FieldExpression`
+pub enum CompiledFieldExpression {
+    Angle(Expression),
+    Components(Expression, Expression),
+}
+
+impl CompiledFieldExpression {
+    /// The flow direction at a point as an angle in radians
+    pub fn heading(&self, variables: &Variables) -> f64 {
+        match self {
+            Self::Angle(angle) => angle.evaluate(variables),
+            Self::Components(u, v) => v.evaluate(variables).atan2(u.evaluate(variables)),
+        }
+    }
+
+    pub fn uses_time(&self) -> bool {
+        match self {
+            Self::Angle(angle) => angle.uses_time(),
+            Self::Components(u, v) => u.uses_time() || v.uses_time(),
+        }
+    }
+}
+
+/// Which of the formulas failed to parse, and why
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldExpressionError {
+    pub formula: &'static str,
+    pub error: ParseError,
+}
+
+impl Display for FieldExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.formula, self.error)
+    }
+}
+
+impl FieldExpression {
+    pub fn compile(&self) -> Result<CompiledFieldExpression, FieldExpressionError> {
+        let parse = |formula: &'static str, source: &str| {
+            Expression::parse(source).map_err(|error| FieldExpressionError { formula, error })
+        };
+
+        Ok(match self.mode {
+            ExpressionMode::Angle => CompiledFieldExpression::Angle(parse("angle", &self.angle)?),
+            ExpressionMode::Components => CompiledFieldExpression::Components(parse("u", &self.u)?, parse("v", &self.v)?),
+        })
+    }
+}
+
+/// The last `FieldExpression` compiled, so its formulas are only parsed again after they change
+#[derive(Default)]
+pub struct FieldExpressionCache {
+    compiled: Option<(FieldExpression, Result<CompiledFieldExpression, FieldExpressionError>)>,
+}
+
+impl FieldExpressionCache {
+    pub fn compile(&mut self, expression: &FieldExpression) -> &Result<CompiledFieldExpression, FieldExpressionError> {
+        let current = self.compiled.as_ref().is_some_and(|(compiled, _)| {
+            compiled.mode == expression.mode
+                && compiled.angle == expression.angle
+                && compiled.u == expression.u
+                && compiled.v == expression.v
+        });
+        if !current {
+            self.compiled = Some((expression.clone(), expression.compile()));
+        }
+        &self.compiled.as_ref().expect("just compiled").1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate_at(source: &str, x: f64, y: f64) -> f64 {
+        let noise = OpenSimplex::new(0);
+        let variables = Variables { x, y, t: 2.0, seed: 7.0, noise: &noise };
+        Expression::parse(source).unwrap_or_else(|e| panic!("{source:?}: {e}")).evaluate(&variables)
+    }
+
+    fn evaluate(source: &str) -> f64 {
+        evaluate_at(source, 0.0, 0.0)
+    }
+
+    #[test]
+    fn operators_follow_precedence() {
+        for (source, expected) in [
+            ("1 + 2 * 3", 7.0),
+            ("(1 + 2) * 3", 9.0),
+            ("10 - 4 - 3", 3.0),
+            ("12 / 3 / 2", 2.0),
+            ("2 ^ 3 ^ 2", 512.0),
+            ("-2 ^ 2", -4.0),
+            ("2 ^ -1", 0.5),
+            ("2 * -3", -6.0),
+            ("+-+3", -3.0),
+            ("7 % 3", 1.0),
+            ("-7 % 3", 2.0),
+            ("1 + 2 * 3 ^ 2 / 6 - 1", 3.0),
+        ] {
+            assert_eq!(evaluate(source), expected, "{source:?}");
+        }
+    }
+
+    #[test]
+    fn names_and_functions() {
+        assert_eq!(evaluate_at("r", 3.0, 4.0), 5.0);
+        assert_eq!(evaluate_at("x * 10 + y", 3.0, 4.0), 34.0);
+        assert_eq!(evaluate("t + seed"), 9.0);
+        assert_eq!(evaluate("min(3, max(1, 2))"), 2.0);
+        assert_eq!(evaluate("clamp(5, 0, 1)"), 1.0);
+        assert_eq!(evaluate("atan2(0, -1)"), std::f64::consts::PI);
+        assert_eq!(evaluate("pi"), std::f64::consts::PI);
+        assert_eq!(evaluate("log(e)"), 1.0);
+        assert!(evaluate("noise(0.3, 0.7)").abs() <= 1.0);
+    }
+
+    #[test]
+    fn uses_time_only_when_t_appears() {
+        assert!(Expression::parse("sin(x + t)").unwrap().uses_time());
+        assert!(!Expression::parse("theta + tau").unwrap().uses_time());
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        for (source, message, position) in [
+            ("1 +", "unexpected end of expression", 3),
+            ("foo + 1", "unknown name \"foo\"", 0),
+            ("1 $ 2", "unexpected character '$'", 2),
+            ("1.2.3", "invalid number \"1.2.3\"", 0),
+            ("(1 + 2", "expected ')'", 6),
+            ("1 2", "unexpected input after the end of the expression", 2),
+            ("sin 1", "expected '(' after \"sin\"", 4),
+            ("x * )", "expected a number, name or '('", 4),
+            ("min(1)", "\"min\" takes 2 arguments but was given 1", 0),
+            ("sqrt(1, 2)", "\"sqrt\" takes 1 argument but was given 2", 0),
+        ] {
+            let error = Expression::parse(source).expect_err(source);
+            assert_eq!(error, ParseError { message: message.to_string(), position }, "{source:?}");
+        }
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error_not_a_stack_overflow() {
+        let too_deep = [
+            format!("{}1", "-".repeat(100_000)),
+            format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000)),
+            format!("{}1", "2^".repeat(100_000)),
+            format!("{}1{}", "sin(".repeat(100_000), ")".repeat(100_000)),
+            format!("1{}", "+1".repeat(100_000)),
+            format!("1{}", "*1".repeat(100_000)),
+        ];
+        for source in &too_deep {
+            let error = Expression::parse(source).expect_err("nested too deeply");
+            assert_eq!(error.message, "the expression is nested too deeply");
+        }
+
+        // Reasonable nesting still parses
+        let nested = format!("{}1{}", "(".repeat(MAX_DEPTH - 1), ")".repeat(MAX_DEPTH - 1));
+        assert_eq!(evaluate(&nested), 1.0);
+        let sum = format!("0{}", " + 1".repeat(MAX_DEPTH - 1));
+        assert_eq!(evaluate(&sum), (MAX_DEPTH - 1) as f64);
+    }
+
+    #[test]
+    fn cache_compiles_again_only_after_an_edit() {
+        let mut cache = FieldExpressionCache::default();
+        let mut expression = FieldExpression::default();
+        assert!(cache.compile(&expression).is_ok());
+
+        expression.angle = "x +".to_string();
+        assert_eq!(cache.compile(&expression).as_ref().err().map(|e| e.formula), Some("angle"));
+
+        // The components aren't in use in angle mode, but switching modes picks them up
+        expression.mode = ExpressionMode::Components;
+        expression.v = "y *".to_string();
+        assert_eq!(cache.compile(&expression).as_ref().err().map(|e| e.formula), Some("v"));
+        expression.v = "y".to_string();
+        assert!(cache.compile(&expression).is_ok());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::model::Model;
+use glam::Vec2;
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldBrushKind {
+    Comb,
+    Smooth,
+    Twirl,
+}
+
+impl FieldBrushKind {
+    pub const ALL: [Self; 3] = [Self::Comb, Self::Smooth, Self::Twirl];
+}
+
+impl Display for FieldBrushKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Comb => write!(f, "Comb"),
+            Self::Smooth => write!(f, "Smooth"),
+            Self::Twirl => write!(f, "Twirl"),
+        }
+    }
+}
+
+/// Paints new directions into an existing flow field
+#[derive(Debug, Clone, Copy)]
+pub struct FieldBrush {
+    pub kind: FieldBrushKind,
+    pub radius: f32,
+    /// How far each dab turns a vector toward its target, from 0 to 1
+    pub strength: f32,
+    /// Exponent shaping how quickly the effect fades toward the edge of the brush
+    pub falloff: f32,
+}
+
+impl Default for FieldBrush {
+    fn default() -> Self {
+        Self {
+            kind: FieldBrushKind::Comb,
+            radius: 80.0,
+            strength: 0.3,
+            falloff: 1.0,
+        }
+    }
+}
+
+impl FieldBrush {
+    /// Apply one dab of the brush centered on `center`. `stroke` is the mouse movement since the
+    /// last dab and is only used by the comb.
+    pub fn apply(&self, model: &mut Model, center: Vec2, stroke: Vec2) {
+        if self.kind == FieldBrushKind::Comb && stroke.length_squared() < f32::EPSILON {
+            return;
+        }
+        model.field_revision += 1;
+
+        let (origin_x, origin_y) = model.get_origin();
+        let spacing = model.vector_spacing;
+        let cell_reach = (self.radius / spacing).ceil() as i32;
+        let center_row = ((center.x - origin_x) / spacing).round() as i32;
+        let center_column = ((center.y - origin_y) / spacing).round() as i32;
+
+        // Smoothing reads the neighbours so it needs the headings from before this dab
+        let headings: Vec<f32> = if self.kind == FieldBrushKind::Smooth {
+            model.flow_vectors.iter().map(|fv| fv.heading()).collect()
+        } else {
+            Vec::new()
+        };
+
+        let grid_width = model.grid_width as i32;
+        let grid_height = model.grid_height as i32;
+        for column_index in (center_column - cell_reach)..=(center_column + cell_reach) {
+            for row_index in (center_row - cell_reach)..=(center_row + cell_reach) {
+                if row_index < 0 || row_index >= grid_width || column_index < 0 || column_index >= grid_height {
+                    continue;
+                }
+
+                let index = (row_index + column_index * grid_width) as usize;
+                let Some(fv) = model.flow_vectors.get_mut(index) else {
+                    continue;
+                };
+
+                let offset = fv.position() - center;
+                let distance = offset.length();
+                if distance > self.radius {
+                    continue;
+                }
+
+                let weight = self.weight_at(distance);
+                let target = match self.kind {
+                    FieldBrushKind::Comb => stroke.y.atan2(stroke.x).to_degrees(),
+                    FieldBrushKind::Smooth => {
+                        neighbour_average(&headings, row_index, column_index, grid_width, grid_height)
+                    }
+                    // Counterclockwise around the center of the brush
+                    FieldBrushKind::Twirl => offset.y.atan2(offset.x).to_degrees() + 90.0,
+                };
+
+                let current = fv.heading();
+                fv.set_heading(current + shortest_turn(current, target) * weight);
+            }
+        }
+    }
+
+    // How much of the turn toward the target a vector `distance` from the center takes
+    fn weight_at(&self, distance: f32) -> f32 {
+        self.strength * (1.0 - distance / self.radius).max(0.0).powf(self.falloff)
+    }
+}
+
+// Signed difference in degrees from `from` to `to`, in the range -180..=180
+fn shortest_turn(from: f32, to: f32) -> f32 {
+    (to - from + 180.0).rem_euclid(360.0) - 180.0
+}
+
+fn neighbour_average(headings: &[f32], row_index: i32, column_index: i32, grid_width: i32, grid_height: i32) -> f32 {
+    let mut sum = Vec2::ZERO;
+    for column in (column_index - 1).max(0)..=(column_index + 1).min(grid_height - 1) {
+        for row in (row_index - 1).max(0)..=(row_index + 1).min(grid_width - 1) {
+            if let Some(heading) = headings.get((row + column * grid_width) as usize) {
+                let radians = heading.to_radians();
+                sum += Vec2::new(radians.cos(), radians.sin());
+            }
+        }
+    }
+    sum.y.atan2(sum.x).to_degrees()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turns_take_the_short_way_around() {
+        assert_eq!(shortest_turn(350.0, 10.0), 20.0);
+        assert_eq!(shortest_turn(10.0, 350.0), -20.0);
+        assert_eq!(shortest_turn(-170.0, 170.0), -20.0);
+        assert_eq!(shortest_turn(45.0, 45.0 + 720.0), 0.0);
+        assert_eq!(shortest_turn(0.0, 90.0), 90.0);
+        assert_eq!(shortest_turn(0.0, 180.0).abs(), 180.0);
+    }
+
+    #[test]
+    fn strength_fades_to_nothing_at_the_edge() {
+        for falloff in [0.5, 1.0, 3.0] {
+            let brush = FieldBrush { falloff, ..FieldBrush::default() };
+            assert_eq!(brush.weight_at(0.0), brush.strength);
+            assert_eq!(brush.weight_at(brush.radius), 0.0);
+            assert!(brush.weight_at(brush.radius / 2.0) > 0.0);
+            assert!(brush.weight_at(brush.radius / 2.0) < brush.strength);
+        }
+        let linear = FieldBrush::default();
+        assert!((linear.weight_at(linear.radius / 4.0) - linear.strength * 0.75).abs() < 1e-6);
+    }
+
+    #[test]
+    fn combing_turns_across_the_wrap_around() {
+        let mut model = Model::new(Vec2::new(400.0, 300.0));
+        for fv in model.flow_vectors.iter_mut() {
+            fv.set_heading(350.0);
+        }
+        let brush = FieldBrush::default();
+        let center = model.flow_vectors[model.flow_vectors.len() / 2].position();
+        let stroke = Vec2::new(10f32.to_radians().cos(), 10f32.to_radians().sin());
+        brush.apply(&mut model, center, stroke);
+
+        for fv in &model.flow_vectors {
+            let distance = fv.position().distance(center);
+            let turned = shortest_turn(350.0, fv.heading());
+            if distance >= brush.radius {
+                assert!(turned.abs() < 1e-3, "{} turned {} from {} away", fv.position(), turned, distance);
+            } else {
+                // Toward 10° through 0°, never back the long way through 180°
+                assert!((turned - 20.0 * brush.weight_at(distance)).abs() < 1e-3, "turned {}", turned);
+            }
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FieldPrimitiveKind {
+    ClockwiseVortex,
+    CounterclockwiseVortex,
+    Source,
+    Sink,
+    Saddle,
+    Dipole,
+    Uniform,
+}
+
+impl FieldPrimitiveKind {
+    pub const ALL: [Self; 7] = [
+        Self::ClockwiseVortex,
+        Self::CounterclockwiseVortex,
+        Self::Source,
+        Self::Sink,
+        Self::Saddle,
+        Self::Dipole,
+        Self::Uniform,
+    ];
+
+    // Only some primitives have a meaningful orientation
+    pub fn has_angle(&self) -> bool {
+        matches!(self, Self::Saddle | Self::Dipole | Self::Uniform)
+    }
+
+    // Uniform flow is the same everywhere so its radius is ignored
+    pub fn has_radius(&self) -> bool {
+        !matches!(self, Self::Uniform)
+    }
+}
+
+impl Display for FieldPrimitiveKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::ClockwiseVortex => "Vortex (CW)",
+                Self::CounterclockwiseVortex => "Vortex (CCW)",
+                Self::Source => "Source",
+                Self::Sink => "Sink",
+                Self::Saddle => "Saddle",
+                Self::Dipole => "Dipole",
+                Self::Uniform => "Uniform Wind",
+            }
+        )
+    }
+}
+
+/// An analytic flow element placed on the canvas. Primitives are summed to build a field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldPrimitive {
+    pub kind: FieldPrimitiveKind,
+    pub xy: Vec2,
+    pub strength: f32,
+    pub radius: f32,
+    /// Orientation in degrees, used by saddles, dipoles and uniform wind
+    pub angle: f32,
+}
+
+impl FieldPrimitive {
+    pub fn new(kind: FieldPrimitiveKind, xy: Vec2) -> Self {
+        Self {
+            kind,
+            xy,
+            strength: 1.0,
+            radius: 200.0,
+            angle: 0.0,
+        }
+    }
+
+    /// The velocity this primitive contributes at `point`
+    pub fn velocity_at(&self, point: Vec2) -> Vec2 {
+        let offset = point - self.xy;
+        let radius = self.radius.max(1.0);
+        // Normalizing by the radius keeps strengths comparable between primitive kinds
+        let r = offset / radius;
+        let distance_sq = r.length_squared();
+        let falloff = 1.0 / (1.0 + distance_sq);
+        let direction = Vec2::new(self.angle.to_radians().cos(), self.angle.to_radians().sin());
+
+        let velocity = match self.kind {
+            FieldPrimitiveKind::ClockwiseVortex => Vec2::new(r.y, -r.x) * falloff,
+            FieldPrimitiveKind::CounterclockwiseVortex => Vec2::new(-r.y, r.x) * falloff,
+            FieldPrimitiveKind::Source => r * falloff,
+            FieldPrimitiveKind::Sink => -r * falloff,
+            FieldPrimitiveKind::Saddle => {
+                // Stretch along `direction` and squeeze along its perpendicular
+                let along = r.dot(direction);
+                let across = r.dot(direction.perp());
+                (direction * along - direction.perp() * across) * falloff
+            }
+            FieldPrimitiveKind::Dipole => {
+                // A doublet: fluid leaves from the front and loops back around to the rear
+                let along = r.dot(direction);
+                (r * (2.0 * along) - direction * (1.0 + distance_sq)) * falloff * falloff
+            }
+            FieldPrimitiveKind::Uniform => direction,
+        };
+
+        velocity * self.strength
+    }
+}
+
+/// Sum the contributions of every primitive at `point`
+pub fn velocity_at(primitives: &[FieldPrimitive], point: Vec2) -> Vec2 {
+    primitives
+        .iter()
+        .map(|primitive| primitive.velocity_at(point))
+        .fold(Vec2::ZERO, |acc, velocity| acc + velocity)
+}
+
+pub fn default_field_primitives() -> Vec<FieldPrimitive> {
+    vec![FieldPrimitive::new(
+        FieldPrimitiveKind::CounterclockwiseVortex,
+        Vec2::ZERO,
+    )]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn primitive(kind: FieldPrimitiveKind) -> FieldPrimitive {
+        FieldPrimitive::new(kind, Vec2::new(50.0, -20.0))
+    }
+
+    // Points on the primitive's radius, all the way around it
+    fn around(primitive: &FieldPrimitive) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+        (0..16).map(move |i| {
+            let angle = i as f32 / 16.0 * std::f32::consts::TAU;
+            let outward = Vec2::new(angle.cos(), angle.sin());
+            (outward, primitive.velocity_at(primitive.xy + outward * primitive.radius))
+        })
+    }
+
+    #[test]
+    fn vortices_are_tangent_at_their_radius() {
+        for (kind, turn) in [
+            (FieldPrimitiveKind::CounterclockwiseVortex, 1.0),
+            (FieldPrimitiveKind::ClockwiseVortex, -1.0),
+        ] {
+            for (outward, velocity) in around(&primitive(kind)) {
+                assert!(velocity.dot(outward).abs() < 1e-5, "{} isn't tangent", kind);
+                // Halfway down the falloff, turning the way the vortex spins
+                assert!((velocity - outward.perp() * turn * 0.5).length() < 1e-5, "{}: {}", kind, velocity);
+            }
+        }
+    }
+
+    #[test]
+    fn sources_point_out_and_sinks_point_in() {
+        for (outward, velocity) in around(&primitive(FieldPrimitiveKind::Source)) {
+            assert!((velocity - outward * 0.5).length() < 1e-5);
+        }
+        for (outward, velocity) in around(&primitive(FieldPrimitiveKind::Sink)) {
+            assert!((velocity + outward * 0.5).length() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn primitives_are_still_at_their_center_and_fade_far_away() {
+        for kind in FieldPrimitiveKind::ALL {
+            let primitive = primitive(kind);
+            let far = primitive.velocity_at(primitive.xy + Vec2::new(1e5, 0.0)).length();
+            if kind == FieldPrimitiveKind::Uniform {
+                assert_eq!(far, 1.0);
+                continue;
+            }
+            if kind != FieldPrimitiveKind::Dipole {
+                assert_eq!(primitive.velocity_at(primitive.xy), Vec2::ZERO, "{}", kind);
+            }
+            assert!(far < 0.01, "{} reaches {} far away", kind, far);
+        }
+    }
+
+    #[test]
+    fn saddles_stretch_along_their_angle() {
+        let mut saddle = primitive(FieldPrimitiveKind::Saddle);
+        saddle.angle = 90.0;
+        let along = saddle.velocity_at(saddle.xy + Vec2::new(0.0, 100.0));
+        let across = saddle.velocity_at(saddle.xy + Vec2::new(100.0, 0.0));
+        assert!(along.y > 0.0 && along.x.abs() < 1e-5, "{}", along);
+        assert!(across.x < 0.0 && across.y.abs() < 1e-5, "{}", across);
+    }
+
+    #[test]
+    fn strength_scales_and_primitives_add_up() {
+        let mut strong = primitive(FieldPrimitiveKind::Source);
+        strong.strength = 3.0;
+        let point = Vec2::new(120.0, 40.0);
+        let weak = primitive(FieldPrimitiveKind::Source);
+        assert!((strong.velocity_at(point) - weak.velocity_at(point) * 3.0).length() < 1e-5);
+
+        let sink = primitive(FieldPrimitiveKind::Sink);
+        assert_eq!(velocity_at(&[weak, sink], point), Vec2::ZERO);
+    }
+}
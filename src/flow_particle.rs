@@ -1,4 +1,4 @@
-use glam::Vec2;
+use glam::{Vec2, Vec3};
 use egui::Color32;
 use crate::model::enums::ParticleShape;
 
@@ -6,7 +6,15 @@ use crate::model::enums::ParticleShape;
 pub struct FlowParticle {
     age: f32,
     aging_rate: f32,
+    // The age it spawned at, which isn't always 0
+    birth_age: f32,
     pub color: Color32,
+    /// Where along the LUT the color came from, for particles colored from the LUT
+    pub lut_position: Option<f32>,
+    /// Radians the shape is turned from the heading, fixed for the particle's life
+    pub rotation_jitter: f32,
+    /// Per-particle offset added to colors sampled from a source image
+    pub color_jitter: Vec3,
     pub previous_xy: Vec2,
     step_length: f32,
     weight: f32,
@@ -28,7 +36,11 @@ impl FlowParticle {
         FlowParticle {
             age,
             aging_rate,
+            birth_age: age,
             color,
+            lut_position: None,
+            rotation_jitter: 0.0,
+            color_jitter: Vec3::ZERO,
             previous_xy: xy,
             step_length,
             weight,
@@ -49,6 +61,20 @@ impl FlowParticle {
         self.age
     }
 
+    /// Frames since the particle spawned
+    pub fn frames_alive(&self) -> f32 {
+        (self.age - self.birth_age) / self.aging_rate
+    }
+
+    /// How far through its life the particle is, from 0 when it spawned to 1 at `lifetime`
+    pub fn life(&self, lifetime: f32) -> f32 {
+        let span = lifetime - self.birth_age;
+        if span <= 0.0 {
+            return 1.0;
+        }
+        ((self.age - self.birth_age) / span).clamp(0.0, 1.0)
+    }
+
     pub fn xy(&self) -> &Vec2 {
         &self.xy
     }
@@ -76,7 +102,11 @@ impl Default for FlowParticle {
         FlowParticle {
             age: 0.0,
             aging_rate: 0.1,
+            birth_age: 0.0,
             color: Color32::BLACK,
+            lut_position: None,
+            rotation_jitter: 0.0,
+            color_jitter: Vec3::ZERO,
             previous_xy: xy,
             step_length: 1.0,
             weight: 1.0,
@@ -87,7 +117,7 @@ impl Default for FlowParticle {
     }
 }
 
-pub type FlowParticleBuilderFn = Box<dyn Fn(FlowParticleBuilderFnOptions) -> FlowParticle>;
+pub type FlowParticleBuilderFn = Box<dyn Fn(FlowParticleBuilderFnOptions) -> FlowParticle + Send>;
 
 pub struct FlowParticleBuilderFnOptions {
     pub age: f32,
@@ -1,11 +1,19 @@
-use crate::model::Model;
+use crate::expression::Variables;
+use crate::field_primitive;
+use crate::guide_curve;
+use crate::model::{
+    constants::{DEFAULT_VECTOR_SPACING, SIMULATION_FRAMES_PER_SECOND},
+    Model,
+};
+use crate::source_image::ImageFieldMode;
 use log::debug;
 use glam::Vec2;
 use noise::{NoiseFn, OpenSimplex, Billow, BasicMulti, Fbm, HybridMulti, Value, Worley, MultiFractal};
+use serde::{Deserialize, Serialize};
 
 const TAU: f32 = 2.0 * std::f32::consts::PI;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FlowVector {
     xy: Vec2,
     vector: Vec2,
@@ -26,6 +34,13 @@ impl FlowVector {
         self.vector.y = heading.sin() * mag;
     }
 
+    pub fn set_heading(&mut self, a: f32) {
+        let heading = a.to_radians();
+        let mag = self.mag();
+        self.vector.x = heading.cos() * mag;
+        self.vector.y = heading.sin() * mag;
+    }
+
     fn mag(&self) -> f32 {
         self.mag_sq().sqrt()
     }
@@ -48,7 +63,7 @@ impl FlowVector {
     }
 }
 
-pub type FlowVectorFieldBuilderFn = Box<dyn Fn(&Model) -> Vec<FlowVector>>;
+pub type FlowVectorFieldBuilderFn = Box<dyn Fn(&Model) -> Vec<FlowVector> + Send>;
 
 pub fn new_right_hand_curve_flow_vectors(model: &Model) -> Vec<FlowVector> {
     debug!("creating new vector field with a right handed curve");
@@ -69,14 +84,192 @@ pub fn new_right_hand_curve_flow_vectors(model: &Model) -> Vec<FlowVector> {
         .collect()
 }
 
+pub fn new_primitive_flow_vectors(model: &Model) -> Vec<FlowVector> {
+    debug!("creating new vector field from {} field primitives", model.field_primitives.len());
+    let (origin_x, origin_y) = model.get_origin();
+    (0..model.grid_height)
+        .flat_map(move |column_index| {
+            (0..model.grid_width).map(move |row_index| {
+                let xy = Vec2::new(
+                    (row_index as f32 * model.vector_spacing) + origin_x,
+                    (column_index as f32 * model.vector_spacing) + origin_y,
+                );
+                let mut fv = FlowVector::new(xy, model.vector_magnitude);
+                let velocity = field_primitive::velocity_at(&model.field_primitives, xy);
+                // `FlowVector::new` points along +y so rotate relative to that
+                let a = velocity.y.atan2(velocity.x) - std::f32::consts::FRAC_PI_2;
+                fv.rotate(a.to_degrees());
+                fv
+            })
+        })
+        .collect()
+}
+
+pub fn new_guide_curve_flow_vectors(model: &Model) -> Vec<FlowVector> {
+    debug!("creating new vector field from {} guide curves", model.guide_curves.len());
+    let settings = &model.guide_curve_settings;
+    let noise_vectors = if settings.noise_mix > 0.0 && settings.noise_builder.is_noise() {
+        Some((settings.noise_builder.as_fn())(model))
+    } else {
+        None
+    };
+
+    let (origin_x, origin_y) = model.get_origin();
+    let mut flow_vectors = Vec::with_capacity(model.grid_width * model.grid_height);
+    for column_index in 0..model.grid_height {
+        for row_index in 0..model.grid_width {
+            let xy = Vec2::new(
+                (row_index as f32 * model.vector_spacing) + origin_x,
+                (column_index as f32 * model.vector_spacing) + origin_y,
+            );
+            let mut direction = guide_curve::tangent_at(&model.guide_curves, settings, xy).unwrap_or(Vec2::Y);
+
+            if let Some(noise_vectors) = &noise_vectors {
+                let noise_direction = noise_vectors[flow_vectors.len()].direction().normalize_or_zero();
+                direction = direction.lerp(noise_direction, settings.noise_mix);
+            }
+
+            let mut fv = FlowVector::new(xy, model.vector_magnitude);
+            fv.set_heading(direction.y.atan2(direction.x).to_degrees());
+            flow_vectors.push(fv);
+        }
+    }
+    flow_vectors
+}
+
+pub fn new_image_flow_vectors(model: &Model) -> Vec<FlowVector> {
+    let Some(source_image) = &model.source_image else {
+        debug!("no source image is loaded, creating a uniform vector field");
+        return new_uniform_flow_vectors(model);
+    };
+    debug!("creating new vector field from {}", source_image.path().display());
+
+    let (origin_x, origin_y) = model.get_origin();
+    let step = model.vector_spacing / 2.0;
+    (0..model.grid_height)
+        .flat_map(move |column_index| {
+            (0..model.grid_width).map(move |row_index| {
+                let xy = Vec2::new(
+                    (row_index as f32 * model.vector_spacing) + origin_x,
+                    (column_index as f32 * model.vector_spacing) + origin_y,
+                );
+                let mut fv = FlowVector::new(xy, model.vector_magnitude);
+                let heading = match model.image_field_mode {
+                    ImageFieldMode::AlongEdges => {
+                        let gradient = source_image.luminance_gradient(xy, &model.window_rect, step);
+                        gradient.perp().try_normalize().map(|d| d.y.atan2(d.x).to_degrees())
+                    }
+                    ImageFieldMode::AlongGradient => {
+                        let gradient = source_image.luminance_gradient(xy, &model.window_rect, step);
+                        gradient.try_normalize().map(|d| d.y.atan2(d.x).to_degrees())
+                    }
+                    ImageFieldMode::HueAngle => source_image.hue(xy, &model.window_rect),
+                };
+                // Flat or gray areas keep the default upward heading
+                if let Some(heading) = heading {
+                    fv.set_heading(heading);
+                }
+                fv
+            })
+        })
+        .collect()
+}
+
+pub fn new_expression_flow_vectors(model: &Model) -> Vec<FlowVector> {
+    let compiled = match model.field_expression.compile() {
+        Ok(compiled) => compiled,
+        Err(e) => {
+            debug!("field expression doesn't parse ({}), creating a uniform vector field", e);
+            return new_uniform_flow_vectors(model);
+        }
+    };
+    debug!("creating new vector field from an expression");
+
+    let noise = OpenSimplex::new(model.noise_seed);
+    let t = model.frame_count as f64 / SIMULATION_FRAMES_PER_SECOND;
+    // Expressions see coordinates scaled so the shorter side of the canvas spans -1 to 1
+    let half_extent = ((model.window_rect.right - model.window_rect.left)
+        .min(model.window_rect.top - model.window_rect.bottom)
+        / 2.0) as f64;
+    let (origin_x, origin_y) = model.get_origin();
+    let mut flow_vectors = Vec::with_capacity(model.grid_width * model.grid_height);
+    for column_index in 0..model.grid_height {
+        for row_index in 0..model.grid_width {
+            let xy = Vec2::new(
+                (row_index as f32 * model.vector_spacing) + origin_x,
+                (column_index as f32 * model.vector_spacing) + origin_y,
+            );
+            let variables = Variables {
+                x: xy.x as f64 / half_extent,
+                y: xy.y as f64 / half_extent,
+                t,
+                seed: model.noise_seed as f64,
+                noise: &noise,
+            };
+            let mut fv = FlowVector::new(xy, model.vector_magnitude);
+            let heading = compiled.heading(&variables);
+            // Division by zero and friends leave the default heading in place
+            if heading.is_finite() {
+                fv.set_heading((heading as f32).to_degrees());
+            }
+            flow_vectors.push(fv);
+        }
+    }
+    flow_vectors
+}
+
+pub fn new_fluid_flow_vectors(model: &Model) -> Vec<FlowVector> {
+    let fluid = match &model.fluid {
+        Some(fluid) if fluid.dimensions() == (model.grid_width, model.grid_height) => fluid,
+        // Until the solver starts, keep the current field so it can seed the fluid's velocity
+        _ if model.flow_vectors.len() == model.grid_width * model.grid_height => {
+            return model.flow_vectors.clone();
+        }
+        _ => return new_uniform_flow_vectors(model),
+    };
+
+    let (origin_x, origin_y) = model.get_origin();
+    (0..model.grid_height)
+        .flat_map(move |column_index| {
+            (0..model.grid_width).map(move |row_index| {
+                let xy = Vec2::new(
+                    (row_index as f32 * model.vector_spacing) + origin_x,
+                    (column_index as f32 * model.vector_spacing) + origin_y,
+                );
+                let mut fv = FlowVector::new(xy, model.vector_magnitude);
+                let velocity = fluid.velocity(row_index, column_index);
+                // Still fluid keeps the default upward heading
+                if velocity.length_squared() > f32::EPSILON {
+                    fv.set_heading(velocity.y.atan2(velocity.x).to_degrees());
+                }
+                fv
+            })
+        })
+        .collect()
+}
+
+fn new_uniform_flow_vectors(model: &Model) -> Vec<FlowVector> {
+    let (origin_x, origin_y) = model.get_origin();
+    (0..model.grid_height)
+        .flat_map(move |column_index| {
+            (0..model.grid_width).map(move |row_index| {
+                let xy = Vec2::new(
+                    (row_index as f32 * model.vector_spacing) + origin_x,
+                    (column_index as f32 * model.vector_spacing) + origin_y,
+                );
+                FlowVector::new(xy, model.vector_magnitude)
+            })
+        })
+        .collect()
+}
+
 // Generic noise function that works with any NoiseFn
 fn create_noise_flow_vectors<N: NoiseFn<f64, 2> + Clone>(model: &Model, noise: N) -> Vec<FlowVector> {
     let (origin_x, origin_y) = model.get_origin();
     
-    // Generate random offsets for X and Y
-    let mut rng = rand::thread_rng();
-    let x_offset = rand::Rng::gen_range(&mut rng, 0.0..1000.0);
-    let y_offset = rand::Rng::gen_range(&mut rng, 0.0..1000.0);
+    // Sample by position rather than cell index so the pattern keeps its size at any resolution
+    // and stays put when the window is resized
+    let sample_scale = model.noise_scale / DEFAULT_VECTOR_SPACING as f64;
     
     (0..model.grid_height)
         .flat_map(move |column_index| {
@@ -87,10 +280,8 @@ fn create_noise_flow_vectors<N: NoiseFn<f64, 2> + Clone>(model: &Model, noise: N
                     (column_index as f32 * model.vector_spacing) + origin_y,
                 );
                 let mut fv = FlowVector::new(xy, model.vector_magnitude);
-                let noise_value = noise.get([
-                    (row_index as f64 * model.noise_scale) + x_offset,
-                    (column_index as f64 * model.noise_scale) + y_offset,
-                ]) as f32;
+                let sample = xy.as_dvec2() * sample_scale + model.noise_offset;
+                let noise_value = noise.get([sample.x, sample.y]) as f32;
                 let a = noise_value * TAU;
                 fv.rotate(a.to_degrees());
                 fv
@@ -148,7 +339,7 @@ pub fn new_worley_noise_flow_vectors(model: &Model) -> Vec<FlowVector> {
     create_noise_flow_vectors(model, noise)
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum FlowVectorFieldBuilder {
     RightHandCurve,
     BasicMulti,
@@ -159,6 +350,11 @@ pub enum FlowVectorFieldBuilder {
     OpenSimplex,
     Value,
     Worley,
+    Primitives,
+    GuideCurves,
+    Image,
+    Expression,
+    Fluid,
 }
 
 impl FlowVectorFieldBuilder {
@@ -173,6 +369,24 @@ impl FlowVectorFieldBuilder {
             Self::OpenSimplex => new_simplex_noise_flow_vectors,
             Self::Value => new_value_noise_flow_vectors,
             Self::Worley => new_worley_noise_flow_vectors,
+            Self::Primitives => new_primitive_flow_vectors,
+            Self::GuideCurves => new_guide_curve_flow_vectors,
+            Self::Image => new_image_flow_vectors,
+            Self::Expression => new_expression_flow_vectors,
+            Self::Fluid => new_fluid_flow_vectors,
         })
     }
+
+    // Noise builders only depend on the seed and scale, so other builders can layer them in
+    pub fn is_noise(&self) -> bool {
+        !matches!(
+            self,
+            Self::RightHandCurve
+                | Self::Primitives
+                | Self::GuideCurves
+                | Self::Image
+                | Self::Expression
+                | Self::Fluid
+        )
+    }
 }
@@ -0,0 +1,309 @@
+use crate::flow_vector::FlowVector;
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FluidSettings {
+    pub viscosity: f32,
+    pub diffusion: f32,
+    /// How much of the mouse movement is injected as velocity
+    pub force: f32,
+    /// Radius in pixels of the area a mouse drag stirs
+    pub radius: f32,
+    pub inject_dye: bool,
+    pub dye_amount: f32,
+    /// Fraction of the dye that survives each step
+    pub dye_persistence: f32,
+}
+
+impl Default for FluidSettings {
+    fn default() -> Self {
+        Self {
+            viscosity: 0.0001,
+            diffusion: 0.0001,
+            force: 1.0,
+            radius: 60.0,
+            inject_dye: true,
+            dye_amount: 0.5,
+            dye_persistence: 0.99,
+        }
+    }
+}
+
+const TIME_STEP: f32 = 0.1;
+const SOLVER_ITERATIONS: usize = 20;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Boundary {
+    Scalar,
+    HorizontalVelocity,
+    VerticalVelocity,
+}
+
+/// Jos Stam's "stable fluids" on a grid with one cell of padding around the edges. Velocities
+/// are measured in cells per unit of time.
+#[derive(Clone)]
+pub struct FluidSolver {
+    width: usize,
+    height: usize,
+    u: Vec<f32>,
+    v: Vec<f32>,
+    u_prev: Vec<f32>,
+    v_prev: Vec<f32>,
+    dye: Vec<f32>,
+    dye_prev: Vec<f32>,
+}
+
+impl FluidSolver {
+    pub fn new(width: usize, height: usize) -> Self {
+        let size = (width + 2) * (height + 2);
+        Self {
+            width,
+            height,
+            u: vec![0.0; size],
+            v: vec![0.0; size],
+            u_prev: vec![0.0; size],
+            v_prev: vec![0.0; size],
+            dye: vec![0.0; size],
+            dye_prev: vec![0.0; size],
+        }
+    }
+
+    /// Start the fluid moving along an existing field, one cell per unit of time
+    pub fn from_flow_vectors(flow_vectors: &[FlowVector], width: usize, height: usize) -> Self {
+        let mut fluid = Self::new(width, height);
+        for (index, fv) in flow_vectors.iter().enumerate().take(width * height) {
+            let direction = fv.direction().normalize_or_zero();
+            let cell = fluid.ix(index % width + 1, index / width + 1);
+            fluid.u[cell] = direction.x;
+            fluid.v[cell] = direction.y;
+        }
+        fluid
+    }
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn ix(&self, i: usize, j: usize) -> usize {
+        i + (self.width + 2) * j
+    }
+
+    /// The velocity of the cell matching the flow vector at (`row_index`, `column_index`)
+    pub fn velocity(&self, row_index: usize, column_index: usize) -> Vec2 {
+        let cell = self.ix(row_index + 1, column_index + 1);
+        Vec2::new(self.u[cell], self.v[cell])
+    }
+
+    pub fn dye(&self, row_index: usize, column_index: usize) -> f32 {
+        self.dye[self.ix(row_index + 1, column_index + 1)]
+    }
+
+    /// Add velocity and dye to every cell within `radius` cells of `center`, fading toward the edge
+    pub fn splat(&mut self, center: Vec2, radius: f32, velocity: Vec2, dye: f32) {
+        let reach = radius.ceil() as i64;
+        for j in (center.y.round() as i64 - reach)..=(center.y.round() as i64 + reach) {
+            for i in (center.x.round() as i64 - reach)..=(center.x.round() as i64 + reach) {
+                if i < 0 || j < 0 || i >= self.width as i64 || j >= self.height as i64 {
+                    continue;
+                }
+
+                let distance = Vec2::new(i as f32, j as f32).distance(center);
+                if distance > radius {
+                    continue;
+                }
+                let weight = 1.0 - distance / radius.max(f32::EPSILON);
+                let cell = self.ix(i as usize + 1, j as usize + 1);
+                self.u[cell] += velocity.x * weight;
+                self.v[cell] += velocity.y * weight;
+                self.dye[cell] = (self.dye[cell] + dye * weight).min(1.0);
+            }
+        }
+    }
+
+    pub fn step(&mut self, settings: &FluidSettings) {
+        self.velocity_step(settings.viscosity);
+        self.dye_step(settings.diffusion);
+        for dye in self.dye.iter_mut() {
+            *dye *= settings.dye_persistence;
+        }
+    }
+
+    fn velocity_step(&mut self, viscosity: f32) {
+        std::mem::swap(&mut self.u, &mut self.u_prev);
+        std::mem::swap(&mut self.v, &mut self.v_prev);
+        self.diffuse(Boundary::HorizontalVelocity, Field::U, viscosity);
+        self.diffuse(Boundary::VerticalVelocity, Field::V, viscosity);
+        self.project();
+
+        std::mem::swap(&mut self.u, &mut self.u_prev);
+        std::mem::swap(&mut self.v, &mut self.v_prev);
+        self.advect_velocity();
+        self.project();
+    }
+
+    fn dye_step(&mut self, diffusion: f32) {
+        std::mem::swap(&mut self.dye, &mut self.dye_prev);
+        self.diffuse(Boundary::Scalar, Field::Dye, diffusion);
+        std::mem::swap(&mut self.dye, &mut self.dye_prev);
+        let mut dye = std::mem::take(&mut self.dye);
+        self.advect(Boundary::Scalar, &mut dye, &self.dye_prev, &self.u, &self.v);
+        self.dye = dye;
+    }
+
+    fn fields(&mut self, field: Field) -> (&mut Vec<f32>, &Vec<f32>) {
+        match field {
+            Field::U => (&mut self.u, &self.u_prev),
+            Field::V => (&mut self.v, &self.v_prev),
+            Field::Dye => (&mut self.dye, &self.dye_prev),
+        }
+    }
+
+    fn diffuse(&mut self, boundary: Boundary, field: Field, rate: f32) {
+        let a = TIME_STEP * rate * (self.width * self.height) as f32;
+        let (width, height) = (self.width, self.height);
+        let (x, x0) = self.fields(field);
+        lin_solve(width, height, boundary, x, x0, a, 1.0 + 4.0 * a);
+    }
+
+    fn advect_velocity(&mut self) {
+        let mut u = std::mem::take(&mut self.u);
+        let mut v = std::mem::take(&mut self.v);
+        self.advect(Boundary::HorizontalVelocity, &mut u, &self.u_prev, &self.u_prev, &self.v_prev);
+        self.advect(Boundary::VerticalVelocity, &mut v, &self.v_prev, &self.u_prev, &self.v_prev);
+        self.u = u;
+        self.v = v;
+    }
+
+    // Trace each cell back through the velocity field and sample where it came from
+    fn advect(&self, boundary: Boundary, d: &mut [f32], d0: &[f32], u: &[f32], v: &[f32]) {
+        let (width, height) = (self.width, self.height);
+        for j in 1..=height {
+            for i in 1..=width {
+                let cell = self.ix(i, j);
+                let x = (i as f32 - TIME_STEP * u[cell]).clamp(0.5, width as f32 + 0.5);
+                let y = (j as f32 - TIME_STEP * v[cell]).clamp(0.5, height as f32 + 0.5);
+                let (i0, j0) = (x.floor() as usize, y.floor() as usize);
+                let (s1, t1) = (x - i0 as f32, y - j0 as f32);
+                let (s0, t0) = (1.0 - s1, 1.0 - t1);
+                d[cell] = s0 * (t0 * d0[self.ix(i0, j0)] + t1 * d0[self.ix(i0, j0 + 1)])
+                    + s1 * (t0 * d0[self.ix(i0 + 1, j0)] + t1 * d0[self.ix(i0 + 1, j0 + 1)]);
+            }
+        }
+        set_boundary(width, height, boundary, d);
+    }
+
+    // Remove divergence so the fluid stays incompressible and swirls instead of piling up
+    fn project(&mut self) {
+        let (width, height) = (self.width, self.height);
+        let stride = width + 2;
+        let (p, div) = (&mut self.u_prev, &mut self.v_prev);
+        for j in 1..=height {
+            for i in 1..=width {
+                let cell = i + stride * j;
+                div[cell] = -0.5 * (self.u[cell + 1] - self.u[cell - 1] + self.v[cell + stride] - self.v[cell - stride]);
+                p[cell] = 0.0;
+            }
+        }
+        set_boundary(width, height, Boundary::Scalar, div);
+        set_boundary(width, height, Boundary::Scalar, p);
+        lin_solve(width, height, Boundary::Scalar, p, div, 1.0, 4.0);
+
+        for j in 1..=height {
+            for i in 1..=width {
+                let cell = i + stride * j;
+                self.u[cell] -= 0.5 * (p[cell + 1] - p[cell - 1]);
+                self.v[cell] -= 0.5 * (p[cell + stride] - p[cell - stride]);
+            }
+        }
+        set_boundary(width, height, Boundary::HorizontalVelocity, &mut self.u);
+        set_boundary(width, height, Boundary::VerticalVelocity, &mut self.v);
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Field {
+    U,
+    V,
+    Dye,
+}
+
+// Gauss-Seidel relaxation
+fn lin_solve(width: usize, height: usize, boundary: Boundary, x: &mut [f32], x0: &[f32], a: f32, c: f32) {
+    let stride = width + 2;
+    for _ in 0..SOLVER_ITERATIONS {
+        for j in 1..=height {
+            for i in 1..=width {
+                let cell = i + stride * j;
+                x[cell] = (x0[cell] + a * (x[cell - 1] + x[cell + 1] + x[cell - stride] + x[cell + stride])) / c;
+            }
+        }
+        set_boundary(width, height, boundary, x);
+    }
+}
+
+// Walls reflect the velocity component pointing into them and copy everything else
+fn set_boundary(width: usize, height: usize, boundary: Boundary, x: &mut [f32]) {
+    let stride = width + 2;
+    let ix = |i: usize, j: usize| i + stride * j;
+    for j in 1..=height {
+        let sign = if boundary == Boundary::HorizontalVelocity { -1.0 } else { 1.0 };
+        x[ix(0, j)] = sign * x[ix(1, j)];
+        x[ix(width + 1, j)] = sign * x[ix(width, j)];
+    }
+    for i in 1..=width {
+        let sign = if boundary == Boundary::VerticalVelocity { -1.0 } else { 1.0 };
+        x[ix(i, 0)] = sign * x[ix(i, 1)];
+        x[ix(i, height + 1)] = sign * x[ix(i, height)];
+    }
+    x[ix(0, 0)] = 0.5 * (x[ix(1, 0)] + x[ix(0, 1)]);
+    x[ix(0, height + 1)] = 0.5 * (x[ix(1, height + 1)] + x[ix(0, height)]);
+    x[ix(width + 1, 0)] = 0.5 * (x[ix(width, 0)] + x[ix(width + 1, 1)]);
+    x[ix(width + 1, height + 1)] = 0.5 * (x[ix(width, height + 1)] + x[ix(width + 1, height)]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total_dye(fluid: &FluidSolver) -> f32 {
+        let (width, height) = fluid.dimensions();
+        (0..height).flat_map(|j| (0..width).map(move |i| (i, j))).map(|(i, j)| fluid.dye(i, j)).sum()
+    }
+
+    fn settings(diffusion: f32) -> FluidSettings {
+        FluidSettings {
+            diffusion,
+            dye_persistence: 1.0,
+            ..FluidSettings::default()
+        }
+    }
+
+    #[test]
+    fn diffusion_spreads_dye_without_losing_any() {
+        let mut fluid = FluidSolver::new(48, 32);
+        fluid.splat(Vec2::new(10.0, 8.0), 5.0, Vec2::ZERO, 1.0);
+        let before = total_dye(&fluid);
+        let peak = fluid.dye(10, 8);
+        for _ in 0..50 {
+            fluid.step(&settings(0.001));
+        }
+        let after = total_dye(&fluid);
+        assert!((after - before).abs() / before < 0.01, "{} became {}", before, after);
+        assert!(fluid.dye(10, 8) < peak / 2.0, "dye didn't spread");
+    }
+
+    #[test]
+    fn advection_moves_dye_without_losing_much() {
+        let mut fluid = FluidSolver::new(48, 48);
+        fluid.splat(Vec2::new(24.0, 24.0), 6.0, Vec2::new(20.0, 10.0), 1.0);
+        let before = total_dye(&fluid);
+        for _ in 0..30 {
+            fluid.step(&settings(0.0));
+        }
+        // Semi-Lagrangian advection smears a little dye away each step, but only a little
+        let after = total_dye(&fluid);
+        assert!((after - before).abs() / before < 0.1, "{} became {}", before, after);
+        assert!(fluid.dye(24, 24) < 0.99, "dye didn't move");
+    }
+}
@@ -0,0 +1,184 @@
+use crate::flow_vector::FlowVectorFieldBuilder;
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+/// A polyline sketched on the canvas. The field follows its tangents.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GuideCurve {
+    pub points: Vec<Vec2>,
+}
+
+impl GuideCurve {
+    pub fn segments(&self) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+        self.points.windows(2).map(|pair| (pair[0], pair[1]))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum GuideWeighting {
+    InverseDistance,
+    RadialBasis,
+}
+
+impl GuideWeighting {
+    pub const ALL: [Self; 2] = [Self::InverseDistance, Self::RadialBasis];
+}
+
+impl Display for GuideWeighting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InverseDistance => write!(f, "Inverse Distance"),
+            Self::RadialBasis => write!(f, "Radial Basis"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuideCurveSettings {
+    pub weighting: GuideWeighting,
+    /// Exponent for inverse distance weighting
+    pub power: f32,
+    /// Width in pixels of the gaussian used for radial basis weighting
+    pub radius: f32,
+    /// How much of `noise_builder`'s field is blended in, from 0 to 1
+    pub noise_mix: f32,
+    pub noise_builder: FlowVectorFieldBuilder,
+}
+
+impl Default for GuideCurveSettings {
+    fn default() -> Self {
+        Self {
+            weighting: GuideWeighting::InverseDistance,
+            power: 2.0,
+            radius: 120.0,
+            noise_mix: 0.0,
+            noise_builder: FlowVectorFieldBuilder::Billow,
+        }
+    }
+}
+
+/// The weighted average of every guide segment's tangent at `point`, or `None` without guides
+pub fn tangent_at(guide_curves: &[GuideCurve], settings: &GuideCurveSettings, point: Vec2) -> Option<Vec2> {
+    let samples: Vec<(f32, Vec2)> = guide_curves
+        .iter()
+        .flat_map(|curve| curve.segments())
+        .filter_map(|(a, b)| {
+            let tangent = (b - a).try_normalize()?;
+            Some((distance_to_segment(point, a, b), tangent))
+        })
+        .collect();
+
+    let nearest = samples
+        .iter()
+        .map(|(distance, _)| *distance)
+        .min_by(|a, b| a.total_cmp(b))?;
+
+    let radius_sq = settings.radius.max(1.0).powi(2);
+    let sum = samples.iter().fold(Vec2::ZERO, |acc, (distance, tangent)| {
+        let weight = match settings.weighting {
+            GuideWeighting::InverseDistance => 1.0 / (distance.powf(settings.power) + 1.0),
+            // Relative to the nearest segment so far away points don't underflow to zero
+            GuideWeighting::RadialBasis => (-(distance.powi(2) - nearest.powi(2)) / radius_sq).exp(),
+        };
+        acc + *tangent * weight
+    });
+
+    sum.try_normalize()
+}
+
+fn distance_to_segment(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let t = ((point - a).dot(ab) / ab.length_squared()).clamp(0.0, 1.0);
+    point.distance(a + ab * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_vector::new_guide_curve_flow_vectors;
+    use crate::model::Model;
+
+    fn curve(points: &[(f32, f32)]) -> GuideCurve {
+        GuideCurve {
+            points: points.iter().map(|&(x, y)| Vec2::new(x, y)).collect(),
+        }
+    }
+
+    fn assert_near(actual: Option<Vec2>, expected: Vec2) {
+        let actual = actual.expect("no tangent");
+        assert!((actual - expected.normalize()).length() < 1e-4, "{} isn't {}", actual, expected);
+    }
+
+    #[test]
+    fn tangents_hold_past_the_ends_of_a_curve() {
+        let guides = [curve(&[(0.0, 0.0), (100.0, 0.0)])];
+        for weighting in GuideWeighting::ALL {
+            let settings = GuideCurveSettings { weighting, ..GuideCurveSettings::default() };
+            for point in [(0.0, 0.0), (100.0, 0.0), (-50.0, 10.0), (160.0, -30.0)] {
+                assert_near(tangent_at(&guides, &settings, Vec2::from(point)), Vec2::X);
+            }
+        }
+        assert_eq!(tangent_at(&[], &GuideCurveSettings::default(), Vec2::ZERO), None);
+        // A single point has no direction to follow
+        assert_eq!(tangent_at(&[curve(&[(5.0, 5.0)])], &GuideCurveSettings::default(), Vec2::ZERO), None);
+    }
+
+    #[test]
+    fn sharp_corners_split_the_difference() {
+        let guides = [curve(&[(0.0, 0.0), (100.0, 0.0), (100.0, 100.0)])];
+        for weighting in GuideWeighting::ALL {
+            let settings = GuideCurveSettings { weighting, ..GuideCurveSettings::default() };
+            assert_near(tangent_at(&guides, &settings, Vec2::new(100.0, 0.0)), Vec2::ONE);
+            // Along either leg, away from the corner, the nearer leg wins
+            let before = tangent_at(&guides, &settings, Vec2::new(10.0, 0.0)).unwrap();
+            let after = tangent_at(&guides, &settings, Vec2::new(100.0, 90.0)).unwrap();
+            assert!(before.x > before.y && before.y > 0.0, "{} {}", weighting, before);
+            assert!(after.y > after.x && after.x > 0.0, "{} {}", weighting, after);
+        }
+    }
+
+    #[test]
+    fn the_nearest_guide_wins_beyond_the_radius() {
+        let guides = [curve(&[(0.0, 0.0), (100.0, 0.0)]), curve(&[(0.0, 1000.0), (0.0, 1100.0)])];
+        let settings = GuideCurveSettings {
+            weighting: GuideWeighting::RadialBasis,
+            ..GuideCurveSettings::default()
+        };
+        // Both guides are far outside the gaussian, which would underflow to nothing unweighted
+        assert_near(tangent_at(&guides, &settings, Vec2::new(50.0, -2000.0)), Vec2::X);
+        assert_near(tangent_at(&guides, &settings, Vec2::new(0.0, 4000.0)), Vec2::Y);
+    }
+
+    #[test]
+    fn noise_mix_blends_between_the_guides_and_the_noise() {
+        let mut model = Model::new(Vec2::new(400.0, 300.0));
+        model.guide_curves = vec![curve(&[(-200.0, 0.0), (200.0, 0.0)])];
+        let headings = |model: &Model| -> Vec<f32> {
+            new_guide_curve_flow_vectors(model).iter().map(|fv| fv.heading()).collect()
+        };
+        let turn = |from: f32, to: f32| (to - from + 180.0).rem_euclid(360.0) - 180.0;
+
+        model.guide_curve_settings.noise_mix = 0.0;
+        assert!(headings(&model).iter().all(|heading| heading.abs() < 1e-3));
+
+        model.guide_curve_settings.noise_mix = 1.0;
+        let noise: Vec<f32> = (model.guide_curve_settings.noise_builder.as_fn())(&model)
+            .iter()
+            .map(|fv| fv.heading())
+            .collect();
+        for (blended, noise) in headings(&model).iter().zip(&noise) {
+            assert!(turn(*noise, *blended).abs() < 1e-2, "{} isn't {}", blended, noise);
+        }
+
+        // Halfway, each vector lands between the guide and the noise
+        model.guide_curve_settings.noise_mix = 0.5;
+        for (blended, noise) in headings(&model).iter().zip(&noise) {
+            if turn(0.0, *noise).abs() > 170.0 {
+                continue;
+            }
+            let arc = turn(0.0, *blended).abs() + turn(*blended, *noise).abs();
+            assert!((arc - turn(0.0, *noise).abs()).abs() < 1e-2, "{} isn't between 0 and {}", blended, noise);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use crate::{
+    model::{
+        constants::{DEFAULT_EXPORT_DIRECTORY, DEFAULT_PRESET_DIRECTORY},
+        enums::RedrawBackground,
+        update::update,
+        Model,
+    },
+    preset::Preset,
+    renderer::Renderer,
+    sequence::{SequenceFormat, SequenceRecorder, SequenceSettings},
+};
+use egui_wgpu::wgpu;
+use glam::Vec2;
+use log::info;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const USAGE: &str = "\
+Usage: flow --headless [options]
+
+Record the simulation without opening a window.
+
+Options:
+  --size <WIDTH>x<HEIGHT>  Canvas size in pixels (default 1280x720)
+  --frames <COUNT>         Number of frames to capture (default 300)
+  --step <COUNT>           Simulation frames per captured frame (default 2)
+  --format <png|gif>       Numbered PNGs or an animated GIF (default png)
+  --preset <NAME>          A preset to load from the presets directory
+  --seed <NUMBER>          Seed the noise field and the simulation's random numbers
+  --output <DIRECTORY>     Where to write the recording (default exports)";
+
+#[derive(Debug, Clone)]
+pub struct HeadlessOptions {
+    pub canvas_size: (u32, u32),
+    pub sequence: SequenceSettings,
+    pub preset: Option<String>,
+    pub seed: Option<u64>,
+    pub output: PathBuf,
+}
+
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        Self {
+            canvas_size: (1280, 720),
+            sequence: SequenceSettings::default(),
+            preset: None,
+            seed: None,
+            output: PathBuf::from(DEFAULT_EXPORT_DIRECTORY),
+        }
+    }
+}
+
+impl HeadlessOptions {
+    /// Parse the command line arguments that follow `--headless`
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--size" => {
+                    let size = value()?;
+                    let (width, height) = size
+                        .split_once('x')
+                        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+                        .filter(|&(width, height)| width > 0 && height > 0)
+                        .ok_or_else(|| format!("invalid size \"{}\"", size))?;
+                    options.canvas_size = (width, height);
+                }
+                "--frames" => options.sequence.frame_limit = parse_number(&arg, &value()?)?,
+                "--step" => options.sequence.frame_step = parse_number::<u32>(&arg, &value()?)?.max(1),
+                "--format" => {
+                    options.sequence.format = match value()?.as_str() {
+                        "png" => SequenceFormat::PngSequence,
+                        "gif" => SequenceFormat::Gif,
+                        format => return Err(format!("unknown format \"{}\"", format)),
+                    }
+                }
+                "--preset" => options.preset = Some(value()?),
+                "--seed" => options.seed = Some(parse_number(&arg, &value()?)?),
+                "--output" => options.output = PathBuf::from(value()?),
+                _ => return Err(format!("unknown option \"{}\"", arg)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{} expects a number, not \"{}\"", option, value))
+}
+
+/// Run the simulation offscreen until the recording is complete
+pub fn run(options: &HeadlessOptions) -> io::Result<PathBuf> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        compatible_surface: None,
+        force_fallback_adapter: false,
+    }))
+    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no graphics adapter found"))?;
+    let (device, queue) = pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: Some("Headless Device"),
+            required_features: wgpu::Features::empty(),
+            required_limits: wgpu::Limits::default(),
+        },
+        None,
+    ))
+    .map_err(io::Error::other)?;
+    info!("Recording headless with {}", adapter.get_info().name);
+
+    let (width, height) = options.canvas_size;
+    let canvas_size = Vec2::new(width as f32, height as f32);
+    let mut model = match options.seed {
+        Some(seed) => Model::new_seeded(canvas_size, seed),
+        None => Model::new(canvas_size),
+    };
+    if let Some(name) = &options.preset {
+        Preset::load(Path::new(DEFAULT_PRESET_DIRECTORY), name)?.apply(&mut model);
+    }
+
+    // Draw into a texture rather than a window, in the format the window would most likely use
+    let format = wgpu::TextureFormat::Bgra8UnormSrgb;
+    let mut renderer = Renderer::new(&device, &queue, format, options.canvas_size);
+    renderer.update_brush(&device, &queue, &model.brush);
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Headless Target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+
+    let mut recorder = SequenceRecorder::start(&options.output, options.sequence.clone(), options.canvas_size)?;
+    let mut should_clear = true;
+    while !recorder.is_finished() {
+        update(&mut model);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Headless Encoder"),
+        });
+        let clear = should_clear || model.redraw_background == RedrawBackground::Pending;
+        renderer.prepare(&device, &queue, &model);
+        renderer.render(&queue, &mut encoder, &view, options.canvas_size, &model, clear);
+        queue.submit(Some(encoder.finish()));
+        should_clear = false;
+
+        if recorder.wants_frame() {
+            recorder.add_frame(renderer.read_composite(&device, &queue)?)?;
+            info!("Captured frame {} of {}", recorder.frames_captured(), recorder.frame_limit());
+        }
+    }
+
+    recorder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<HeadlessOptions, String> {
+        HeadlessOptions::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn no_options_uses_the_defaults() {
+        let options = parse(&[]).unwrap();
+        assert_eq!(options.canvas_size, (1280, 720));
+        assert_eq!(options.sequence.frame_limit, 300);
+        assert_eq!(options.sequence.frame_step, 2);
+        assert_eq!(options.sequence.format, SequenceFormat::PngSequence);
+        assert_eq!(options.preset, None);
+        assert_eq!(options.seed, None);
+        assert_eq!(options.output, PathBuf::from(DEFAULT_EXPORT_DIRECTORY));
+    }
+
+    #[test]
+    fn parses_every_option() {
+        let options = parse(&[
+            "--size", "640x360", "--frames", "12", "--step", "3", "--format", "gif", "--preset", "calm", "--seed",
+            "42", "--output", "out",
+        ])
+        .unwrap();
+        assert_eq!(options.canvas_size, (640, 360));
+        assert_eq!(options.sequence.frame_limit, 12);
+        assert_eq!(options.sequence.frame_step, 3);
+        assert_eq!(options.sequence.format, SequenceFormat::Gif);
+        assert_eq!(options.preset.as_deref(), Some("calm"));
+        assert_eq!(options.seed, Some(42));
+        assert_eq!(options.output, PathBuf::from("out"));
+    }
+
+    #[test]
+    fn a_zero_step_captures_every_frame() {
+        assert_eq!(parse(&["--step", "0"]).unwrap().sequence.frame_step, 1);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        for (args, error) in [
+            (&["--size", "640"][..], "invalid size \"640\""),
+            (&["--size", "0x360"], "invalid size \"0x360\""),
+            (&["--size", "wide x tall"], "invalid size \"wide x tall\""),
+            (&["--frames", "many"], "--frames expects a number, not \"many\""),
+            (&["--seed", "-1"], "--seed expects a number, not \"-1\""),
+            (&["--format", "mp4"], "unknown format \"mp4\""),
+            (&["--frames"], "--frames needs a value"),
+            (&["--fullscreen"], "unknown option \"--fullscreen\""),
+        ] {
+            assert_eq!(parse(args).unwrap_err(), error, "{:?}", args);
+        }
+    }
+}
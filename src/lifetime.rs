@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+/// A multiplier over a particle's life, which runs from 0 when it spawns to 1 when it dies
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum LifetimeCurve {
+    #[default]
+    Constant,
+    /// Ramps up from nothing as the particle spawns
+    EaseIn,
+    /// Ramps down to nothing as the particle dies
+    EaseOut,
+    EaseInOut,
+    /// Grows steadily over the whole life
+    Rise,
+    /// Shrinks steadily over the whole life
+    Fall,
+}
+
+impl LifetimeCurve {
+    pub const ALL: [Self; 6] = [
+        Self::Constant,
+        Self::EaseIn,
+        Self::EaseOut,
+        Self::EaseInOut,
+        Self::Rise,
+        Self::Fall,
+    ];
+
+    /// The multiplier at `life`, where the eased curves take `ramp` of the life to ramp up or down
+    pub fn at(&self, life: f32, ramp: f32) -> f32 {
+        let ease_in = || smoothstep(0.0, ramp, life);
+        let ease_out = || 1.0 - smoothstep(1.0 - ramp, 1.0, life);
+        match self {
+            Self::Constant => 1.0,
+            Self::EaseIn => ease_in(),
+            Self::EaseOut => ease_out(),
+            Self::EaseInOut => ease_in() * ease_out(),
+            Self::Rise => life,
+            Self::Fall => 1.0 - life,
+        }
+    }
+}
+
+impl Display for LifetimeCurve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Constant => write!(f, "Constant"),
+            Self::EaseIn => write!(f, "Ease In"),
+            Self::EaseOut => write!(f, "Ease Out"),
+            Self::EaseInOut => write!(f, "Ease In and Out"),
+            Self::Rise => write!(f, "Rise"),
+            Self::Fall => write!(f, "Fall"),
+        }
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge1 <= edge0 {
+        return if x < edge0 { 0.0 } else { 1.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// How particles change as they age
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LifetimeSettings {
+    pub opacity: LifetimeCurve,
+    pub size: LifetimeCurve,
+    /// How much of the life the eased curves spend ramping, from 0 to 0.5
+    pub ramp: f32,
+    /// How far along the LUT a LUT-colored particle's color moves by the end of its life, from
+    /// -1 to 1
+    pub color_shift: f32,
+}
+
+impl Default for LifetimeSettings {
+    fn default() -> Self {
+        Self {
+            opacity: LifetimeCurve::Constant,
+            size: LifetimeCurve::Constant,
+            ramp: 0.15,
+            color_shift: 0.0,
+        }
+    }
+}
+
+impl LifetimeSettings {
+    pub fn opacity_at(&self, life: f32) -> f32 {
+        self.opacity.at(life, self.ramp)
+    }
+
+    pub fn size_at(&self, life: f32) -> f32 {
+        self.size.at(life, self.ramp)
+    }
+
+    /// The LUT position a particle spawned at `lut_position` has reached at `life`
+    pub fn lut_position_at(&self, lut_position: f32, life: f32) -> f32 {
+        (lut_position + self.color_shift * life).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curves_at_the_ends_of_life() {
+        for (curve, birth, middle, death) in [
+            (LifetimeCurve::Constant, 1.0, 1.0, 1.0),
+            (LifetimeCurve::EaseIn, 0.0, 1.0, 1.0),
+            (LifetimeCurve::EaseOut, 1.0, 1.0, 0.0),
+            (LifetimeCurve::EaseInOut, 0.0, 1.0, 0.0),
+            (LifetimeCurve::Rise, 0.0, 0.5, 1.0),
+            (LifetimeCurve::Fall, 1.0, 0.5, 0.0),
+        ] {
+            assert_eq!(curve.at(0.0, 0.2), birth, "{} at birth", curve);
+            assert_eq!(curve.at(0.5, 0.2), middle, "{} in the middle", curve);
+            assert_eq!(curve.at(1.0, 0.2), death, "{} at death", curve);
+        }
+    }
+
+    #[test]
+    fn eased_curves_ramp_smoothly_over_the_ramp() {
+        assert!((LifetimeCurve::EaseIn.at(0.1, 0.2) - 0.5).abs() < 1e-5);
+        assert!((LifetimeCurve::EaseOut.at(0.9, 0.2) - 0.5).abs() < 1e-5);
+        let ramp: Vec<f32> = (0..=10).map(|i| LifetimeCurve::EaseIn.at(i as f32 * 0.02, 0.2)).collect();
+        assert!(ramp.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", ramp);
+    }
+
+    #[test]
+    fn a_zero_ramp_steps_instead_of_dividing_by_zero() {
+        assert_eq!(LifetimeCurve::EaseIn.at(0.0, 0.0), 1.0);
+        assert_eq!(LifetimeCurve::EaseOut.at(0.5, 0.0), 1.0);
+        assert_eq!(LifetimeCurve::EaseOut.at(1.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn particles_keep_their_look_by_default() {
+        let settings = LifetimeSettings::default();
+        assert_eq!(settings.opacity, LifetimeCurve::Constant);
+        assert_eq!(settings.size, LifetimeCurve::Constant);
+    }
+}
@@ -1,8 +1,27 @@
+mod attractor;
+mod brush;
+mod camera;
+mod density;
+mod expression;
+mod field_brush;
+mod field_primitive;
 mod flow_particle;
 mod flow_vector;
+mod fluid;
+mod guide_curve;
+mod lifetime;
 mod model;
+mod particle_mesh;
 mod renderer;
+mod source_image;
 mod lut_manager;
+mod preset;
+mod recording;
+mod export;
+mod poster;
+mod post;
+mod sequence;
+mod headless;
 
 use egui_wgpu::wgpu;
 use egui_winit::egui;
@@ -14,7 +33,10 @@ use winit::{
     window::WindowBuilder,
 };
 use glam::Vec2;
-use crate::model::constants::DEFAULT_AUTO_SPAWN_PARTICLE_COUNT_LIMIT;
+use crate::model::constants::{
+    DEFAULT_AUTO_SPAWN_PARTICLE_COUNT_LIMIT, DEFAULT_EXPORT_DIRECTORY, DEFAULT_FIXED_CANVAS_SIZE,
+    DEFAULT_PRESET_DIRECTORY,
+};
 use crate::renderer::Renderer;
 use std::sync::Arc;
 struct App {
@@ -23,8 +45,9 @@ struct App {
     egui_state: egui_winit::State,
     egui_ctx: egui::Context,
     surface: wgpu::Surface<'static>,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
+    // Shared with the poster render's thread
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
     renderer: Renderer,
@@ -33,6 +56,24 @@ struct App {
     window: Arc<winit::window::Window>,
     should_clear_screen: bool,
     is_paused: bool,
+    preset_name: String,
+    preset_names: Vec<String>,
+    source_image_path: String,
+    // The field expression as last parsed for the UI
+    expression_cache: crate::expression::FieldExpressionCache,
+    // The brush path being typed in
+    brush_path: String,
+    canvas_size_input: (u32, u32),
+    cursor_window_xy: Vec2,
+    panning_camera: bool,
+    export_directory: String,
+    poster_settings: crate::poster::PosterSettings,
+    poster_render: Option<crate::poster::PosterRender>,
+    // Everything drawn since the layers were last cleared, for high resolution screenshots
+    recording: crate::recording::Recording,
+    screenshot_scale: u32,
+    sequence_settings: crate::sequence::SequenceSettings,
+    sequence_recorder: Option<crate::sequence::SequenceRecorder>,
 }
 
 impl App {
@@ -107,7 +148,8 @@ impl App {
         let model = Model::new(Vec2::new(size.width as f32, size.height as f32));
 
         // Initialize renderer
-        let renderer = Renderer::new(&device, surface_format, (size.width, size.height));
+        let renderer = Renderer::new(&device, &queue, surface_format, (size.width, size.height));
+        let recording = crate::recording::Recording::new(&model);
 
         Self {
             model,
@@ -115,8 +157,8 @@ impl App {
             egui_state,
             egui_ctx,
             surface,
-            device,
-            queue,
+            device: Arc::new(device),
+            queue: Arc::new(queue),
             config,
             size,
             renderer,
@@ -125,6 +167,21 @@ impl App {
             window,
             should_clear_screen: true,
             is_paused: false,
+            preset_name: String::new(),
+            preset_names: crate::preset::list_presets(std::path::Path::new(DEFAULT_PRESET_DIRECTORY)),
+            source_image_path: String::new(),
+            expression_cache: Default::default(),
+            brush_path: String::new(),
+            canvas_size_input: (DEFAULT_FIXED_CANVAS_SIZE.0, DEFAULT_FIXED_CANVAS_SIZE.1),
+            cursor_window_xy: Vec2::ZERO,
+            panning_camera: false,
+            export_directory: DEFAULT_EXPORT_DIRECTORY.to_string(),
+            poster_settings: crate::poster::PosterSettings::default(),
+            poster_render: None,
+            recording,
+            screenshot_scale: 1,
+            sequence_settings: crate::sequence::SequenceSettings::default(),
+            sequence_recorder: None,
         }
     }
 
@@ -134,18 +191,119 @@ impl App {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
-            
-            // Update model's window rect
-            self.model.window_rect = crate::model::SimpleRect::from_w_h(
-                new_size.width as f32, 
-                new_size.height as f32
-            );
-            
-            // Resize trail texture
-            self.renderer.resize(&self.device, (new_size.width, new_size.height));
-            
-            use crate::model::update::resized;
-            resized(&mut self.model);
+            self.apply_canvas_size();
+        }
+    }
+
+    // The canvas size in pixels, limited to what the GPU can hold in a texture
+    fn canvas_dimensions(&self) -> (u32, u32) {
+        let max_dimension = self.device.limits().max_texture_dimension_2d;
+        self.model.canvas_size.dimensions((self.size.width, self.size.height), max_dimension)
+    }
+
+    // Resize the model and the layer textures when the canvas size has changed
+    fn apply_canvas_size(&mut self) {
+        let (width, height) = self.canvas_dimensions();
+        let rect = &self.model.window_rect;
+        if rect.width() == width as f32 && rect.height() == height as f32 {
+            return;
+        }
+
+        info!("Resizing the canvas to {}x{}", width, height);
+        // Update model's window rect
+        self.model.window_rect = crate::model::SimpleRect::from_w_h(width as f32, height as f32);
+
+        // Resize trail texture
+        self.renderer.resize(&self.device, (width, height));
+
+        use crate::model::update::resized;
+        resized(&mut self.model);
+    }
+
+    fn render_poster(&mut self) {
+        let directory = std::path::Path::new(&self.export_directory);
+        let extension = self.poster_settings.format.extension();
+        match crate::export::timestamped_path(directory, "poster", extension) {
+            Ok(path) => {
+                self.poster_render = Some(crate::poster::PosterRender::start(
+                    self.device.clone(),
+                    self.queue.clone(),
+                    &self.model,
+                    self.poster_settings.clone(),
+                    path,
+                ));
+            }
+            Err(e) => log::error!("Failed to render poster: {}", e),
+        }
+    }
+
+    // Collect the poster render once its thread is done
+    fn finish_poster(&mut self) {
+        if !self.poster_render.as_ref().is_some_and(|render| render.is_finished()) {
+            return;
+        }
+        if let Some(Err(e)) = self.poster_render.take().map(|render| render.finish()) {
+            log::error!("Failed to render poster: {}", e);
+        }
+    }
+
+    fn save_screenshot(&mut self, kind: crate::export::ScreenshotKind) {
+        let directory = std::path::Path::new(&self.export_directory);
+        self.recording.capture_field(&self.model);
+        let result = crate::export::save_screenshot(
+            &self.device,
+            &self.queue,
+            &self.renderer,
+            &self.recording,
+            kind,
+            self.screenshot_scale,
+            directory,
+        );
+        match result {
+            Ok(paths) => {
+                for path in paths {
+                    info!("Saved screenshot to {}", path.display());
+                }
+            }
+            Err(e) => log::error!("Failed to save screenshot: {}", e),
+        }
+    }
+
+    fn start_sequence(&mut self) {
+        let directory = std::path::Path::new(&self.export_directory);
+        let size = self.canvas_dimensions();
+        match crate::sequence::SequenceRecorder::start(directory, self.sequence_settings.clone(), size) {
+            Ok(recorder) => self.sequence_recorder = Some(recorder),
+            Err(e) => log::error!("Failed to start recording: {}", e),
+        }
+    }
+
+    fn stop_sequence(&mut self) {
+        if let Some(recorder) = self.sequence_recorder.take() {
+            if let Err(e) = recorder.finish() {
+                log::error!("Failed to finish recording: {}", e);
+            }
+        }
+    }
+
+    // Capture this frame if the recorder is due one
+    fn capture_sequence_frame(&mut self) {
+        let Some(recorder) = &mut self.sequence_recorder else {
+            return;
+        };
+        if !recorder.wants_frame() {
+            return;
+        }
+
+        let result = self
+            .renderer
+            .read_composite(&self.device, &self.queue)
+            .and_then(|pixels| recorder.add_frame(pixels));
+        if let Err(e) = result {
+            log::error!("Stopped recording: {}", e);
+            self.stop_sequence();
+        } else if recorder.is_finished() {
+            self.stop_sequence();
         }
     }
 
@@ -165,6 +323,9 @@ impl App {
                 winit::keyboard::Key::Character(c) if c == "c" => {
                     self.should_clear_screen = true;
                 }
+                winit::keyboard::Key::Character(c) if c == "s" => {
+                    self.save_screenshot(crate::export::ScreenshotKind::Composite);
+                }
                 _ => {
                     use crate::model::update::key_pressed;
                     key_pressed(&mut self.model, event);
@@ -175,6 +336,11 @@ impl App {
 
     fn handle_mouse_input(&mut self, button: winit::event::MouseButton, state: winit::event::ElementState) {
         use crate::model::update::{mouse_pressed, mouse_released};
+        // The middle button drags the camera rather than acting on the simulation
+        if button == winit::event::MouseButton::Middle {
+            self.panning_camera = state == winit::event::ElementState::Pressed;
+            return;
+        }
         match state {
             winit::event::ElementState::Pressed => mouse_pressed(&mut self.model, button, state),
             winit::event::ElementState::Released => mouse_released(&mut self.model, button),
@@ -185,15 +351,46 @@ impl App {
         use crate::model::update::mouse_moved;
         
         // Convert screen coordinates to model coordinates 
-        let model_pos = Vec2::new(
+        let window_pos = Vec2::new(
             position.x as f32 - self.size.width as f32 / 2.0,
             self.size.height as f32 / 2.0 - position.y as f32,
         );
+        let window_size = Vec2::new(self.size.width as f32, self.size.height as f32);
+        if self.panning_camera {
+            let delta = window_pos - self.cursor_window_xy;
+            self.model.camera.pan_by(delta, canvas_size(&self.model), window_size);
+        }
+        self.cursor_window_xy = window_pos;
+
+        let model_pos = self.model.camera.window_to_canvas(window_pos, canvas_size(&self.model), window_size);
         mouse_moved(&mut self.model, model_pos);
     }
 
+    fn handle_mouse_wheel(&mut self, delta: winit::event::MouseScrollDelta) {
+        const ZOOM_PER_LINE: f32 = 1.1;
+        // Touchpads scroll by pixels, roughly this many to a wheel notch
+        const PIXELS_PER_LINE: f32 = 50.0;
+
+        let lines = match delta {
+            winit::event::MouseScrollDelta::LineDelta(_, y) => y,
+            winit::event::MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+        };
+        let window_size = Vec2::new(self.size.width as f32, self.size.height as f32);
+        self.model.camera.zoom_at(
+            ZOOM_PER_LINE.powf(lines),
+            self.cursor_window_xy,
+            canvas_size(&self.model),
+            window_size,
+        );
+    }
+
     fn render_ui(&mut self, window: &winit::window::Window) {
+        self.finish_poster();
         let raw_input = self.egui_state.take_egui_input(window);
+        let mut canvas_size_changed = false;
+        let mut poster_requested = false;
+        let mut screenshot_requested = None;
+        let mut sequence_toggled = false;
         let full_output = self.egui_ctx.run(raw_input, |ctx| {
             if self.model.show_ui {
                 egui::Window::new("Controls")
@@ -228,7 +425,8 @@ impl App {
                             .show_ui(ui, |ui| {
                                 let types = [
                                     "RightHandCurve", "BasicMulti", "Billow", "TerracedBillow",
-                                    "Fbm", "HybridMulti", "OpenSimplex", "Value", "Worley"
+                                    "Fbm", "HybridMulti", "OpenSimplex", "Value", "Worley", "Primitives",
+                                    "GuideCurves", "Image", "Expression", "Fluid"
                                 ];
                                 for noise_type in types {
                                     if ui.selectable_label(
@@ -245,6 +443,11 @@ impl App {
                                             "OpenSimplex" => crate::flow_vector::FlowVectorFieldBuilder::OpenSimplex,
                                             "Value" => crate::flow_vector::FlowVectorFieldBuilder::Value,
                                             "Worley" => crate::flow_vector::FlowVectorFieldBuilder::Worley,
+                                            "Primitives" => crate::flow_vector::FlowVectorFieldBuilder::Primitives,
+                                            "GuideCurves" => crate::flow_vector::FlowVectorFieldBuilder::GuideCurves,
+                                            "Image" => crate::flow_vector::FlowVectorFieldBuilder::Image,
+                                            "Expression" => crate::flow_vector::FlowVectorFieldBuilder::Expression,
+                                            "Fluid" => crate::flow_vector::FlowVectorFieldBuilder::Fluid,
                                             _ => current_noise_type.clone(),
                                         };
                                         self.model.new_flow_vector_fn = self.model.flow_vector_field_builder_type.as_fn();
@@ -253,6 +456,69 @@ impl App {
                                 }
                             });
 
+                        if let crate::flow_vector::FlowVectorFieldBuilder::Primitives = self.model.flow_vector_field_builder_type {
+                            ui.add_space(10.0);
+                            field_primitive_controls(ui, &mut self.model);
+                        }
+
+                        if let crate::flow_vector::FlowVectorFieldBuilder::GuideCurves = self.model.flow_vector_field_builder_type {
+                            ui.add_space(10.0);
+                            guide_curve_controls(ui, &mut self.model);
+                        }
+
+                        if let crate::flow_vector::FlowVectorFieldBuilder::Expression = self.model.flow_vector_field_builder_type {
+                            ui.add_space(10.0);
+                            field_expression_controls(ui, &mut self.model, &mut self.expression_cache);
+                        }
+
+                        if let crate::flow_vector::FlowVectorFieldBuilder::Fluid = self.model.flow_vector_field_builder_type {
+                            ui.add_space(10.0);
+                            fluid_controls(ui, &mut self.model);
+                        }
+
+                        ui.add_space(10.0);
+
+                        ui.heading("Source Image");
+                        ui.label("Enter a PNG or JPEG path, or drop an image onto the window");
+                        ui.horizontal(|ui| {
+                            ui.text_edit_singleline(&mut self.source_image_path);
+                            if ui.button("Load").clicked() {
+                                let path = std::path::PathBuf::from(self.source_image_path.trim());
+                                if let Err(e) = self.model.load_source_image(&path) {
+                                    log::error!("Failed to load source image {}: {}", path.display(), e);
+                                }
+                            }
+                        });
+                        if let Some(source_image) = &self.model.source_image {
+                            let (width, height) = source_image.dimensions();
+                            ui.label(format!("Loaded {} ({}x{})", source_image.path().display(), width, height));
+                        }
+                        let current_color_source = self.model.particle_color_source;
+                        egui::ComboBox::from_label("Particle Colors")
+                            .selected_text(format!("{}", current_color_source))
+                            .show_ui(ui, |ui| {
+                                for color_source in crate::model::enums::ParticleColorSource::ALL {
+                                    ui.selectable_value(&mut self.model.particle_color_source, color_source, format!("{}", color_source));
+                                }
+                            });
+                        if self.model.particle_color_source != crate::model::enums::ParticleColorSource::Lut {
+                            ui.add(egui::Slider::new(&mut self.model.particle_color_jitter, 0.0..=0.5).text("Color Jitter"));
+                            ui.checkbox(&mut self.model.quantize_image_colors, "Quantize Through LUT");
+                        }
+                        if let crate::flow_vector::FlowVectorFieldBuilder::Image = self.model.flow_vector_field_builder_type {
+                            let current_mode = self.model.image_field_mode;
+                            egui::ComboBox::from_label("Image Field Mode")
+                                .selected_text(format!("{}", current_mode))
+                                .show_ui(ui, |ui| {
+                                    for mode in crate::source_image::ImageFieldMode::ALL {
+                                        ui.selectable_value(&mut self.model.image_field_mode, mode, format!("{}", mode));
+                                    }
+                                });
+                            if self.model.image_field_mode != current_mode {
+                                self.model.regen_flow_vectors();
+                            }
+                        }
+
                         ui.add_space(10.0);
 
                         let mut noise_scale = self.model.noise_scale as f32;
@@ -261,12 +527,21 @@ impl App {
                             self.model.regen_flow_vectors();
                         }
 
+                        if ui.add(egui::Slider::new(&mut self.model.vector_spacing, 4.0..=60.0).text("Field Cell Size (px)")).changed() {
+                            self.model.resize_grid();
+                            self.model.regen_flow_vectors();
+                        }
+                        if ui.add(egui::Slider::new(&mut self.model.vector_display_spacing, 4.0..=120.0).text("Vector Display Spacing (px)")).changed() {
+                            self.model.redraw_background = RedrawBackground::Pending;
+                        }
+
                         ui.add_space(10.0);
 
                         let mut particle_lifetime = self.model.particle_lifetime;
                         if ui.add(egui::Slider::new(&mut particle_lifetime, 10.0..=200.0).text("Particle Lifetime")).changed() {
                             self.model.particle_lifetime = particle_lifetime;
                         }
+                        lifetime_controls(ui, &mut self.model);
 
                         ui.add_space(10.0);
 
@@ -292,6 +567,24 @@ impl App {
                             self.model.particle_step_length = particle_step_length;
                         }
 
+                        ui.add(
+                            egui::Slider::new(&mut self.model.trail_fade, 0.0..=0.25)
+                                .logarithmic(true)
+                                .text("Trail Fade"),
+                        )
+                        .on_hover_text("How much the trails fade toward the background each frame");
+
+                        ui.add_space(10.0);
+
+                        // Trails drawn in the other mode don't carry over
+                        if density_controls(ui, &mut self.model) {
+                            self.should_clear_screen = true;
+                        }
+
+                        ui.add_space(10.0);
+
+                        canvas_size_changed = canvas_controls(ui, &mut self.model, &mut self.canvas_size_input);
+
                         ui.add_space(10.0);
 
                         // Background Selection
@@ -384,34 +677,116 @@ impl App {
                         egui::ComboBox::from_label("Particle Shape")
                             .selected_text(format!("{}", current_shape))
                             .show_ui(ui, |ui| {
-                                let shapes = [
-                                    "Circle", "Square", "Triangle", "Star", "Diamond"
-                                ];
-                                for shape in shapes {
-                                    if ui.selectable_label(
-                                        format!("{}", current_shape) == shape,
-                                        shape,
-                                    ).clicked() {
-                                        self.model.particle_shape = match shape {
-                                            "Circle" => crate::model::enums::ParticleShape::Circle,
-                                            "Square" => crate::model::enums::ParticleShape::Square,
-                                            "Triangle" => crate::model::enums::ParticleShape::Triangle,
-                                            "Star" => crate::model::enums::ParticleShape::Star,
-                                            "Diamond" => crate::model::enums::ParticleShape::Diamond,
-                                            _ => current_shape,
-                                        };
-                                    }
+                                for shape in crate::model::enums::ParticleShape::ALL {
+                                    ui.selectable_value(&mut self.model.particle_shape, shape, format!("{}", shape));
+                                }
+                            });
+                        ui.add(egui::Slider::new(&mut self.model.particle_spin, -30.0..=30.0).text("Particle Spin (°/frame)"))
+                            .on_hover_text("Turns shapes away from the direction they're heading as they age");
+                        ui.add(egui::Slider::new(&mut self.model.particle_rotation_jitter, 0.0..=180.0).text("Rotation Jitter (°)"))
+                            .on_hover_text("Turns each new particle's shape a random amount up to this from its heading");
+                        if self.model.particle_shape == crate::model::enums::ParticleShape::Sprite {
+                            brush_controls(ui, &mut self.model, &mut self.brush_path);
+                        }
+
+                        let current_blend_mode = self.model.blend_mode;
+                        egui::ComboBox::from_label("Blend Mode")
+                            .selected_text(format!("{}", current_blend_mode))
+                            .show_ui(ui, |ui| {
+                                for blend_mode in crate::model::enums::BlendMode::ALL {
+                                    ui.selectable_value(&mut self.model.blend_mode, blend_mode, format!("{}", blend_mode));
                                 }
                             });
 
                         ui.add_space(10.0);
 
+                        post_controls(ui, &mut self.model);
+
+                        ui.add_space(10.0);
+
+                        ui.heading("Attractors");
+                        let current_tool = self.model.mouse_tool;
+                        egui::ComboBox::from_label("Mouse Tool")
+                            .selected_text(format!("{}", current_tool))
+                            .show_ui(ui, |ui| {
+                                for tool in crate::model::enums::MouseTool::ALL {
+                                    ui.selectable_value(&mut self.model.mouse_tool, tool, format!("{}", tool));
+                                }
+                            });
+                        ui.add(egui::Slider::new(&mut self.model.attractor_strength, 0.1..=10.0).text("Attractor Strength"));
+                        ui.add(egui::Slider::new(&mut self.model.attractor_falloff, 10.0..=1000.0).text("Attractor Falloff"));
+                        if ui.button(format!("Clear {} Attractors", self.model.attractors.len())).clicked() {
+                            self.model.attractors.clear();
+                        }
+
+                        ui.add_space(10.0);
+
+                        ui.heading("Field Brush");
+                        let current_brush = self.model.field_brush.kind;
+                        egui::ComboBox::from_label("Brush")
+                            .selected_text(format!("{}", current_brush))
+                            .show_ui(ui, |ui| {
+                                for kind in crate::field_brush::FieldBrushKind::ALL {
+                                    ui.selectable_value(&mut self.model.field_brush.kind, kind, format!("{}", kind));
+                                }
+                            });
+                        ui.add(egui::Slider::new(&mut self.model.field_brush.radius, 10.0..=400.0).text("Brush Radius"));
+                        ui.add(egui::Slider::new(&mut self.model.field_brush.strength, 0.01..=1.0).text("Brush Strength"));
+                        ui.add(egui::Slider::new(&mut self.model.field_brush.falloff, 0.1..=4.0).text("Brush Falloff"));
+                        let undo_steps = self.model.flow_vectors_undo_stack.len();
+                        if ui.add_enabled(undo_steps > 0, egui::Button::new(format!("Undo Field Edit ({})", undo_steps))).clicked() {
+                            self.model.undo_flow_vectors_edit();
+                        }
+
+                        ui.add_space(10.0);
+
                         if ui.button(format!("Kill {} Particles", self.model.flow_particles.len()))
                             .clicked()
                         {
                             self.model.flow_particles = Vec::with_capacity(DEFAULT_AUTO_SPAWN_PARTICLE_COUNT_LIMIT);
                         }
 
+                        ui.add_space(10.0);
+                        ui.separator();
+                        ui.heading("Presets");
+                        let preset_directory = std::path::Path::new(DEFAULT_PRESET_DIRECTORY);
+                        ui.horizontal(|ui| {
+                            ui.text_edit_singleline(&mut self.preset_name);
+                            if ui.add_enabled(!self.preset_name.trim().is_empty(), egui::Button::new("Save")).clicked() {
+                                match crate::preset::Preset::from_model(&self.model).save(preset_directory, self.preset_name.trim()) {
+                                    Ok(path) => info!("Saved preset to {}", path.display()),
+                                    Err(e) => log::error!("Failed to save preset: {}", e),
+                                }
+                                self.preset_names = crate::preset::list_presets(preset_directory);
+                            }
+                        });
+                        for preset_name in &self.preset_names {
+                            if ui.button(format!("Load \"{}\"", preset_name)).clicked() {
+                                match crate::preset::Preset::load(preset_directory, preset_name) {
+                                    Ok(preset) => {
+                                        preset.apply(&mut self.model);
+                                        self.preset_name = preset_name.clone();
+                                    }
+                                    Err(e) => log::error!("Failed to load preset \"{}\": {}", preset_name, e),
+                                }
+                            }
+                        }
+
+                        ui.add_space(10.0);
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.label("Export Directory");
+                            ui.text_edit_singleline(&mut self.export_directory);
+                        });
+                        screenshot_requested =
+                            screenshot_controls(ui, &mut self.screenshot_scale, self.recording.is_truncated());
+
+                        ui.add_space(10.0);
+                        sequence_toggled = sequence_controls(ui, &mut self.sequence_settings, self.sequence_recorder.as_ref());
+
+                        ui.add_space(10.0);
+                        poster_requested = poster_controls(ui, &mut self.poster_settings, self.poster_render.as_mut());
+
                         ui.add_space(10.0);
                         ui.separator();
                         ui.heading("Quick Actions");
@@ -426,6 +801,10 @@ impl App {
 
                         if ui.button("New Random Seed").clicked() {
                             self.model.noise_seed = rand::random();
+                            self.model.noise_offset = glam::DVec2::new(
+                                rand::random::<f64>() * 1000.0,
+                                rand::random::<f64>() * 1000.0,
+                            );
                             self.model.new_flow_vector_fn = self.model.flow_vector_field_builder_type.as_fn();
                             self.model.regen_flow_vectors();
                         }
@@ -439,13 +818,49 @@ impl App {
                         ui.heading("Controls");
                         ui.label("Left Click  - Spawn a new particle where you clicked");
                         ui.label("Right Click - \"Draw\" new particles where you click and drag");
+                        ui.label("Shift       - Hold to pull particles toward the cursor");
                         ui.label("Space       - Spawn new particle in a random location");
                         ui.label("C           - Clear screen and change background");
-
+                        ui.label("S           - Save a screenshot without the UI");
+                        ui.label("Ctrl+Z      - Undo the last field brush stroke");
+                        ui.label("Home        - Reset the camera");
                         ui.label("/           - Show/hide this UI");
                     });
+
+                if let crate::flow_vector::FlowVectorFieldBuilder::Primitives = self.model.flow_vector_field_builder_type {
+                    field_primitive_handles(ctx, &mut self.model, self.size);
+                }
+
+                guide_curve_overlay(ctx, &self.model, self.size);
+                attractor_markers(ctx, &self.model, self.size);
+
+                if self.model.mouse_tool == crate::model::enums::MouseTool::SculptField {
+                    let pixels_per_point = ctx.pixels_per_point();
+                    let center = model_to_screen(self.model.mouse_xy, &self.model, self.size, pixels_per_point);
+                    ctx.layer_painter(egui::LayerId::background()).circle_stroke(
+                        center,
+                        model_to_screen_length(self.model.field_brush.radius, &self.model, self.size, pixels_per_point),
+                        egui::Stroke::new(1.0, egui::Color32::WHITE),
+                    );
+                }
             }
         });
+        if canvas_size_changed {
+            self.apply_canvas_size();
+        }
+        if poster_requested {
+            self.render_poster();
+        }
+        if sequence_toggled {
+            if self.sequence_recorder.is_some() {
+                self.stop_sequence();
+            } else {
+                self.start_sequence();
+            }
+        }
+        if let Some(kind) = screenshot_requested {
+            self.save_screenshot(kind);
+        }
         self.egui_state.handle_platform_output(window, full_output.platform_output);
         self.egui_shapes = full_output.shapes;
         self.egui_textures_delta = full_output.textures_delta;
@@ -465,16 +880,24 @@ impl App {
 
         // Render particles with trail accumulation
         let should_clear = self.should_clear_screen || self.model.redraw_background == crate::model::enums::RedrawBackground::Pending;
+        self.renderer.update_brush(&self.device, &self.queue, &self.model.brush);
+        self.renderer.prepare(&self.device, &self.queue, &self.model);
         self.renderer.render(
             &self.queue,
             &mut encoder,
             &view,
-            self.model.background,
-            &self.model.flow_vectors,
-            &self.model.flow_particles,
             (self.size.width, self.size.height),
+            &self.model,
             should_clear,
         );
+
+        // Follow along with what was drawn
+        if should_clear {
+            self.recording = crate::recording::Recording::new(&self.model);
+        }
+        if !self.is_paused {
+            self.recording.record_frame(&self.model);
+        }
         
         // After first frame, don't clear to create trails
         if self.should_clear_screen {
@@ -535,14 +958,571 @@ impl App {
         
         self.queue.submit(std::iter::once(encoder.finish()));
         output.present();
+
+        // Frames follow the simulation rather than the clock, so paused frames aren't captured
+        if !self.is_paused {
+            self.capture_sequence_frame();
+        }
         Ok(())
     }
 }
 
+// Convert a position in model coordinates (centered, y up) to egui screen points
+fn canvas_size(model: &Model) -> Vec2 {
+    Vec2::new(model.window_rect.width(), model.window_rect.height())
+}
+
+fn model_to_screen(xy: Vec2, model: &Model, size: winit::dpi::PhysicalSize<u32>, pixels_per_point: f32) -> egui::Pos2 {
+    let window_size = Vec2::new(size.width as f32, size.height as f32);
+    let window_pos = model.camera.canvas_to_window(xy, canvas_size(model), window_size);
+    egui::pos2(
+        (window_pos.x + size.width as f32 / 2.0) / pixels_per_point,
+        (size.height as f32 / 2.0 - window_pos.y) / pixels_per_point,
+    )
+}
+
+// Convert a distance on the canvas to egui points
+fn model_to_screen_length(length: f32, model: &Model, size: winit::dpi::PhysicalSize<u32>, pixels_per_point: f32) -> f32 {
+    let window_size = Vec2::new(size.width as f32, size.height as f32);
+    length * model.camera.scale(canvas_size(model), window_size) / pixels_per_point
+}
+
+fn field_primitive_controls(ui: &mut egui::Ui, model: &mut Model) {
+    use crate::field_primitive::{FieldPrimitive, FieldPrimitiveKind};
+
+    ui.heading("Field Primitives");
+    ui.label("Drag the handles on the canvas to move primitives");
+
+    let mut changed = false;
+    let mut removed = None;
+    for (index, primitive) in model.field_primitives.iter_mut().enumerate() {
+        ui.push_id(index, |ui| {
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("kind")
+                    .selected_text(format!("{}", primitive.kind))
+                    .show_ui(ui, |ui| {
+                        for kind in FieldPrimitiveKind::ALL {
+                            if ui.selectable_value(&mut primitive.kind, kind, format!("{}", kind)).clicked() {
+                                changed = true;
+                            }
+                        }
+                    });
+                if ui.button("Remove").clicked() {
+                    removed = Some(index);
+                }
+            });
+            changed |= ui.add(egui::Slider::new(&mut primitive.strength, -5.0..=5.0).text("Strength")).changed();
+            if primitive.kind.has_radius() {
+                changed |= ui.add(egui::Slider::new(&mut primitive.radius, 10.0..=1000.0).text("Radius")).changed();
+            }
+            if primitive.kind.has_angle() {
+                changed |= ui.add(egui::Slider::new(&mut primitive.angle, -180.0..=180.0).text("Angle")).changed();
+            }
+        });
+        ui.add_space(5.0);
+    }
+
+    if let Some(index) = removed {
+        model.field_primitives.remove(index);
+        changed = true;
+    }
+
+    if ui.button("Add Primitive").clicked() {
+        model.field_primitives.push(FieldPrimitive::new(FieldPrimitiveKind::CounterclockwiseVortex, Vec2::ZERO));
+        changed = true;
+    }
+
+    if changed {
+        model.regen_flow_vectors();
+    }
+}
+
+// Returns true when density mode was switched on or off
+fn density_controls(ui: &mut egui::Ui, model: &mut Model) -> bool {
+    use crate::density::{DensityColor, ToneMap};
+
+    ui.heading("Density");
+    let settings = &mut model.density;
+    let toggled = ui
+        .checkbox(&mut settings.enabled, "Accumulate Density")
+        .on_hover_text("Add up where particles land and tone map the result, instead of painting them over each other")
+        .changed();
+    if settings.enabled {
+        egui::ComboBox::from_label("Tone Map")
+            .selected_text(format!("{}", settings.tone_map))
+            .show_ui(ui, |ui| {
+                for tone_map in ToneMap::ALL {
+                    ui.selectable_value(&mut settings.tone_map, tone_map, format!("{}", tone_map));
+                }
+            });
+        ui.add(egui::Slider::new(&mut settings.exposure, 0.001..=100.0).logarithmic(true).text("Exposure"));
+        ui.add(egui::Slider::new(&mut settings.gamma, 0.2..=5.0).text("Gamma"));
+        egui::ComboBox::from_label("Density Color")
+            .selected_text(format!("{}", settings.color))
+            .show_ui(ui, |ui| {
+                for color in DensityColor::ALL {
+                    ui.selectable_value(&mut settings.color, color, format!("{}", color));
+                }
+            });
+    }
+    toggled
+}
+
+fn brush_controls(ui: &mut egui::Ui, model: &mut Model, brush_path: &mut String) {
+    ui.label("Enter a PNG brush path. Opaque images paint where they're dark.");
+    ui.horizontal(|ui| {
+        ui.text_edit_singleline(brush_path);
+        if ui.button("Load").clicked() {
+            let path = std::path::PathBuf::from(brush_path.trim());
+            if let Err(e) = model.load_brush(&path) {
+                log::error!("Failed to load brush {}: {}", path.display(), e);
+            }
+        }
+        if ui.button("Soft Dot").clicked() {
+            model.brush = crate::brush::Brush::soft_dot();
+        }
+    });
+    let (width, height) = model.brush.dimensions();
+    match model.brush.path() {
+        Some(path) => ui.label(format!("Brush {} ({}x{})", path.display(), width, height)),
+        None => ui.label("Brush: Soft Dot"),
+    };
+}
+
+fn lifetime_controls(ui: &mut egui::Ui, model: &mut Model) {
+    use crate::lifetime::LifetimeCurve;
+
+    let settings = &mut model.lifetime;
+    let curve_combo = |ui: &mut egui::Ui, label: &str, curve: &mut LifetimeCurve| {
+        egui::ComboBox::from_label(label)
+            .selected_text(format!("{}", curve))
+            .show_ui(ui, |ui| {
+                for option in LifetimeCurve::ALL {
+                    ui.selectable_value(curve, option, format!("{}", option));
+                }
+            });
+    };
+    curve_combo(ui, "Opacity Over Life", &mut settings.opacity);
+    curve_combo(ui, "Size Over Life", &mut settings.size);
+    ui.add(egui::Slider::new(&mut settings.ramp, 0.0..=0.5).text("Ease Length"))
+        .on_hover_text("How much of each particle's life the eased curves spend ramping up or down");
+    ui.add(egui::Slider::new(&mut settings.color_shift, -1.0..=1.0).text("Color Shift Over Life"))
+        .on_hover_text("How far along the LUT particles colored from it move by the end of their life");
+}
+
+fn post_controls(ui: &mut egui::Ui, model: &mut Model) {
+    ui.heading("Post-Processing");
+    let settings = &mut model.post;
+    ui.add(egui::Slider::new(&mut settings.bloom_intensity, 0.0..=4.0).text("Bloom Intensity"))
+        .on_hover_text("How strongly bright areas glow. 0 turns bloom off.");
+    if settings.bloom_intensity > 0.0 {
+        ui.add(egui::Slider::new(&mut settings.bloom_threshold, 0.0..=1.0).text("Bloom Threshold"))
+            .on_hover_text("How bright a pixel has to be to glow");
+        ui.add(egui::Slider::new(&mut settings.bloom_radius, 1.0..=128.0).logarithmic(true).suffix(" px").text("Bloom Radius"));
+    }
+    ui.add(egui::Slider::new(&mut settings.blur_radius, 0.0..=64.0).suffix(" px").text("Blur Radius"));
+    ui.add(egui::Slider::new(&mut settings.grain, 0.0..=0.5).text("Film Grain"));
+    ui.add(egui::Slider::new(&mut settings.vignette, 0.0..=1.0).text("Vignette"));
+}
+
+// Returns true when the canvas size needs applying
+fn canvas_controls(ui: &mut egui::Ui, model: &mut Model, size_input: &mut (u32, u32)) -> bool {
+    use crate::model::enums::CanvasSize;
+
+    ui.heading("Canvas");
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        if ui.radio(model.canvas_size == CanvasSize::MatchWindow, "Match Window").clicked() {
+            model.canvas_size = CanvasSize::MatchWindow;
+            changed = true;
+        }
+        if ui.radio(matches!(model.canvas_size, CanvasSize::Fixed { .. }), "Fixed Size").clicked() {
+            model.canvas_size = CanvasSize::Fixed {
+                width: size_input.0,
+                height: size_input.1,
+            };
+            changed = true;
+        }
+    });
+
+    if let CanvasSize::Fixed { width, height } = model.canvas_size {
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut size_input.0).clamp_range(1..=16_384).suffix(" px"));
+            ui.label("×");
+            ui.add(egui::DragValue::new(&mut size_input.1).clamp_range(1..=16_384).suffix(" px"));
+            // Reallocating the layers is expensive so wait for an explicit apply
+            let edited = *size_input != (width, height);
+            if ui.add_enabled(edited, egui::Button::new("Apply")).clicked() {
+                model.canvas_size = CanvasSize::Fixed {
+                    width: size_input.0,
+                    height: size_input.1,
+                };
+                changed = true;
+            }
+        });
+
+        // The canvas is only resized when applied, so the model holds the size in use
+        let (actual_width, actual_height) = (model.window_rect.width() as u32, model.window_rect.height() as u32);
+        if (actual_width, actual_height) != (width, height) {
+            ui.colored_label(
+                egui::Color32::YELLOW,
+                format!("Downsized to {}×{} to fit the GPU's texture limit", actual_width, actual_height),
+            );
+        }
+    }
+
+    use crate::camera::Camera;
+
+    ui.add(egui::Slider::new(&mut model.camera.zoom, Camera::MIN_ZOOM..=Camera::MAX_ZOOM).logarithmic(true).text("Zoom"));
+    ui.horizontal(|ui| {
+        ui.label("Pan");
+        ui.add(egui::DragValue::new(&mut model.camera.pan.x).speed(4.0).prefix("x "));
+        ui.add(egui::DragValue::new(&mut model.camera.pan.y).speed(4.0).prefix("y "));
+        if ui.button("Reset Camera").clicked() {
+            model.camera = Camera::default();
+        }
+    });
+
+    changed
+}
+
+// Returns the kind of screenshot to save, if one was asked for. Screenshots are only redrawn at a
+// higher resolution while the recording holds everything on the canvas.
+fn screenshot_controls(ui: &mut egui::Ui, scale: &mut u32, truncated: bool) -> Option<crate::export::ScreenshotKind> {
+    use crate::export::ScreenshotKind;
+
+    if truncated {
+        *scale = 1;
+    }
+    let mut requested = None;
+    ui.horizontal(|ui| {
+        if ui.button("Save Screenshot").clicked() {
+            requested = Some(ScreenshotKind::Composite);
+        }
+        for option in [1, 2, 4] {
+            ui.add_enabled_ui(option == 1 || !truncated, |ui| {
+                ui.radio_value(scale, option, format!("{}×", option));
+            });
+        }
+    });
+    if truncated {
+        ui.colored_label(
+            egui::Color32::YELLOW,
+            "Too much has been drawn to redraw at 2× or 4×. Clear the screen to start over.",
+        );
+    }
+    ui.horizontal(|ui| {
+        if ui.button("Save Layers").clicked() {
+            requested = Some(ScreenshotKind::Layers);
+        }
+        if ui.button("Save Particles on Transparent").clicked() {
+            requested = Some(ScreenshotKind::Particles);
+        }
+    });
+
+    requested
+}
+
+// Returns true when recording should start or stop
+fn sequence_controls(
+    ui: &mut egui::Ui,
+    settings: &mut crate::sequence::SequenceSettings,
+    recorder: Option<&crate::sequence::SequenceRecorder>,
+) -> bool {
+    ui.heading("Recording");
+    ui.add_enabled_ui(recorder.is_none(), |ui| {
+        egui::ComboBox::from_label("Recording Format")
+            .selected_text(format!("{}", settings.format))
+            .show_ui(ui, |ui| {
+                for format in crate::sequence::SequenceFormat::ALL {
+                    ui.selectable_value(&mut settings.format, format, format!("{}", format));
+                }
+            });
+        ui.add(egui::DragValue::new(&mut settings.frame_step).clamp_range(1..=600).prefix("Capture every ").suffix(" frames"));
+        ui.add(egui::DragValue::new(&mut settings.frame_limit).clamp_range(1..=100_000).prefix("Frame limit "));
+    });
+
+    match recorder {
+        Some(recorder) => {
+            ui.label(format!("Recording frame {} of {}", recorder.frames_captured(), recorder.frame_limit()));
+            ui.button("Stop Recording").clicked()
+        }
+        None => ui.button("Start Recording").clicked(),
+    }
+}
+
+// Returns true when a poster should be rendered
+fn poster_controls(
+    ui: &mut egui::Ui,
+    settings: &mut crate::poster::PosterSettings,
+    render: Option<&mut crate::poster::PosterRender>,
+) -> bool {
+    ui.heading("Poster");
+    ui.add(egui::DragValue::new(&mut settings.width).clamp_range(64..=100_000).prefix("Width ").suffix(" px"));
+    ui.add(egui::DragValue::new(&mut settings.frames).clamp_range(1..=100_000).prefix("Frames "));
+    ui.add(egui::DragValue::new(&mut settings.seed).prefix("Seed "));
+    ui.add(egui::DragValue::new(&mut settings.tile_size).clamp_range(64..=8192).prefix("Tile Size ").suffix(" px"));
+    egui::ComboBox::from_label("Format")
+        .selected_text(format!("{}", settings.format))
+        .show_ui(ui, |ui| {
+            for format in crate::export::ImageFormat::ALL {
+                ui.selectable_value(&mut settings.format, format, format!("{}", format));
+            }
+        });
+
+    match render {
+        Some(render) => {
+            ui.label(format!("{}...", render.progress()));
+            false
+        }
+        None => ui.button("Render Poster").clicked(),
+    }
+}
+
+fn field_primitive_handles(ctx: &egui::Context, model: &mut Model, size: winit::dpi::PhysicalSize<u32>) {
+    const HANDLE_RADIUS: f32 = 8.0;
+
+    let pixels_per_point = ctx.pixels_per_point();
+    let centers: Vec<egui::Pos2> = model
+        .field_primitives
+        .iter()
+        .map(|primitive| model_to_screen(primitive.xy, model, size, pixels_per_point))
+        .collect();
+    let canvas_units_per_point = 1.0 / model_to_screen_length(1.0, model, size, pixels_per_point);
+    let mut moved = false;
+    for (index, (primitive, center)) in model.field_primitives.iter_mut().zip(centers).enumerate() {
+        egui::Area::new(egui::Id::new(("field_primitive_handle", index)))
+            .fixed_pos(center - egui::vec2(HANDLE_RADIUS, HANDLE_RADIUS))
+            .order(egui::Order::Middle)
+            .show(ctx, |ui| {
+                let (rect, response) = ui.allocate_exact_size(
+                    egui::vec2(HANDLE_RADIUS * 2.0, HANDLE_RADIUS * 2.0),
+                    egui::Sense::drag(),
+                );
+                let painter = ui.painter();
+                let stroke_color = if response.hovered() || response.dragged() {
+                    egui::Color32::YELLOW
+                } else {
+                    egui::Color32::WHITE
+                };
+                painter.circle(
+                    rect.center(),
+                    HANDLE_RADIUS,
+                    egui::Color32::from_black_alpha(160),
+                    egui::Stroke::new(2.0, stroke_color),
+                );
+
+                // Point oriented primitives along their angle (screen y points down)
+                if primitive.kind.has_angle() {
+                    let angle = primitive.angle.to_radians();
+                    let tip = rect.center() + egui::vec2(angle.cos(), -angle.sin()) * HANDLE_RADIUS * 2.5;
+                    painter.line_segment([rect.center(), tip], egui::Stroke::new(2.0, stroke_color));
+                }
+
+                painter.text(
+                    rect.right_center() + egui::vec2(4.0, 0.0),
+                    egui::Align2::LEFT_CENTER,
+                    format!("{}", primitive.kind),
+                    egui::FontId::default(),
+                    stroke_color,
+                );
+
+                if response.dragged() {
+                    let delta = response.drag_delta() * canvas_units_per_point;
+                    primitive.xy += Vec2::new(delta.x, -delta.y);
+                    moved = true;
+                }
+            });
+    }
+
+    if moved {
+        model.regen_flow_vectors();
+    }
+}
+
+fn guide_curve_controls(ui: &mut egui::Ui, model: &mut Model) {
+    use crate::flow_vector::FlowVectorFieldBuilder;
+    use crate::guide_curve::GuideWeighting;
+
+    ui.heading("Guide Curves");
+    ui.label("Use the \"Draw Guide Curves\" mouse tool to sketch strokes for the field to follow");
+
+    let settings = &mut model.guide_curve_settings;
+    let mut changed = false;
+    egui::ComboBox::from_label("Weighting")
+        .selected_text(format!("{}", settings.weighting))
+        .show_ui(ui, |ui| {
+            for weighting in GuideWeighting::ALL {
+                changed |= ui
+                    .selectable_value(&mut settings.weighting, weighting, format!("{}", weighting))
+                    .clicked();
+            }
+        });
+    match settings.weighting {
+        GuideWeighting::InverseDistance => {
+            changed |= ui.add(egui::Slider::new(&mut settings.power, 0.5..=6.0).text("Power")).changed();
+        }
+        GuideWeighting::RadialBasis => {
+            changed |= ui.add(egui::Slider::new(&mut settings.radius, 10.0..=1000.0).text("Radius")).changed();
+        }
+    }
+    changed |= ui.add(egui::Slider::new(&mut settings.noise_mix, 0.0..=1.0).text("Noise Mix")).changed();
+    egui::ComboBox::from_label("Mixed Noise")
+        .selected_text(format!("{:?}", settings.noise_builder))
+        .show_ui(ui, |ui| {
+            let noise_builders = [
+                FlowVectorFieldBuilder::BasicMulti,
+                FlowVectorFieldBuilder::Billow,
+                FlowVectorFieldBuilder::TerracedBillow,
+                FlowVectorFieldBuilder::Fbm,
+                FlowVectorFieldBuilder::HybridMulti,
+                FlowVectorFieldBuilder::OpenSimplex,
+                FlowVectorFieldBuilder::Value,
+                FlowVectorFieldBuilder::Worley,
+            ];
+            for builder in noise_builders {
+                let label = format!("{:?}", builder);
+                changed |= ui.selectable_value(&mut settings.noise_builder, builder, label).clicked();
+            }
+        });
+
+    ui.horizontal(|ui| {
+        if ui.button("Remove Last Guide").clicked() {
+            changed |= model.guide_curves.pop().is_some();
+        }
+        if ui.button(format!("Clear {} Guides", model.guide_curves.len())).clicked() {
+            model.guide_curves.clear();
+            changed = true;
+        }
+    });
+
+    if changed {
+        model.regen_flow_vectors();
+    }
+}
+
+fn field_expression_controls(ui: &mut egui::Ui, model: &mut Model, cache: &mut crate::expression::FieldExpressionCache) {
+    use crate::expression::ExpressionMode;
+
+    ui.heading("Expression");
+    ui.label("Variables: x, y (the shorter side spans -1 to 1), r, theta, t (seconds), seed");
+    ui.label("Functions: sin, cos, tan, atan2, sqrt, abs, exp, ln, pow, min, max, clamp, noise(x, y), ...");
+
+    let expression = &mut model.field_expression;
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        for mode in [ExpressionMode::Angle, ExpressionMode::Components] {
+            changed |= ui.selectable_value(&mut expression.mode, mode, format!("{}", mode)).clicked();
+        }
+    });
+    match expression.mode {
+        ExpressionMode::Angle => {
+            ui.label("Angle (radians)");
+            changed |= ui.add(egui::TextEdit::singleline(&mut expression.angle).code_editor()).changed();
+        }
+        ExpressionMode::Components => {
+            ui.label("u");
+            changed |= ui.add(egui::TextEdit::singleline(&mut expression.u).code_editor()).changed();
+            ui.label("v");
+            changed |= ui.add(egui::TextEdit::singleline(&mut expression.v).code_editor()).changed();
+        }
+    }
+
+    match cache.compile(expression) {
+        Ok(compiled) => {
+            if compiled.uses_time() {
+                ui.checkbox(&mut expression.animate, "Animate (rebuild the field every frame)");
+            }
+            if changed {
+                model.regen_flow_vectors();
+            }
+        }
+        Err(e) => {
+            ui.colored_label(egui::Color32::from_rgb(255, 100, 100), format!("{}", e));
+        }
+    }
+}
+
+fn fluid_controls(ui: &mut egui::Ui, model: &mut Model) {
+    ui.heading("Fluid");
+    ui.label("Stir the fluid with the Stir Fluid mouse tool");
+
+    let settings = &mut model.fluid_settings;
+    ui.add(egui::Slider::new(&mut settings.viscosity, 0.0..=0.01).logarithmic(true).text("Viscosity"));
+    ui.add(egui::Slider::new(&mut settings.diffusion, 0.0..=0.01).logarithmic(true).text("Dye Diffusion"));
+    ui.add(egui::Slider::new(&mut settings.force, 0.1..=10.0).text("Stir Force"));
+    ui.add(egui::Slider::new(&mut settings.radius, 5.0..=300.0).text("Stir Radius"));
+    ui.checkbox(&mut settings.inject_dye, "Inject Dye");
+    if settings.inject_dye {
+        ui.add(egui::Slider::new(&mut settings.dye_amount, 0.0..=1.0).text("Dye Amount"));
+    }
+    ui.add(egui::Slider::new(&mut settings.dye_persistence, 0.9..=1.0).text("Dye Persistence"));
+
+    if ui.button("Reset Fluid").clicked() {
+        model.fluid = Some(crate::fluid::FluidSolver::new(model.grid_width, model.grid_height));
+    }
+}
+
+fn guide_curve_overlay(ctx: &egui::Context, model: &Model, size: winit::dpi::PhysicalSize<u32>) {
+    let pixels_per_point = ctx.pixels_per_point();
+    let painter = ctx.layer_painter(egui::LayerId::background());
+    for guide_curve in model.guide_curves.iter().chain(model.guide_curve_in_progress.iter()) {
+        let points = guide_curve
+            .points
+            .iter()
+            .map(|xy| model_to_screen(*xy, model, size, pixels_per_point))
+            .collect();
+        painter.add(egui::Shape::line(points, egui::Stroke::new(2.0, egui::Color32::from_rgb(255, 200, 60))));
+    }
+}
+
+fn attractor_markers(ctx: &egui::Context, model: &Model, size: winit::dpi::PhysicalSize<u32>) {
+    use crate::attractor::AttractorKind;
+
+    let pixels_per_point = ctx.pixels_per_point();
+    let painter = ctx.layer_painter(egui::LayerId::background());
+    for attractor in model.attractors.iter().chain(model.cursor_attractor().iter()) {
+        let center = model_to_screen(attractor.xy, model, size, pixels_per_point);
+        let color = match attractor.kind {
+            AttractorKind::Attractor => egui::Color32::from_rgb(80, 220, 120),
+            AttractorKind::Repeller => egui::Color32::from_rgb(240, 80, 80),
+        };
+        painter.circle_stroke(center, 4.0, egui::Stroke::new(2.0, color));
+        // Outline the falloff distance so strength can be judged at a glance
+        painter.circle_stroke(
+            center,
+            model_to_screen_length(attractor.falloff, model, size, pixels_per_point),
+            egui::Stroke::new(1.0, color.linear_multiply(0.4)),
+        );
+    }
+}
+
 fn main() {
     let _ = dotenv::dotenv();
     env_logger::init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("--headless") {
+        use crate::headless::{run, HeadlessOptions, USAGE};
+
+        if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+            println!("{}", USAGE);
+            return;
+        }
+        let options = match HeadlessOptions::from_args(args.into_iter().skip(1)) {
+            Ok(options) => options,
+            Err(e) => {
+                eprintln!("{}\n\n{}", e, USAGE);
+                std::process::exit(2);
+            }
+        };
+        if let Err(e) = run(&options) {
+            log::error!("Headless recording failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     info!("Starting up the flow field...");
 
     let event_loop = EventLoop::new().unwrap();
@@ -562,37 +1542,49 @@ fn main() {
 
     event_loop.run(move |event, target| {
         match event {
-            Event::WindowEvent { ref event, .. } => {
-                if !app.input(&window, event) {
-                    match event {
-                        WindowEvent::CloseRequested => target.exit(),
-                        WindowEvent::Resized(physical_size) => {
-                            app.resize(*physical_size);
-                        }
-                        WindowEvent::ScaleFactorChanged { .. } => {
-                            app.resize(app.size);
-                        }
-                        WindowEvent::KeyboardInput { event, .. } => {
-                            app.handle_keyboard_input(event);
-                        }
-                        WindowEvent::MouseInput { button, state, .. } => {
-                            app.handle_mouse_input(*button, *state);
-                        }
-                        WindowEvent::CursorMoved { position, .. } => {
-                            app.handle_mouse_moved(*position);
+            Event::WindowEvent { ref event, .. } if !app.input(&window, event) => {
+                match event {
+                    WindowEvent::CloseRequested => target.exit(),
+                    WindowEvent::Resized(physical_size) => {
+                        app.resize(*physical_size);
+                    }
+                    WindowEvent::ScaleFactorChanged { .. } => {
+                        app.resize(app.size);
+                    }
+                    WindowEvent::KeyboardInput { event, .. } => {
+                        app.handle_keyboard_input(event);
+                    }
+                    WindowEvent::MouseInput { button, state, .. } => {
+                        app.handle_mouse_input(*button, *state);
+                    }
+                    WindowEvent::CursorMoved { position, .. } => {
+                        app.handle_mouse_moved(*position);
+                    }
+                    WindowEvent::MouseWheel { delta, .. } => {
+                        app.handle_mouse_wheel(*delta);
+                    }
+                    WindowEvent::DroppedFile(path) => {
+                        if let Err(e) = app.model.load_source_image(path) {
+                            log::error!("Failed to load source image {}: {}", path.display(), e);
+                        } else {
+                            app.source_image_path = path.display().to_string();
                         }
-                        WindowEvent::RedrawRequested => {
-                            app.update();
-                            app.render_ui(&window);
-                            match app.render() {
-                                Ok(_) => {}
-                                Err(wgpu::SurfaceError::Lost) => app.resize(app.size),
-                                Err(wgpu::SurfaceError::OutOfMemory) => target.exit(),
-                                Err(e) => eprintln!("{:?}", e),
-                            }
+                    }
+                    WindowEvent::ModifiersChanged(modifiers) => {
+                        use crate::model::update::modifiers_changed;
+                        modifiers_changed(&mut app.model, modifiers);
+                    }
+                    WindowEvent::RedrawRequested => {
+                        app.update();
+                        app.render_ui(&window);
+                        match app.render() {
+                            Ok(_) => {}
+                            Err(wgpu::SurfaceError::Lost) => app.resize(app.size),
+                            Err(wgpu::SurfaceError::OutOfMemory) => target.exit(),
+                            Err(e) => eprintln!("{:?}", e),
                         }
-                        _ => {}
                     }
+                    _ => {}
                 }
             }
             Event::AboutToWait => {
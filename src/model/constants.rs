@@ -6,4 +6,22 @@ pub const DEFAULT_PARTICLE_LIFETIME: f32 = 100.0;
 pub const DEFAULT_STEP_LENGTH: f32 = 3.0;
 pub const DEFAULT_VECTOR_MAGNITUDE: f32 = 12.0;
 pub const DEFAULT_VECTOR_SPACING: f32 = 10.0 + DEFAULT_VECTOR_MAGNITUDE;
+pub const DEFAULT_VECTOR_DISPLAY_SPACING: f32 = DEFAULT_VECTOR_SPACING;
+pub const DEFAULT_TRAIL_FADE: f32 = 0.0;
 pub const DEFAULT_OUTSIDE_BOUNDS_TIMEOUT: f32 = 60.0; // frames before killing particle outside bounds
+pub const DEFAULT_ATTRACTOR_STRENGTH: f32 = 1.5;
+pub const DEFAULT_ATTRACTOR_FALLOFF: f32 = 150.0;
+pub const MAX_FIELD_UNDO_STEPS: usize = 32;
+pub const GUIDE_CURVE_POINT_SPACING: f32 = 8.0;
+pub const DEFAULT_PRESET_DIRECTORY: &str = "presets";
+pub const DEFAULT_EXPORT_DIRECTORY: &str = "exports";
+pub const DEFAULT_TILE_SIZE: u32 = 2048;
+// About 90 MB of particle geometry kept for high resolution screenshots. The oldest frames are
+// dropped to make room for new ones.
+pub const MAX_RECORDED_PARTICLE_STAMPS: usize = 2_000_000;
+// Posters are simulated on request, so they may keep more, about 440 MB
+pub const MAX_SIMULATED_PARTICLE_STAMPS: usize = 10_000_000;
+// Frames kept at most, even when they hold few particles. As many as a poster simulates.
+pub const MAX_RECORDED_FRAMES: usize = 100_000;
+pub const SIMULATION_FRAMES_PER_SECOND: f64 = 60.0;
+pub const DEFAULT_FIXED_CANVAS_SIZE: (u32, u32) = (7200, 4800);
@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use std::fmt::{self, Display};
 
 // This enum only exists because I'm unsure of how to mutate the model during drawing or
@@ -20,10 +21,11 @@ impl RedrawBackground {
     }
 }
 
-#[derive(PartialEq, Debug, Clone, Copy)]
+#[derive(PartialEq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub enum Background {
     Black,
     White,
+    #[default]
     Vectors,
 }
 
@@ -41,19 +43,34 @@ impl Display for Background {
     }
 }
 
-impl Default for Background {
-    fn default() -> Self {
-        Self::Vectors
-    }
-}
-
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum ParticleShape {
     Circle,
     Square,
     Triangle,
     Star,
     Diamond,
+    Arrow,
+    /// A round head trailing a tail that fades out, longer the faster the particle moves
+    Comet,
+    /// A circle stretched along the heading by the particle's speed
+    Ellipse,
+    /// The model's brush texture, tinted by the particle color
+    Sprite,
+}
+
+impl ParticleShape {
+    pub const ALL: [Self; 9] = [
+        Self::Circle,
+        Self::Square,
+        Self::Triangle,
+        Self::Star,
+        Self::Diamond,
+        Self::Arrow,
+        Self::Comet,
+        Self::Ellipse,
+        Self::Sprite,
+    ];
 }
 
 impl std::fmt::Display for ParticleShape {
@@ -64,6 +81,139 @@ impl std::fmt::Display for ParticleShape {
             Self::Triangle => write!(f, "Triangle"),
             Self::Star => write!(f, "Star"),
             Self::Diamond => write!(f, "Diamond"),
+            Self::Arrow => write!(f, "Arrow"),
+            Self::Comet => write!(f, "Comet"),
+            Self::Ellipse => write!(f, "Ellipse"),
+            Self::Sprite => write!(f, "Brush Sprite"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MouseTool {
+    #[default]
+    SpawnParticles,
+    PlaceAttractor,
+    PlaceRepeller,
+    SculptField,
+    DrawGuides,
+    StirFluid,
+}
+
+impl MouseTool {
+    pub const ALL: [Self; 6] = [
+        Self::SpawnParticles,
+        Self::PlaceAttractor,
+        Self::PlaceRepeller,
+        Self::SculptField,
+        Self::DrawGuides,
+        Self::StirFluid,
+    ];
+}
+
+impl Display for MouseTool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SpawnParticles => write!(f, "Spawn Particles"),
+            Self::PlaceAttractor => write!(f, "Place Attractor"),
+            Self::PlaceRepeller => write!(f, "Place Repeller"),
+            Self::SculptField => write!(f, "Sculpt Field"),
+            Self::DrawGuides => write!(f, "Draw Guide Curves"),
+            Self::StirFluid => write!(f, "Stir Fluid"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum ParticleColorSource {
+    /// A random entry from the active LUT
+    #[default]
+    Lut,
+    /// The source image's color where the particle spawned
+    ImageAtSpawn,
+    /// The source image's color wherever the particle currently is
+    ImageContinuous,
+}
+
+impl ParticleColorSource {
+    pub const ALL: [Self; 3] = [Self::Lut, Self::ImageAtSpawn, Self::ImageContinuous];
+}
+
+impl Display for ParticleColorSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lut => write!(f, "Color LUT"),
+            Self::ImageAtSpawn => write!(f, "Image (at spawn)"),
+            Self::ImageContinuous => write!(f, "Image (continuous)"),
+        }
+    }
+}
+
+/// The simulation's canvas either follows the window or has a fixed size in pixels, for print
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CanvasSize {
+    #[default]
+    MatchWindow,
+    Fixed {
+        width: u32,
+        height: u32,
+    },
+}
+
+impl CanvasSize {
+    /// The size in pixels for a window of `window_size`. Sizes too large for a texture are scaled
+    /// down evenly until both sides fit within `max_dimension`, keeping the aspect ratio.
+    pub fn dimensions(&self, window_size: (u32, u32), max_dimension: u32) -> (u32, u32) {
+        let (width, height) = match *self {
+            Self::MatchWindow => window_size,
+            Self::Fixed { width, height } => (width, height),
+        };
+        let (width, height) = (width.max(1), height.max(1));
+        let longest = width.max(height);
+        if longest <= max_dimension {
+            return (width, height);
+        }
+        let scale = |side: u32| ((side as u64 * max_dimension as u64 / longest as u64) as u32).max(1);
+        (scale(width), scale(height))
+    }
+}
+
+/// How particles combine with each other and with the layers underneath them
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    /// Light adds up, for glowing trails on black
+    Additive,
+    Screen,
+    /// Darkens what's underneath, like ink on paper
+    Multiply,
+    /// Keeps the brighter of the particles and what's underneath
+    Lighten,
+    /// Takes the particles' light away from what's underneath
+    Subtractive,
+}
+
+impl BlendMode {
+    pub const ALL: [Self; 6] = [
+        Self::Normal,
+        Self::Additive,
+        Self::Screen,
+        Self::Multiply,
+        Self::Lighten,
+        Self::Subtractive,
+    ];
+}
+
+impl Display for BlendMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Normal => write!(f, "Normal"),
+            Self::Additive => write!(f, "Additive"),
+            Self::Screen => write!(f, "Screen"),
+            Self::Multiply => write!(f, "Multiply"),
+            Self::Lighten => write!(f, "Lighten (Max)"),
+            Self::Subtractive => write!(f, "Subtractive"),
         }
     }
 }
@@ -3,18 +3,31 @@ pub mod enums;
 pub mod update;
 
 use crate::{
+    attractor::{Attractor, AttractorKind},
+    brush::Brush,
+    camera::Camera,
+    density::DensitySettings,
+    expression::FieldExpression,
+    field_brush::FieldBrush,
+    field_primitive::{default_field_primitives, FieldPrimitive},
+    fluid::{FluidSettings, FluidSolver},
     flow_particle::{FlowParticle, FlowParticleBuilderFn, FlowParticleBuilderFnOptions},
     flow_vector::{FlowVector, FlowVectorFieldBuilder, FlowVectorFieldBuilderFn},
-    lut_manager::LutManager,
+    guide_curve::{GuideCurve, GuideCurveSettings},
+    lifetime::LifetimeSettings,
+    lut_manager::{LutData, LutManager},
+    post::PostSettings,
+    source_image::{ImageFieldMode, SourceImage},
 };
 use constants::{
-    DEFAULT_AGING_RATE, DEFAULT_AUTO_SPAWN_PARTICLE_COUNT_LIMIT, DEFAULT_MAX_WEIGHT, DEFAULT_MIN_WEIGHT, DEFAULT_OUTSIDE_BOUNDS_TIMEOUT, 
-    DEFAULT_PARTICLE_LIFETIME, DEFAULT_STEP_LENGTH, DEFAULT_VECTOR_MAGNITUDE,
-    DEFAULT_VECTOR_SPACING,
+    DEFAULT_AGING_RATE, DEFAULT_ATTRACTOR_FALLOFF, DEFAULT_ATTRACTOR_STRENGTH, DEFAULT_AUTO_SPAWN_PARTICLE_COUNT_LIMIT, DEFAULT_MAX_WEIGHT, DEFAULT_MIN_WEIGHT, DEFAULT_OUTSIDE_BOUNDS_TIMEOUT, 
+    DEFAULT_PARTICLE_LIFETIME, DEFAULT_STEP_LENGTH, DEFAULT_TRAIL_FADE, DEFAULT_VECTOR_MAGNITUDE,
+    DEFAULT_VECTOR_DISPLAY_SPACING, DEFAULT_VECTOR_SPACING, MAX_FIELD_UNDO_STEPS,
 };
-use enums::{Background, RedrawBackground, ParticleShape};
-use glam::Vec2;
-use rand::Rng;
+use enums::{Background, BlendMode, CanvasSize, MouseTool, ParticleColorSource, RedrawBackground, ParticleShape};
+use glam::{DVec2, Vec2, Vec3};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use winit::keyboard::ModifiersState;
 pub use update::update;
 
 #[derive(Clone, Copy, Debug)]
@@ -35,56 +48,119 @@ impl SimpleRect {
         }
     }
 
+    pub fn width(&self) -> f32 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> f32 {
+        self.top - self.bottom
+    }
+
     pub fn contains(&self, point: Vec2) -> bool {
         point.x >= self.left && point.x <= self.right && point.y >= self.bottom && point.y <= self.top
     }
 }
 
-type NearestAngleFn = Box<dyn Fn(Vec2, &Model) -> f32>;
+type NearestAngleFn = Box<dyn Fn(Vec2, &Model) -> f32 + Send>;
 
 pub struct Model {
+    pub attractor_falloff: f32,
+    pub attractor_strength: f32,
+    pub attractors: Vec<Attractor>,
     pub automatically_spawn_particles: bool,
     pub background: Background,
+    pub blend_mode: BlendMode,
+    /// What sprite particles are stamped with
+    pub brush: Brush,
+    pub camera: Camera,
+    pub canvas_size: CanvasSize,
     pub current_lut: String,
+    pub density: DensitySettings,
     pub draw_particle_mode: bool,
+    pub field_expression: FieldExpression,
+    pub field_brush: FieldBrush,
+    pub field_primitives: Vec<FieldPrimitive>,
+    /// Counts the changes to `flow_vectors`, so whatever is drawn from them knows when it's stale
+    pub field_revision: u64,
     pub flow_particles: Vec<FlowParticle>,
+    pub fluid: Option<FluidSolver>,
+    pub fluid_settings: FluidSettings,
+    pub stirring_fluid: bool,
     pub flow_vector_field_builder_type: FlowVectorFieldBuilder,
     pub flow_vectors: Vec<FlowVector>,
+    pub flow_vectors_undo_stack: Vec<Vec<FlowVector>>,
+    pub frame_count: u64,
     pub grid_height: usize,
+    pub guide_curve_in_progress: Option<GuideCurve>,
+    pub guide_curve_settings: GuideCurveSettings,
+    pub guide_curves: Vec<GuideCurve>,
     pub grid_width: usize,
+    pub image_field_mode: ImageFieldMode,
+    /// How particles fade, resize and shift color as they age
+    pub lifetime: LifetimeSettings,
     pub lut_manager: LutManager,
+    pub modifiers: ModifiersState,
+    pub mouse_tool: MouseTool,
     pub mouse_xy: Vec2,
     pub nearest_angle_fn: NearestAngleFn,
     pub new_flow_particle_fn: FlowParticleBuilderFn,
     pub new_flow_vector_fn: FlowVectorFieldBuilderFn,
     pub noise_scale: f64,
+    /// Where the field's window onto the noise starts. It stays put across resizes so the part
+    /// of the field that was already visible doesn't change.
+    pub noise_offset: DVec2,
     pub noise_seed: u32,
     pub particle_auto_spawn_limit: usize,
     pub particle_cleanup_requested: bool,
+    pub particle_color_jitter: f32,
+    pub particle_color_source: ParticleColorSource,
     pub particle_lifetime: f32,
     pub particle_max_weight: f32,
     pub particle_min_weight: f32,
     pub particle_step_length: f32,
     pub outside_bounds_timeout: f32,
+    pub post: PostSettings,
+    pub quantize_image_colors: bool,
     pub redraw_background: RedrawBackground,
-    pub rng: rand::rngs::ThreadRng,
+    /// Seedable so a simulation can be replayed exactly, e.g. for offline renders
+    pub rng: StdRng,
+    pub sculpting_field: bool,
     pub show_ui: bool,
+    pub source_image: Option<SourceImage>,
+    /// How much of the trails fades toward the background each frame, from 0 (never) to 1
+    pub trail_fade: f32,
+    pub vector_display_spacing: f32,
     pub vector_magnitude: f32,
+    /// Size in pixels of a field cell. Particles follow the field at this resolution no matter
+    /// how sparsely the vectors are drawn.
     pub vector_spacing: f32,
     pub window_rect: SimpleRect,
     pub particle_shape: ParticleShape,
+    /// Degrees particle shapes turn each frame, on top of following their heading
+    pub particle_spin: f32,
+    /// The most degrees each particle's shape is randomly turned from its heading
+    pub particle_rotation_jitter: f32,
 }
 
 impl Model {
     pub fn new(window_size: Vec2) -> Self {
+        Self::with_rng(window_size, StdRng::from_entropy())
+    }
+
+    /// A model whose noise field and random numbers all follow from `seed`, so the same seed
+    /// always runs the same way
+    pub fn new_seeded(window_size: Vec2, seed: u64) -> Self {
+        Self::with_rng(window_size, StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(window_size: Vec2, mut rng: StdRng) -> Self {
         let window_rect = SimpleRect::from_w_h(window_size.x, window_size.y);
-        let mut rng = rand::thread_rng();
         let noise_seed = rng.gen_range(0..100_000);
         
         // Calculate grid size based on window dimensions
         let vector_spacing = DEFAULT_VECTOR_SPACING;
-        let grid_width = (window_size.x / vector_spacing).ceil() as usize;
-        let grid_height = (window_size.y / vector_spacing).ceil() as usize;
+        let (grid_width, grid_height) = grid_size(&window_rect, vector_spacing);
+        let noise_offset = DVec2::new(rng.gen_range(0.0..1000.0), rng.gen_range(0.0..1000.0));
         
         // Adjust noise scale based on window size
         let noise_scale = 0.05_f64 * (1920.0_f64 / window_size.x as f64).max(1.0);
@@ -106,36 +182,71 @@ impl Model {
         let current_lut = available_luts.first().unwrap().clone();
         
         let mut model = Self {
+            attractor_falloff: DEFAULT_ATTRACTOR_FALLOFF,
+            attractor_strength: DEFAULT_ATTRACTOR_STRENGTH,
+            attractors: Vec::new(),
             automatically_spawn_particles: true,
             background: Background::Vectors,
+            blend_mode: BlendMode::default(),
+            brush: Brush::default(),
+            camera: Camera::default(),
+            canvas_size: CanvasSize::default(),
             current_lut,
+            density: DensitySettings::default(),
             draw_particle_mode: false,
+            field_expression: FieldExpression::default(),
+            field_brush: FieldBrush::default(),
+            field_primitives: default_field_primitives(),
+            field_revision: 0,
             flow_particles: Vec::with_capacity(DEFAULT_AUTO_SPAWN_PARTICLE_COUNT_LIMIT),
+            fluid: None,
+            fluid_settings: FluidSettings::default(),
+            stirring_fluid: false,
             flow_vectors: Vec::new(),
+            flow_vectors_undo_stack: Vec::new(),
+            frame_count: 0,
             grid_height,
+            guide_curve_in_progress: None,
+            guide_curve_settings: GuideCurveSettings::default(),
+            guide_curves: Vec::new(),
             grid_width,
+            image_field_mode: ImageFieldMode::default(),
+            lifetime: LifetimeSettings::default(),
             lut_manager,
+            modifiers: ModifiersState::empty(),
+            mouse_tool: MouseTool::default(),
             mouse_xy: Vec2::new(0.0, 0.0),
             nearest_angle_fn: Box::new(nearest_angle_in_grid),
             new_flow_particle_fn,
             flow_vector_field_builder_type: FlowVectorFieldBuilder::Billow,
             new_flow_vector_fn: FlowVectorFieldBuilder::Billow.as_fn(),
+            noise_offset,
             noise_scale,
             noise_seed,
             particle_auto_spawn_limit: DEFAULT_AUTO_SPAWN_PARTICLE_COUNT_LIMIT,
             particle_cleanup_requested: false,
+            particle_color_jitter: 0.0,
+            particle_color_source: ParticleColorSource::default(),
             particle_lifetime: DEFAULT_PARTICLE_LIFETIME,
             particle_max_weight: DEFAULT_MAX_WEIGHT,
             particle_min_weight: DEFAULT_MIN_WEIGHT,
             particle_step_length: DEFAULT_STEP_LENGTH,
+            post: PostSettings::default(),
             outside_bounds_timeout: DEFAULT_OUTSIDE_BOUNDS_TIMEOUT,
+            quantize_image_colors: false,
             redraw_background: RedrawBackground::Pending,
             rng,
+            sculpting_field: false,
             show_ui: true,
+            source_image: None,
+            trail_fade: DEFAULT_TRAIL_FADE,
+            vector_display_spacing: DEFAULT_VECTOR_DISPLAY_SPACING,
             vector_magnitude: DEFAULT_VECTOR_MAGNITUDE,
             vector_spacing,
             window_rect,
             particle_shape: ParticleShape::Circle,
+            particle_spin: 0.0,
+            particle_rotation_jitter: 0.0,
         };
         model.regen_flow_vectors();
         
@@ -143,25 +254,29 @@ impl Model {
     }
 
     pub fn spawn_new_particle(&mut self, xy: Vec2) {
-        let age = map_range(rand::random::<f32>(), 0.0, 1.0, 0.0, self.particle_lifetime);
+        let age = map_range(self.rng.gen::<f32>(), 0.0, 1.0, 0.0, self.particle_lifetime);
         
         // Get color from LUT
         let lut_data = self.lut_manager.load_lut(&self.current_lut).unwrap();
-        let color_index = (rand::random::<f32>() * 255.0) as usize;
-        let color = egui::Color32::from_rgb(
-            lut_data.red[color_index],
-            lut_data.green[color_index],
-            lut_data.blue[color_index],
-        );
+        let color_jitter = (Vec3::new(self.rng.gen(), self.rng.gen(), self.rng.gen()) * 2.0 - Vec3::ONE)
+            * self.particle_color_jitter;
+        let lut_position = match (self.particle_color_source, &self.source_image) {
+            (ParticleColorSource::Lut, _) | (_, None) => Some(self.rng.gen::<f32>()),
+            _ => None,
+        };
+        let color = match lut_position {
+            Some(position) => lut_color(&lut_data, position),
+            None => self.source_image_color(xy, color_jitter, &lut_data),
+        };
         
         let weight = map_range(
-            rand::random::<f32>(),
+            self.rng.gen::<f32>(),
             0.0,
             1.0,
             self.particle_min_weight,
             self.particle_max_weight,
         );
-        let new_particle = (self.new_flow_particle_fn)(FlowParticleBuilderFnOptions {
+        let mut new_particle = (self.new_flow_particle_fn)(FlowParticleBuilderFnOptions {
             age,
             aging_rate: DEFAULT_AGING_RATE,
             color,
@@ -170,19 +285,57 @@ impl Model {
             xy,
             shape: self.particle_shape,
         });
+        new_particle.color_jitter = color_jitter;
+        new_particle.lut_position = lut_position;
+        new_particle.rotation_jitter = self.rng.gen_range(-1.0..=1.0) * self.particle_rotation_jitter.to_radians();
         self.flow_particles.push(new_particle);
     }
 
-    pub fn get_random_xy(&self) -> Vec2 {
+    /// Sample the source image for a particle color, optionally snapping it to the nearest LUT entry
+    pub fn source_image_color(&self, xy: Vec2, jitter: Vec3, lut_data: &LutData) -> egui::Color32 {
+        let Some(source_image) = &self.source_image else {
+            return egui::Color32::BLACK;
+        };
+
+        let color = ((source_image.sample(xy, &self.window_rect) + jitter).clamp(Vec3::ZERO, Vec3::ONE) * 255.0)
+            .round();
+        let [r, g, b] = [color.x as u8, color.y as u8, color.z as u8];
+        if !self.quantize_image_colors {
+            return egui::Color32::from_rgb(r, g, b);
+        }
+
+        let distance = |index: usize| {
+            let dr = lut_data.red[index] as i32 - r as i32;
+            let dg = lut_data.green[index] as i32 - g as i32;
+            let db = lut_data.blue[index] as i32 - b as i32;
+            dr * dr + dg * dg + db * db
+        };
+        let nearest = (0..lut_data.red.len()).min_by_key(|index| distance(*index)).unwrap_or(0);
+        egui::Color32::from_rgb(lut_data.red[nearest], lut_data.green[nearest], lut_data.blue[nearest])
+    }
+
+    // While a modifier is held the cursor pulls particles like a placed attractor
+    pub fn cursor_attractor(&self) -> Option<Attractor> {
+        self.modifiers.shift_key().then(|| {
+            Attractor::new(
+                AttractorKind::Attractor,
+                self.mouse_xy,
+                self.attractor_strength,
+                self.attractor_falloff,
+            )
+        })
+    }
+
+    pub fn get_random_xy(&mut self) -> Vec2 {
         let x = map_range(
-            rand::random::<f32>(),
+            self.rng.gen::<f32>(),
             0.0,
             1.0,
             self.window_rect.left,
             self.window_rect.right,
         );
         let y = map_range(
-            rand::random::<f32>(),
+            self.rng.gen::<f32>(),
             0.0,
             1.0,
             self.window_rect.bottom,
@@ -191,32 +344,130 @@ impl Model {
         Vec2::new(x, y)
     }
 
+    /// The center of the bottom left cell
     pub fn get_origin(&self) -> (f32, f32) {
-        let origin_x = self.window_rect.left + self.vector_spacing;
-        let origin_y = self.window_rect.bottom + self.vector_spacing;
+        let origin_x = self.window_rect.left + self.vector_spacing / 2.0;
+        let origin_y = self.window_rect.bottom + self.vector_spacing / 2.0;
         (origin_x, origin_y)
     }
 
+    /// The (row, column) of the cell containing `xy`, or `None` if it's outside the grid
+    pub fn cell_at(&self, xy: Vec2) -> Option<(usize, usize)> {
+        let row_index = ((xy.x - self.window_rect.left) / self.vector_spacing).floor();
+        let column_index = ((xy.y - self.window_rect.bottom) / self.vector_spacing).floor();
+        if row_index < 0.0 || column_index < 0.0 {
+            return None;
+        }
+
+        let (row_index, column_index) = (row_index as usize, column_index as usize);
+        (row_index < self.grid_width && column_index < self.grid_height).then_some((row_index, column_index))
+    }
+
+    /// Size the grid to cover the window at the current field resolution. The flow vectors need
+    /// regenerating afterwards.
+    pub fn resize_grid(&mut self) {
+        (self.grid_width, self.grid_height) = grid_size(&self.window_rect, self.vector_spacing);
+    }
+
+    /// How many field cells apart the drawn vectors are, in each direction
+    pub fn vector_display_stride(&self) -> usize {
+        (self.vector_display_spacing / self.vector_spacing).round().max(1.0) as usize
+    }
+
     pub fn regen_flow_vectors(&mut self) {
         self.flow_vectors = (self.new_flow_vector_fn)(self);
+        self.field_revision += 1;
+        // Sculpted edits belong to the old field
+        self.flow_vectors_undo_stack.clear();
         self.background = Background::Vectors;
         self.redraw_background = RedrawBackground::Pending;
     }
+
+    pub fn load_brush(&mut self, path: &std::path::Path) -> std::io::Result<()> {
+        self.brush = Brush::load(path)?;
+        Ok(())
+    }
+
+    pub fn load_source_image(&mut self, path: &std::path::Path) -> std::io::Result<()> {
+        self.source_image = Some(SourceImage::load(path)?);
+        if self.flow_vector_field_builder_type == FlowVectorFieldBuilder::Image {
+            self.regen_flow_vectors();
+        }
+        Ok(())
+    }
+
+    /// Advance the fluid solver and copy its velocity into the field. The solver is (re)started
+    /// from the current field whenever the grid doesn't match it.
+    pub fn step_fluid(&mut self) {
+        let dimensions = (self.grid_width, self.grid_height);
+        let fluid = match &mut self.fluid {
+            Some(fluid) if fluid.dimensions() == dimensions => fluid,
+            fluid => fluid.insert(FluidSolver::from_flow_vectors(&self.flow_vectors, dimensions.0, dimensions.1)),
+        };
+        fluid.step(&self.fluid_settings);
+        // The field changes every frame, so skip the background reset `regen_flow_vectors` does
+        self.flow_vectors = (self.new_flow_vector_fn)(self);
+        self.field_revision += 1;
+    }
+
+    /// Push the fluid under the mouse along the direction it moved
+    pub fn stir_fluid(&mut self, from: Vec2, to: Vec2) {
+        let (origin_x, origin_y) = self.get_origin();
+        let Some(fluid) = &mut self.fluid else {
+            return;
+        };
+
+        let spacing = self.vector_spacing;
+        let center = Vec2::new((to.x - origin_x) / spacing, (to.y - origin_y) / spacing);
+        let velocity = (to - from) / spacing * self.fluid_settings.force;
+        let dye = if self.fluid_settings.inject_dye {
+            self.fluid_settings.dye_amount
+        } else {
+            0.0
+        };
+        fluid.splat(center, self.fluid_settings.radius / spacing, velocity, dye);
+    }
+
+    // Snapshot the field before an edit so it can be undone
+    pub fn push_flow_vectors_undo(&mut self) {
+        if self.flow_vectors_undo_stack.len() == MAX_FIELD_UNDO_STEPS {
+            self.flow_vectors_undo_stack.remove(0);
+        }
+        self.flow_vectors_undo_stack.push(self.flow_vectors.clone());
+    }
+
+    pub fn undo_flow_vectors_edit(&mut self) {
+        if let Some(flow_vectors) = self.flow_vectors_undo_stack.pop() {
+            self.flow_vectors = flow_vectors;
+            self.field_revision += 1;
+        }
+    }
+}
+
+// The LUT entry `position` of the way along it
+fn lut_color(lut_data: &LutData, position: f32) -> egui::Color32 {
+    let index = (position.clamp(0.0, 1.0) * 255.0) as usize;
+    egui::Color32::from_rgb(lut_data.red[index], lut_data.green[index], lut_data.blue[index])
 }
 
 fn map_range(value: f32, in_min: f32, in_max: f32, out_min: f32, out_max: f32) -> f32 {
     (value - in_min) / (in_max - in_min) * (out_max - out_min) + out_min
 }
 
+// Enough cells to cover `rect`, edges included
+fn grid_size(rect: &SimpleRect, vector_spacing: f32) -> (usize, usize) {
+    let grid_width = (rect.width() / vector_spacing).floor() as usize + 1;
+    let grid_height = (rect.height() / vector_spacing).floor() as usize + 1;
+    (grid_width, grid_height)
+}
+
 pub fn nearest_angle_in_grid(xy: Vec2, model: &Model) -> f32 {
-    let origin_x = model.window_rect.left + model.vector_spacing;
-    let origin_y = model.window_rect.bottom + model.vector_spacing;
-    let row_index = ((xy.x - origin_x) / model.vector_spacing).round() as i32;
-    let column_index = ((xy.y - origin_y) / model.vector_spacing).round() as i32;
-    
-    // Clamp to valid grid bounds
-    let row_index = row_index.max(0).min(model.grid_width as i32 - 1) as usize;
-    let column_index = column_index.max(0).min(model.grid_height as i32 - 1) as usize;
+    // Particles outside the window follow the nearest edge cell
+    let rect = &model.window_rect;
+    let clamped = Vec2::new(xy.x.clamp(rect.left, rect.right), xy.y.clamp(rect.bottom, rect.top));
+    let Some((row_index, column_index)) = model.cell_at(clamped) else {
+        return 0.0;
+    };
     
     let fv_index = row_index + column_index * model.grid_width;
     
@@ -228,3 +479,59 @@ pub fn nearest_angle_in_grid(xy: Vec2, model: &Model) -> f32 {
         0.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_point_in_window_maps_to_a_cell() {
+        let mut model = Model::new(Vec2::new(333.0, 250.0));
+        for (width, height, vector_spacing) in [(333.0, 250.0, 22.0), (1024.0, 768.0, 16.0), (997.0, 641.0, 7.5)] {
+            model.window_rect = SimpleRect::from_w_h(width, height);
+            model.vector_spacing = vector_spacing;
+            update::resized(&mut model);
+            assert_eq!(model.flow_vectors.len(), model.grid_width * model.grid_height);
+
+            let rect = &model.window_rect;
+            let steps = 200;
+            for i in 0..=steps {
+                for j in 0..=steps {
+                    let xy = Vec2::new(
+                        rect.left + rect.width() * i as f32 / steps as f32,
+                        rect.bottom + rect.height() * j as f32 / steps as f32,
+                    );
+                    let (row_index, column_index) =
+                        model.cell_at(xy).unwrap_or_else(|| panic!("{xy} in {width}x{height} has no cell"));
+                    let fv_index = row_index + column_index * model.grid_width;
+                    assert!(fv_index < model.flow_vectors.len());
+                    // The cell's vector sits within half a cell of the point
+                    let offset = model.flow_vectors[fv_index].position() - xy;
+                    assert!(offset.abs().max_element() <= vector_spacing / 2.0 + 1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_canvases_shrink_evenly() {
+        let fixed = |width, height| CanvasSize::Fixed { width, height };
+        assert_eq!(fixed(7200, 4800).dimensions((800, 600), 8192), (7200, 4800));
+        assert_eq!(fixed(16_000, 8000).dimensions((800, 600), 8192), (8192, 4096));
+        assert_eq!(fixed(3000, 12_000).dimensions((800, 600), 8192), (2048, 8192));
+        assert_eq!(fixed(100_000, 1).dimensions((800, 600), 8192), (8192, 1));
+        assert_eq!(CanvasSize::MatchWindow.dimensions((1280, 0), 8192), (1280, 1));
+    }
+
+    #[test]
+    fn the_same_seed_builds_the_same_field() {
+        let size = Vec2::new(320.0, 180.0);
+        let headings = |model: &Model| model.flow_vectors.iter().map(|vector| vector.heading()).collect::<Vec<_>>();
+        let (a, b) = (Model::new_seeded(size, 42), Model::new_seeded(size, 42));
+        assert_eq!((a.noise_seed, a.noise_offset), (b.noise_seed, b.noise_offset));
+        assert_eq!(headings(&a), headings(&b));
+
+        let c = Model::new_seeded(size, 43);
+        assert_ne!(headings(&a), headings(&c));
+    }
+}
@@ -1,16 +1,64 @@
-use super::{enums::RedrawBackground, Model};
-use winit::event::{ElementState, MouseButton, KeyEvent};
+use super::{constants::GUIDE_CURVE_POINT_SPACING, enums::{MouseTool, ParticleColorSource, RedrawBackground}, lut_color, Model};
+use crate::attractor::{self, Attractor, AttractorKind};
+use crate::camera::Camera;
+use crate::flow_vector::FlowVectorFieldBuilder;
+use crate::guide_curve::GuideCurve;
+use winit::event::{ElementState, MouseButton, KeyEvent, Modifiers};
 use winit::keyboard::{Key, NamedKey};
 use glam::Vec2;
 
 pub fn update(model: &mut Model) {
+    model.frame_count += 1;
+    if model.flow_vector_field_builder_type == FlowVectorFieldBuilder::Expression && model.field_expression.animate {
+        // Animated fields change every frame, so skip the background reset `regen_flow_vectors` does
+        model.flow_vectors = (model.new_flow_vector_fn)(model);
+        model.field_revision += 1;
+    }
+    if model.flow_vector_field_builder_type == FlowVectorFieldBuilder::Fluid {
+        model.step_fluid();
+    }
+
+    let cursor_attractor = model.cursor_attractor();
+    let continuous_color_lut = match model.particle_color_source {
+        ParticleColorSource::ImageContinuous if model.source_image.is_some() => {
+            model.lut_manager.load_lut(&model.current_lut).ok()
+        }
+        _ => None,
+    };
+    let shifting_lut = if model.lifetime.color_shift != 0.0 {
+        model.lut_manager.load_lut(&model.current_lut).ok()
+    } else {
+        None
+    };
+
     for index in 0..model.flow_particles.len() {
         if model.flow_particles[index].age() > model.particle_lifetime {
             model.particle_cleanup_requested = true;
         }
 
-        let nearest_angle = (model.nearest_angle_fn)(*model.flow_particles[index].xy(), model);
-        model.flow_particles[index].update(nearest_angle, model.particle_step_length);
+        let xy = *model.flow_particles[index].xy();
+        let nearest_angle = (model.nearest_angle_fn)(xy, model);
+        let heading = attractor::steer(
+            nearest_angle,
+            xy,
+            model.attractors.iter().chain(cursor_attractor.iter()),
+        );
+        model.flow_particles[index].update(heading, model.particle_step_length);
+
+        if let Some(lut_data) = &continuous_color_lut {
+            let particle = &model.flow_particles[index];
+            let color = model.source_image_color(particle.xy, particle.color_jitter, lut_data);
+            model.flow_particles[index].color = color;
+        }
+
+        if let Some(lut_data) = &shifting_lut {
+            let particle = &model.flow_particles[index];
+            if let Some(lut_position) = particle.lut_position {
+                let life = particle.life(model.particle_lifetime);
+                let color = lut_color(lut_data, model.lifetime.lut_position_at(lut_position, life));
+                model.flow_particles[index].color = color;
+            }
+        }
         
         // Update time outside bounds
         let is_outside = !model.window_rect.contains(*model.flow_particles[index].xy());
@@ -39,7 +87,8 @@ pub fn update(model: &mut Model) {
     if model.automatically_spawn_particles
         && model.flow_particles.len() < model.particle_auto_spawn_limit
     {
-        model.spawn_new_particle(model.get_random_xy());
+        let xy = model.get_random_xy();
+        model.spawn_new_particle(xy);
     }
 
     if model.draw_particle_mode {
@@ -48,24 +97,105 @@ pub fn update(model: &mut Model) {
 }
 
 pub fn mouse_moved(model: &mut Model, pos: Vec2) {
+    if model.sculpting_field {
+        let brush = model.field_brush;
+        brush.apply(model, pos, pos - model.mouse_xy);
+    }
+    if model.stirring_fluid {
+        model.stir_fluid(model.mouse_xy, pos);
+    }
+    if let Some(guide_curve) = &mut model.guide_curve_in_progress {
+        let far_enough = guide_curve
+            .points
+            .last()
+            .is_none_or(|last| last.distance(pos) >= GUIDE_CURVE_POINT_SPACING);
+        if far_enough {
+            guide_curve.points.push(pos);
+        }
+    }
     model.mouse_xy = pos;
 }
 
 pub fn mouse_pressed(model: &mut Model, button: MouseButton, state: ElementState) {
     if state == ElementState::Pressed {
-        match button {
-            MouseButton::Left => {
+        match (model.mouse_tool, button) {
+            (MouseTool::SpawnParticles, MouseButton::Left) => {
                 model.spawn_new_particle(model.mouse_xy);
             }
-            MouseButton::Right => model.draw_particle_mode = true,
+            (MouseTool::SpawnParticles, MouseButton::Right) => model.draw_particle_mode = true,
+            (MouseTool::PlaceAttractor, MouseButton::Left) => {
+                place_attractor(model, AttractorKind::Attractor);
+            }
+            (MouseTool::PlaceRepeller, MouseButton::Left) => {
+                place_attractor(model, AttractorKind::Repeller);
+            }
+            (MouseTool::SculptField, MouseButton::Left) => {
+                model.push_flow_vectors_undo();
+                model.sculpting_field = true;
+                // Twirl and smooth work in place, so dab once without waiting for movement
+                let brush = model.field_brush;
+                brush.apply(model, model.mouse_xy, Vec2::ZERO);
+            }
+            (MouseTool::DrawGuides, MouseButton::Left) => {
+                model.guide_curve_in_progress = Some(GuideCurve {
+                    points: vec![model.mouse_xy],
+                });
+            }
+            (MouseTool::StirFluid, MouseButton::Left) => {
+                if model.flow_vector_field_builder_type != FlowVectorFieldBuilder::Fluid {
+                    model.flow_vector_field_builder_type = FlowVectorFieldBuilder::Fluid;
+                    model.new_flow_vector_fn = model.flow_vector_field_builder_type.as_fn();
+                    model.regen_flow_vectors();
+                }
+                model.stirring_fluid = true;
+            }
+            // Right clicking with an attractor tool removes the nearest one
+            (MouseTool::PlaceAttractor | MouseTool::PlaceRepeller, MouseButton::Right) => {
+                if let Some(index) = attractor::nearest_attractor(&model.attractors, model.mouse_xy) {
+                    model.attractors.remove(index);
+                }
+            }
             _ => {}
         };
     }
 }
 
+fn place_attractor(model: &mut Model, kind: AttractorKind) {
+    model.attractors.push(Attractor::new(
+        kind,
+        model.mouse_xy,
+        model.attractor_strength,
+        model.attractor_falloff,
+    ));
+}
+
+fn finish_guide_curve(model: &mut Model, guide_curve: GuideCurve) {
+    // A single click doesn't have a direction to follow
+    if guide_curve.points.len() < 2 {
+        return;
+    }
+
+    model.guide_curves.push(guide_curve);
+    model.flow_vector_field_builder_type = FlowVectorFieldBuilder::GuideCurves;
+    model.new_flow_vector_fn = model.flow_vector_field_builder_type.as_fn();
+    model.regen_flow_vectors();
+}
+
+pub fn modifiers_changed(model: &mut Model, modifiers: &Modifiers) {
+    model.modifiers = modifiers.state();
+}
+
 pub fn mouse_released(model: &mut Model, button: MouseButton) {
-    if let MouseButton::Right = button {
-        model.draw_particle_mode = false;
+    match button {
+        MouseButton::Left => {
+            model.sculpting_field = false;
+            model.stirring_fluid = false;
+            if let Some(guide_curve) = model.guide_curve_in_progress.take() {
+                finish_guide_curve(model, guide_curve);
+            }
+        }
+        MouseButton::Right => model.draw_particle_mode = false,
+        _ => {}
     }
 }
 
@@ -73,11 +203,19 @@ pub fn key_pressed(model: &mut Model, input: &KeyEvent) {
     if input.state == ElementState::Pressed {
         match &input.logical_key {
             Key::Named(NamedKey::Space) => {
-                model.spawn_new_particle(model.get_random_xy());
+                let xy = model.get_random_xy();
+                model.spawn_new_particle(xy);
             }
             Key::Named(NamedKey::Escape) => {
                 std::process::exit(0);
             }
+            // Ctrl+Z on most platforms, Cmd+Z on macOS
+            Key::Character(c) if c == "z" && (model.modifiers.control_key() || model.modifiers.super_key()) => {
+                model.undo_flow_vectors_edit();
+            }
+            Key::Named(NamedKey::Home) => {
+                model.camera = Camera::default();
+            }
             Key::Character(c) if c == "/" || c == "?" => {
                 model.show_ui = !model.show_ui;
             }
@@ -87,5 +225,10 @@ pub fn key_pressed(model: &mut Model, input: &KeyEvent) {
 }
 
 pub fn resized(model: &mut Model) {
+    model.resize_grid();
+    // Regenerate in place since `regen_flow_vectors` would also reset the chosen background
+    model.flow_vectors = (model.new_flow_vector_fn)(model);
+    model.field_revision += 1;
+    model.flow_vectors_undo_stack.clear();
     model.redraw_background = RedrawBackground::Pending;
 }
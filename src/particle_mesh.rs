@@ -0,0 +1,327 @@
+use egui_wgpu::wgpu;
+use crate::model::enums::ParticleShape;
+use crate::recording::ParticleStamp;
+use crate::renderer::NO_UV;
+use std::ops::Range;
+
+// Instances the buffer holds to begin with, before a frame needs more
+const INITIAL_INSTANCE_CAPACITY: usize = 4096;
+
+/// A corner of a particle shape one unit in size, pointing along +x
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShapeVertex {
+    pub position: [f32; 2],
+    /// Where on the brush texture the corner lies, or `NO_UV` for flat color
+    pub uv: [f32; 2],
+    /// 1 where the particle's stretch lengthens the shape along its heading, otherwise 0
+    pub stretch: f32,
+    /// Multiplies the particle's opacity at this corner
+    pub alpha: f32,
+}
+
+impl ShapeVertex {
+    pub const ATTRIBS: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+        0 => Float32x2, // position
+        1 => Float32x2, // uv
+        2 => Float32,   // stretch
+        3 => Float32,   // alpha
+    ];
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ShapeVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+/// One particle drawn as an instance of its shape's mesh
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ParticleInstance {
+    pub position: [f32; 2],
+    pub size: f32,
+    /// The direction the shape points in radians, counterclockwise from +x
+    pub angle: f32,
+    pub stretch: f32,
+    /// Width over height of the brush for sprites, and 1 for every other shape
+    pub aspect: f32,
+    pub color: [f32; 4],
+    /// The `ParticleShape` whose mesh is drawn. Only used to pick the mesh, so it isn't an
+    /// attribute.
+    pub shape: u32,
+}
+
+impl ParticleInstance {
+    pub const ATTRIBS: [wgpu::VertexAttribute; 6] = wgpu::vertex_attr_array![
+        4 => Float32x2, // position
+        5 => Float32,   // size
+        6 => Float32,   // angle
+        7 => Float32,   // stretch
+        8 => Float32,   // aspect
+        9 => Float32x4, // color
+    ];
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ParticleInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+
+    pub fn from_stamp(stamp: &ParticleStamp) -> Self {
+        Self {
+            position: stamp.xy.to_array(),
+            size: stamp.size,
+            angle: stamp.angle,
+            stretch: stamp.stretch,
+            aspect: if stamp.shape == ParticleShape::Sprite { stamp.aspect } else { 1.0 },
+            color: stamp.color,
+            shape: stamp.shape as u32,
+        }
+    }
+}
+
+// A fan of triangles filling a unit circle around the origin from angle `start` to `end`
+fn arc_vertices(start: f32, end: f32, segments: usize, stretch: f32) -> Vec<ShapeVertex> {
+    let corner = |position: [f32; 2]| ShapeVertex { position, uv: NO_UV, stretch, alpha: 1.0 };
+    let mut vertices = Vec::with_capacity(segments * 3);
+    let angle_step = (end - start) / segments as f32;
+    for i in 0..segments {
+        let angle1 = start + i as f32 * angle_step;
+        let angle2 = angle1 + angle_step;
+        vertices.push(corner([0.0, 0.0]));
+        vertices.push(corner([angle1.cos(), angle1.sin()]));
+        vertices.push(corner([angle2.cos(), angle2.sin()]));
+    }
+    vertices
+}
+
+// The triangles of `shape` at size 1. The particle's size scales them, and the brush's aspect
+// squeezes sprites to fit within the same size.
+fn shape_vertices(shape: ParticleShape) -> Vec<ShapeVertex> {
+    let flat = |positions: &[[f32; 2]]| {
+        positions
+            .iter()
+            .map(|&position| ShapeVertex { position, uv: NO_UV, stretch: 0.0, alpha: 1.0 })
+            .collect()
+    };
+    match shape {
+        ParticleShape::Circle => arc_vertices(0.0, std::f32::consts::TAU, 16, 0.0),
+        ParticleShape::Square => flat(&[
+            [-0.5, -0.5],
+            [0.5, -0.5],
+            [-0.5, 0.5],
+            [0.5, -0.5],
+            [0.5, 0.5],
+            [-0.5, 0.5],
+        ]),
+        ParticleShape::Triangle => {
+            let height = 0.866; // sqrt(3)/2
+            flat(&[[1.0, 0.0], [-height, -1.0], [-height, 1.0]])
+        }
+        ParticleShape::Star => {
+            let inner_radius = 0.4;
+            let points = 5;
+            let angle_step = std::f32::consts::TAU / points as f32;
+            let mut positions = Vec::with_capacity(points * 3);
+            for i in 0..points {
+                let outer_angle = i as f32 * angle_step;
+                let inner_angle = outer_angle + angle_step * 0.5;
+                positions.extend([
+                    [0.0, 0.0],
+                    [outer_angle.cos(), outer_angle.sin()],
+                    [inner_radius * inner_angle.cos(), inner_radius * inner_angle.sin()],
+                ]);
+            }
+            flat(&positions)
+        }
+        ParticleShape::Diamond => flat(&[
+            [1.0, 0.0],
+            [0.0, 0.5],
+            [-1.0, 0.0],
+            [1.0, 0.0],
+            [-1.0, 0.0],
+            [0.0, -0.5],
+        ]),
+        ParticleShape::Arrow => {
+            let head = 0.6;
+            let shaft = 0.2;
+            flat(&[
+                [1.0, 0.0],
+                [0.0, head],
+                [0.0, -head],
+                [-1.0, -shaft],
+                [0.0, -shaft],
+                [-1.0, shaft],
+                [0.0, -shaft],
+                [0.0, shaft],
+                [-1.0, shaft],
+            ])
+        }
+        ParticleShape::Comet => {
+            // The front half of a round head, and a tail fading out behind it so the two don't
+            // overlap
+            let radius = 0.6;
+            let half_turn = std::f32::consts::FRAC_PI_2;
+            let mut vertices = arc_vertices(-half_turn, half_turn, 8, 0.0);
+            for vertex in &mut vertices {
+                vertex.position = [vertex.position[0] * radius, vertex.position[1] * radius];
+            }
+            vertices.extend([
+                ShapeVertex { position: [0.0, radius], uv: NO_UV, stretch: 0.0, alpha: 1.0 },
+                ShapeVertex { position: [0.0, -radius], uv: NO_UV, stretch: 0.0, alpha: 1.0 },
+                ShapeVertex { position: [-2.0, 0.0], uv: NO_UV, stretch: 1.0, alpha: 0.0 },
+            ]);
+            vertices
+        }
+        ParticleShape::Ellipse => arc_vertices(0.0, std::f32::consts::TAU, 24, 1.0),
+        ParticleShape::Sprite => {
+            // The brush's +x runs along the heading
+            let corner = |position: [f32; 2], uv: [f32; 2]| ShapeVertex { position, uv, stretch: 0.0, alpha: 1.0 };
+            vec![
+                corner([-1.0, -1.0], [0.0, 1.0]),
+                corner([1.0, -1.0], [1.0, 1.0]),
+                corner([-1.0, 1.0], [0.0, 0.0]),
+                corner([1.0, -1.0], [1.0, 1.0]),
+                corner([1.0, 1.0], [1.0, 0.0]),
+                corner([-1.0, 1.0], [0.0, 0.0]),
+            ]
+        }
+    }
+}
+
+/// The meshes of every particle shape, one after another in a single vertex buffer
+pub struct ShapeMeshes {
+    vertex_buffer: wgpu::Buffer,
+    // The vertices of each shape, in `ParticleShape::ALL` order
+    ranges: Vec<Range<u32>>,
+}
+
+impl ShapeMeshes {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let mut vertices = Vec::new();
+        let mut ranges = Vec::with_capacity(ParticleShape::ALL.len());
+        for shape in ParticleShape::ALL {
+            let start = vertices.len() as u32;
+            vertices.extend(shape_vertices(shape));
+            ranges.push(start..vertices.len() as u32);
+        }
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shape Mesh Buffer"),
+            size: std::mem::size_of_val(vertices.as_slice()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+
+        Self { vertex_buffer, ranges }
+    }
+
+    /// Draws `instances`, already written to the start of `instance_buffer`, in order. Each run
+    /// of particles sharing a shape is one instanced draw.
+    pub fn draw<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>, instance_buffer: &'a wgpu::Buffer, instances: &[ParticleInstance]) {
+        rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        rpass.set_vertex_buffer(1, instance_buffer.slice(..));
+        let mut start = 0;
+        while start < instances.len() {
+            let shape = instances[start].shape;
+            let end = instances[start..]
+                .iter()
+                .position(|instance| instance.shape != shape)
+                .map_or(instances.len(), |length| start + length);
+            rpass.draw(self.ranges[shape as usize].clone(), start as u32..end as u32);
+            start = end;
+        }
+    }
+}
+
+// The capacity a buffer of `capacity` instances needs to hold `needed` of them, as far as
+// `max_capacity` allows. Doubles up so a growing crowd of particles doesn't reallocate every frame,
+// and never shrinks.
+fn grown_capacity(capacity: usize, needed: usize, max_capacity: usize) -> usize {
+    if needed <= capacity || capacity >= max_capacity {
+        return capacity;
+    }
+    needed.next_power_of_two().min(max_capacity)
+}
+
+/// Holds a frame's particle instances, and grows when a frame has more than fit
+pub struct InstanceBuffer {
+    buffer: wgpu::Buffer,
+    capacity: usize,
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            buffer: Self::create_buffer(device, INITIAL_INSTANCE_CAPACITY),
+            capacity: INITIAL_INSTANCE_CAPACITY,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Instance Buffer"),
+            size: (capacity * std::mem::size_of::<ParticleInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Uploads as many of `instances` as the device allows a buffer to hold, returning those
+    /// that were
+    pub fn write<'a>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        instances: &'a [ParticleInstance],
+    ) -> &'a [ParticleInstance] {
+        let max_capacity = (device.limits().max_buffer_size / std::mem::size_of::<ParticleInstance>() as u64) as usize;
+        let capacity = grown_capacity(self.capacity, instances.len(), max_capacity);
+        if capacity != self.capacity {
+            self.capacity = capacity;
+            self.buffer = Self::create_buffer(device, self.capacity);
+        }
+
+        let instances = &instances[..instances.len().min(self.capacity)];
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(instances));
+        instances
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_capacity_grows_to_the_next_power_of_two() {
+        assert_eq!(grown_capacity(4096, 100, 1 << 20), 4096);
+        assert_eq!(grown_capacity(4096, 4096, 1 << 20), 4096);
+        assert_eq!(grown_capacity(4096, 4097, 1 << 20), 8192);
+        assert_eq!(grown_capacity(4096, 100_000, 1 << 20), 131_072);
+        // Never shrinks back down when the crowd thins out
+        assert_eq!(grown_capacity(131_072, 10, 1 << 20), 131_072);
+    }
+
+    #[test]
+    fn instance_capacity_stops_at_the_device_limit() {
+        assert_eq!(grown_capacity(4096, 3_000_000, 2_500_000), 2_500_000);
+        assert_eq!(grown_capacity(2_500_000, 3_000_000, 2_500_000), 2_500_000);
+    }
+
+    #[test]
+    fn every_shape_has_whole_triangles() {
+        for shape in ParticleShape::ALL {
+            let vertices = shape_vertices(shape);
+            assert!(!vertices.is_empty() && vertices.len() % 3 == 0, "{}", shape);
+        }
+    }
+}
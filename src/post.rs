@@ -0,0 +1,436 @@
+use egui_wgpu::wgpu;
+use serde::{Deserialize, Serialize};
+
+// Intermediate results keep more than 8 bits so blurs and bloom don't band
+const INTERMEDIATE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Effects applied to the composited canvas. Each one is off at 0.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PostSettings {
+    pub bloom_intensity: f32,
+    /// Brightness above which pixels bloom, from 0 to 1
+    pub bloom_threshold: f32,
+    /// How far the bloom spreads, in canvas pixels
+    pub bloom_radius: f32,
+    /// Gaussian blur radius in canvas pixels
+    pub blur_radius: f32,
+    pub grain: f32,
+    /// How much the corners darken, from 0 to 1
+    pub vignette: f32,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            bloom_intensity: 0.0,
+            bloom_threshold: 0.6,
+            bloom_radius: 16.0,
+            blur_radius: 0.0,
+            grain: 0.0,
+            vignette: 0.0,
+        }
+    }
+}
+
+impl PostSettings {
+    /// How far in canvas pixels the effects gather color from, so tiles can be drawn with enough
+    /// overlap to join up seamlessly
+    pub fn reach(&self) -> f32 {
+        let blur = if self.blur_radius > 0.0 { self.blur_radius } else { 0.0 };
+        let bloom = if self.bloom_intensity > 0.0 { self.bloom_radius } else { 0.0 };
+        blur + bloom
+    }
+}
+
+/// Where the processed texture sits in the final image, which is bigger than the canvas in
+/// offline renders and made of many textures in tiled ones
+#[derive(Debug, Clone, Copy)]
+pub struct PostFrame {
+    /// The texture's top left corner in image pixels
+    pub origin: [f32; 2],
+    pub image_size: [f32; 2],
+    /// Image pixels per canvas pixel
+    pub scale: f32,
+    /// Changes the grain from frame to frame
+    pub seed: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct BlurUniforms {
+    direction: [f32; 2],
+    radius: f32,
+    // Pixels darker than this are left out, or nothing is when negative
+    threshold: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct EffectUniforms {
+    image_size: [f32; 2],
+    origin: [f32; 2],
+    bloom_intensity: f32,
+    grain: f32,
+    grain_size: f32,
+    vignette: f32,
+    seed: u32,
+    _padding: [u32; 3],
+}
+
+// One of the textures the passes read and write
+struct Target {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+impl Target {
+    fn new(device: &wgpu::Device, label: &str, size: (u32, u32), format: wgpu::TextureFormat) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self { texture, view }
+    }
+}
+
+// The textures and the bind groups that read them, which all follow the size
+struct Targets {
+    input: Target,
+    scene_a: Target,
+    scene_b: Target,
+    bloom_a: Target,
+    bloom_b: Target,
+    output: Target,
+    blur_scene_h: wgpu::BindGroup,
+    blur_scene_v: wgpu::BindGroup,
+    bloom_h_input: wgpu::BindGroup,
+    bloom_h_blurred: wgpu::BindGroup,
+    bloom_v: wgpu::BindGroup,
+    finish_input: wgpu::BindGroup,
+    finish_blurred: wgpu::BindGroup,
+}
+
+// The layouts, sampler and uniform buffers the passes bind, which outlive any one size
+struct Bindings {
+    blur_layout: wgpu::BindGroupLayout,
+    finish_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    blur_scene_h_buffer: wgpu::Buffer,
+    blur_scene_v_buffer: wgpu::Buffer,
+    bloom_h_buffer: wgpu::Buffer,
+    bloom_v_buffer: wgpu::Buffer,
+    effect_buffer: wgpu::Buffer,
+}
+
+impl Bindings {
+    fn create_targets(&self, device: &wgpu::Device, format: wgpu::TextureFormat, size: (u32, u32)) -> Targets {
+        let input = Target::new(device, "Post Input Texture", size, format);
+        let scene_a = Target::new(device, "Post Scene Texture A", size, INTERMEDIATE_FORMAT);
+        let scene_b = Target::new(device, "Post Scene Texture B", size, INTERMEDIATE_FORMAT);
+        let bloom_a = Target::new(device, "Bloom Texture A", size, INTERMEDIATE_FORMAT);
+        let bloom_b = Target::new(device, "Bloom Texture B", size, INTERMEDIATE_FORMAT);
+        let output = Target::new(device, "Post Output Texture", size, format);
+
+        let blur_bind_group = |source: &Target, buffer: &wgpu::Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Blur Bind Group"),
+                layout: &self.blur_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&source.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        let finish_bind_group = |scene: &Target| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Post Finish Bind Group"),
+                layout: &self.finish_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&scene.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&bloom_a.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: self.effect_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+
+        Targets {
+            blur_scene_h: blur_bind_group(&input, &self.blur_scene_h_buffer),
+            blur_scene_v: blur_bind_group(&scene_a, &self.blur_scene_v_buffer),
+            bloom_h_input: blur_bind_group(&input, &self.bloom_h_buffer),
+            bloom_h_blurred: blur_bind_group(&scene_b, &self.bloom_h_buffer),
+            bloom_v: blur_bind_group(&bloom_b, &self.bloom_v_buffer),
+            finish_input: finish_bind_group(&input),
+            finish_blurred: finish_bind_group(&scene_b),
+            input,
+            scene_a,
+            scene_b,
+            bloom_a,
+            bloom_b,
+            output,
+        }
+    }
+}
+
+/// Runs the post-processing chain over whatever is composited into `input_view`, leaving the
+/// result in `output_texture`: blur, then bloom, then vignette and grain
+pub struct PostProcessor {
+    blur_pipeline: wgpu::RenderPipeline,
+    finish_pipeline: wgpu::RenderPipeline,
+    bindings: Bindings,
+    format: wgpu::TextureFormat,
+    targets: Targets,
+}
+
+impl PostProcessor {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, size: (u32, u32)) -> Self {
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let blur_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Blur Bind Group Layout"),
+            entries: &[texture_entry(0), sampler_entry(1), uniform_entry(2)],
+        });
+        let finish_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Post Finish Bind Group Layout"),
+            entries: &[texture_entry(0), texture_entry(1), sampler_entry(2), uniform_entry(3)],
+        });
+
+        let blur_pipeline = create_pass_pipeline(
+            device,
+            "Blur",
+            include_str!("shaders/blur.wgsl"),
+            &blur_layout,
+            INTERMEDIATE_FORMAT,
+        );
+        let finish_pipeline = create_pass_pipeline(
+            device,
+            "Post Finish",
+            include_str!("shaders/post.wgsl"),
+            &finish_layout,
+            format,
+        );
+
+        let create_buffer = |label, size| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let blur_size = std::mem::size_of::<BlurUniforms>();
+        let bindings = Bindings {
+            blur_layout,
+            finish_layout,
+            sampler,
+            blur_scene_h_buffer: create_buffer("Blur Horizontal Buffer", blur_size),
+            blur_scene_v_buffer: create_buffer("Blur Vertical Buffer", blur_size),
+            bloom_h_buffer: create_buffer("Bloom Horizontal Buffer", blur_size),
+            bloom_v_buffer: create_buffer("Bloom Vertical Buffer", blur_size),
+            effect_buffer: create_buffer("Post Effect Buffer", std::mem::size_of::<EffectUniforms>()),
+        };
+        let targets = bindings.create_targets(device, format, size);
+
+        Self {
+            blur_pipeline,
+            finish_pipeline,
+            bindings,
+            format,
+            targets,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: (u32, u32)) {
+        self.targets = self.bindings.create_targets(device, self.format, size);
+    }
+
+    /// Where the layers are composited for processing
+    pub fn input_view(&self) -> &wgpu::TextureView {
+        &self.targets.input.view
+    }
+
+    /// The processed image, once `apply` has run
+    pub fn output_texture(&self) -> &wgpu::Texture {
+        &self.targets.output.texture
+    }
+
+    pub fn output_view(&self) -> &wgpu::TextureView {
+        &self.targets.output.view
+    }
+
+    pub fn apply(&self, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, settings: &PostSettings, frame: PostFrame) {
+        let targets = &self.targets;
+        let bindings = &self.bindings;
+        let write_blur = |buffer: &wgpu::Buffer, direction: [f32; 2], radius: f32, threshold: f32| {
+            let uniforms = BlurUniforms {
+                direction,
+                radius: radius * frame.scale,
+                threshold,
+            };
+            queue.write_buffer(buffer, 0, bytemuck::bytes_of(&uniforms));
+        };
+
+        let blurred = settings.blur_radius > 0.0;
+        if blurred {
+            write_blur(&bindings.blur_scene_h_buffer, [1.0, 0.0], settings.blur_radius, -1.0);
+            write_blur(&bindings.blur_scene_v_buffer, [0.0, 1.0], settings.blur_radius, -1.0);
+            self.pass(encoder, &self.blur_pipeline, &targets.blur_scene_h, &targets.scene_a.view);
+            self.pass(encoder, &self.blur_pipeline, &targets.blur_scene_v, &targets.scene_b.view);
+        }
+
+        let bloom = settings.bloom_intensity > 0.0;
+        if bloom {
+            let threshold = settings.bloom_threshold.max(0.0);
+            write_blur(&bindings.bloom_h_buffer, [1.0, 0.0], settings.bloom_radius, threshold);
+            write_blur(&bindings.bloom_v_buffer, [0.0, 1.0], settings.bloom_radius, -1.0);
+            let bloom_h = if blurred { &targets.bloom_h_blurred } else { &targets.bloom_h_input };
+            self.pass(encoder, &self.blur_pipeline, bloom_h, &targets.bloom_b.view);
+            self.pass(encoder, &self.blur_pipeline, &targets.bloom_v, &targets.bloom_a.view);
+        }
+
+        let effects = EffectUniforms {
+            image_size: frame.image_size,
+            origin: frame.origin,
+            bloom_intensity: if bloom { settings.bloom_intensity } else { 0.0 },
+            grain: settings.grain,
+            grain_size: frame.scale.max(1.0),
+            vignette: settings.vignette,
+            seed: frame.seed,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&bindings.effect_buffer, 0, bytemuck::bytes_of(&effects));
+        let finish = if blurred { &targets.finish_blurred } else { &targets.finish_input };
+        self.pass(encoder, &self.finish_pipeline, finish, &targets.output.view);
+    }
+
+    fn pass(&self, encoder: &mut wgpu::CommandEncoder, pipeline: &wgpu::RenderPipeline, bind_group: &wgpu::BindGroup, target: &wgpu::TextureView) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Post Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        rpass.set_pipeline(pipeline);
+        rpass.set_bind_group(0, bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
+
+// A fullscreen pass that replaces its target
+fn create_pass_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    source: &str,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(&format!("{} Shader", label)),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(&format!("{} Pipeline Layout", label)),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format!("{} Pipeline", label)),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
@@ -0,0 +1,624 @@
+use crate::{
+    brush::BrushTexture,
+    camera::canvas_projection,
+    density::DensityBuffer,
+    export::{write_rgba_strips, ImageFormat},
+    model::{constants::DEFAULT_TILE_SIZE, enums::Background, Model},
+    particle_mesh::{ParticleInstance, ShapeMeshes},
+    post::{PostFrame, PostProcessor},
+    recording::Recording,
+    renderer::{
+        create_geometry_pipeline, create_particle_pipeline, particle_composite_blending, particle_layer_blending,
+        read_texture_rgba, Renderer, Vertex, LAYER_BLENDING,
+    },
+};
+use egui_wgpu::wgpu;
+use glam::{Mat4, Vec2, Vec3, Vec4Swizzles};
+use log::{info, warn};
+use std::fmt::{self, Display};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+const TILE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+// Vertices uploaded per draw. A multiple of 6 so a batch never splits a line or triangle.
+const VERTEX_BATCH_SIZE: usize = 6 * 65_536;
+// Particle instances uploaded per draw
+const INSTANCE_BATCH_SIZE: usize = 65_536;
+
+#[derive(Debug, Clone)]
+pub struct PosterSettings {
+    /// Width of the poster in pixels. The height follows the canvas' aspect ratio.
+    pub width: u32,
+    pub frames: u32,
+    pub seed: u64,
+    pub tile_size: u32,
+    pub format: ImageFormat,
+}
+
+impl Default for PosterSettings {
+    fn default() -> Self {
+        Self {
+            width: 20_000,
+            frames: 600,
+            seed: 1,
+            tile_size: DEFAULT_TILE_SIZE,
+            format: ImageFormat::default(),
+        }
+    }
+}
+
+/// How far along a poster render is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PosterProgress {
+    Simulating { frames: u32 },
+    Rendering { strip: u32, strips: u32 },
+}
+
+impl Display for PosterProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Simulating { frames } => write!(f, "Simulating {} frames", frames),
+            Self::Rendering { strip, strips } => write!(f, "Rendering strip {} of {}", strip + 1, strips),
+        }
+    }
+}
+
+/// A poster rendering on its own thread, so the window keeps running in the meantime
+pub struct PosterRender {
+    progress: Receiver<PosterProgress>,
+    latest_progress: PosterProgress,
+    thread: JoinHandle<io::Result<PathBuf>>,
+}
+
+impl PosterRender {
+    /// Start rendering a copy of `model`'s scene to `path`
+    pub fn start(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        model: &Model,
+        settings: PosterSettings,
+        path: PathBuf,
+    ) -> Self {
+        let simulation = Recording::simulation(model, settings.seed);
+        let latest_progress = PosterProgress::Simulating { frames: settings.frames };
+        let (sender, progress) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            render_poster(&device, &queue, simulation, &settings, &path, |progress| {
+                // The window may have stopped listening, which doesn't stop the render
+                let _ = sender.send(progress);
+            })?;
+            Ok(path)
+        });
+        Self {
+            progress,
+            latest_progress,
+            thread,
+        }
+    }
+
+    /// The latest progress the render has reported
+    pub fn progress(&mut self) -> PosterProgress {
+        if let Some(progress) = self.progress.try_iter().last() {
+            self.latest_progress = progress;
+        }
+        self.latest_progress
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Wait for the render to finish, returning where the poster was saved
+    pub fn finish(self) -> io::Result<PathBuf> {
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("the poster render panicked")))
+    }
+}
+
+// Run `simulation` and draw it at poster size, one tile at a time, straight to `path`. Calls
+// `report` as each stage starts.
+fn render_poster(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    simulation: Model,
+    settings: &PosterSettings,
+    path: &Path,
+    mut report: impl FnMut(PosterProgress),
+) -> io::Result<()> {
+    info!("Simulating {} frames for the poster", settings.frames);
+    report(PosterProgress::Simulating { frames: settings.frames });
+    let recording = Recording::simulate(simulation, settings.frames);
+    let scale = settings.width as f32 / recording.canvas_size.x;
+    let image_size = (settings.width, ((recording.canvas_size.y * scale).round() as u32).max(1));
+    info!("Rendering a {}x{} poster to {}", image_size.0, image_size.1, path.display());
+
+    let rasterizer = TiledRasterizer::new(device, queue, &recording, image_size, settings.tile_size);
+    let strips = image_size.1.div_ceil(rasterizer.tile_size);
+    write_rgba_strips(path, settings.format, image_size, rasterizer.tile_size, |strip| {
+        info!("Poster strip {} of {}", strip + 1, strips);
+        report(PosterProgress::Rendering { strip, strips });
+        rasterizer.render_strip(strip)
+    })?;
+    info!("Saved poster to {}", path.display());
+    Ok(())
+}
+
+/// Draws a `Recording` at any size by splitting it into tiles small enough for the GPU
+pub struct TiledRasterizer<'a> {
+    device: &'a wgpu::Device,
+    queue: &'a wgpu::Queue,
+    recording: &'a Recording,
+    image_size: (u32, u32),
+    pub tile_size: u32,
+    // Extra pixels drawn around each tile for post-processing to blur in from, then cropped off
+    margin: u32,
+    // Image pixels per canvas pixel
+    scale: f32,
+    particle_pipeline: wgpu::RenderPipeline,
+    line_pipeline: wgpu::RenderPipeline,
+    // Blends the particle tile onto the tile in the recording's blend mode
+    composite_pipeline: wgpu::RenderPipeline,
+    composite_bind_group: wgpu::BindGroup,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    brush_texture: BrushTexture,
+    vertex_buffer: wgpu::Buffer,
+    shape_meshes: ShapeMeshes,
+    instance_buffer: wgpu::Buffer,
+    // The tile is composited into its post-processing chain
+    post: PostProcessor,
+    // The particles on their own, as the particle layer would hold them
+    particle_texture: wgpu::Texture,
+    particle_view: wgpu::TextureView,
+    vector_vertices: Vec<Vertex>,
+    particles_only: bool,
+    // Tile-sized with the margin, for recordings drawn in density mode
+    density: Option<DensityBuffer>,
+}
+
+impl<'a> TiledRasterizer<'a> {
+    pub fn new(
+        device: &'a wgpu::Device,
+        queue: &'a wgpu::Queue,
+        recording: &'a Recording,
+        image_size: (u32, u32),
+        tile_size: u32,
+    ) -> Self {
+        let scale = image_size.0 as f32 / recording.canvas_size.x;
+        let max_size = device.limits().max_texture_dimension_2d;
+        // Leave room for the smallest tile. Blurs reaching further than that seam at tile edges.
+        let max_margin = max_size.saturating_sub(64) / 2;
+        let margin = (recording.post.reach() * scale).ceil() as u32;
+        if margin > max_margin {
+            warn!("Post-processing reaches {} pixels, but tiles only have room for {}", margin, max_margin);
+        }
+        let margin = margin.min(max_margin);
+        let tile_size = tile_size.clamp(64, max_size.saturating_sub(2 * margin).max(64));
+        let render_size = tile_size + 2 * margin;
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Tile Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/shader.wgsl").into()),
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tile Uniform Buffer"),
+            size: std::mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tile Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tile Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+        let brush_bind_group_layout = BrushTexture::bind_group_layout(device);
+        let brush_texture = BrushTexture::new(device, queue, &brush_bind_group_layout, &recording.brush);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tile Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, &brush_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let particle_pipeline = create_particle_pipeline(
+            device,
+            "Tile Particle Pipeline",
+            &pipeline_layout,
+            &shader,
+            TILE_FORMAT,
+            particle_layer_blending(recording.blend_mode),
+        );
+        let line_pipeline = create_geometry_pipeline(
+            device,
+            "Tile Line Pipeline",
+            &pipeline_layout,
+            &shader,
+            TILE_FORMAT,
+            wgpu::PrimitiveTopology::LineList,
+            LAYER_BLENDING,
+        );
+
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tile Vertex Buffer"),
+            size: (VERTEX_BATCH_SIZE * std::mem::size_of::<Vertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tile Instance Buffer"),
+            size: (INSTANCE_BATCH_SIZE * std::mem::size_of::<ParticleInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let post = PostProcessor::new(device, TILE_FORMAT, (render_size, render_size));
+        let particle_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Particle Tile Texture"),
+            size: wgpu::Extent3d {
+                width: render_size,
+                height: render_size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TILE_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let particle_view = particle_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let composite_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tile Composite Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            }],
+        });
+        let composite_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tile Composite Bind Group"),
+            layout: &composite_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&particle_view),
+            }],
+        });
+        let composite_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Tile Composite Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/layer.wgsl").into()),
+        });
+        let composite_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tile Composite Pipeline Layout"),
+            bind_group_layouts: &[&composite_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let composite_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tile Composite Pipeline"),
+            layout: Some(&composite_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &composite_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &composite_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: TILE_FORMAT,
+                    blend: Some(particle_composite_blending(recording.blend_mode)),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let density = recording.density.enabled.then(|| {
+            let density = DensityBuffer::new(device, (render_size, render_size), &pipeline_layout, &shader, TILE_FORMAT);
+            density.set_settings(queue, &recording.density);
+            if let Some(lut) = &recording.lut {
+                density.set_lut(queue, lut);
+            }
+            density
+        });
+
+        let vector_vertices = if recording.background == Background::Vectors {
+            Renderer::generate_vector_vertices(
+                &recording.flow_vectors,
+                recording.grid_width,
+                recording.vector_display_stride,
+            )
+        } else {
+            Vec::new()
+        };
+
+        Self {
+            device,
+            queue,
+            recording,
+            image_size,
+            tile_size,
+            margin,
+            scale,
+            particle_pipeline,
+            line_pipeline,
+            composite_pipeline,
+            composite_bind_group,
+            uniform_buffer,
+            uniform_bind_group,
+            brush_texture,
+            vertex_buffer,
+            shape_meshes: ShapeMeshes::new(device, queue),
+            instance_buffer,
+            post,
+            particle_texture,
+            particle_view,
+            vector_vertices,
+            particles_only: false,
+            density,
+        }
+    }
+
+    /// Draw only the particles, on a transparent background
+    pub fn particles_only(mut self) -> Self {
+        self.vector_vertices.clear();
+        self.particles_only = true;
+        self
+    }
+
+    /// The RGBA rows of the `strip`th row of tiles, the full width of the image
+    pub fn render_strip(&self, strip: u32) -> io::Result<Vec<u8>> {
+        let (width, height) = self.image_size;
+        let top = strip * self.tile_size;
+        let rows = self.tile_size.min(height - top);
+        let row_bytes = width as usize * 4;
+        let mut pixels = vec![0; row_bytes * rows as usize];
+
+        for left in (0..width).step_by(self.tile_size as usize) {
+            let columns = self.tile_size.min(width - left);
+            self.render_tile(left, top);
+            let texture = if self.particles_only { &self.particle_texture } else { self.post.output_texture() };
+            let tile = read_texture_rgba(self.device, self.queue, texture)?;
+            let margin = self.margin as usize;
+            let tile_row_bytes = (self.tile_size + 2 * self.margin) as usize * 4;
+            for row in 0..rows as usize {
+                let source = &tile[(row + margin) * tile_row_bytes + margin * 4..][..columns as usize * 4];
+                pixels[row * row_bytes + left as usize * 4..][..source.len()].copy_from_slice(source);
+            }
+        }
+
+        Ok(pixels)
+    }
+
+    // Draw the tile whose top left corner is at image pixel (left, top), and its margin
+    fn render_tile(&self, left: u32, top: u32) {
+        let left = left as f32 - self.margin as f32;
+        let top = top as f32 - self.margin as f32;
+
+        // Map the whole image's clip space onto this tile's. Tiles are offset by whole pixels so
+        // every tile rasterizes its edges the same way.
+        let tile = (self.tile_size + 2 * self.margin) as f32;
+        let (width, height) = (self.image_size.0 as f32, self.image_size.1 as f32);
+        let tile_projection = Mat4::from_translation(Vec3::new(
+            (width - 2.0 * left) / tile - 1.0,
+            1.0 - (height - 2.0 * top) / tile,
+            0.0,
+        )) * Mat4::from_scale(Vec3::new(width / tile, height / tile, 1.0));
+        let view_projection = tile_projection * canvas_projection(self.recording.canvas_size);
+        self.queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&view_projection.to_cols_array()));
+
+        if !self.particles_only {
+            let background = match self.recording.background {
+                Background::White => wgpu::Color::WHITE,
+                Background::Black | Background::Vectors => wgpu::Color::BLACK,
+            };
+            self.clear(self.post.input_view(), background);
+            for batch in self.vector_vertices.chunks(VERTEX_BATCH_SIZE) {
+                self.draw(&self.line_pipeline, self.post.input_view(), batch);
+            }
+        }
+        self.clear(&self.particle_view, wgpu::Color::TRANSPARENT);
+
+        // Density mode accumulates the particles apart from the particle tile and tone maps them
+        // onto it
+        let (particle_pipeline, particle_view) = match &self.density {
+            Some(density) => {
+                let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Clear Tile Density"),
+                });
+                density.clear(&mut encoder);
+                self.queue.submit(Some(encoder.finish()));
+                (density.accumulate_pipeline(), density.view())
+            }
+            None => (&self.particle_pipeline, &self.particle_view),
+        };
+
+        // The tile's bounds in canvas coordinates
+        let clip_to_canvas = view_projection.inverse();
+        let corner_a = (clip_to_canvas * glam::Vec4::new(-1.0, -1.0, 0.0, 1.0)).xy();
+        let corner_b = (clip_to_canvas * glam::Vec4::new(1.0, 1.0, 0.0, 1.0)).xy();
+        let (min, max) = (corner_a.min(corner_b), corner_a.max(corner_b));
+        // Trails fade a little more with every frame drawn after them. Fading the stamps by their
+        // age instead comes out close enough.
+        let keep = 1.0 - self.recording.trail_fade.clamp(0.0, 1.0);
+        let frame_count = self.recording.frame_count();
+        let mut instances = Vec::with_capacity(INSTANCE_BATCH_SIZE);
+        for (frame, stamps) in self.recording.frames().enumerate() {
+            let fade = keep.powi((frame_count - 1 - frame) as i32);
+            if fade < 1.0 / 255.0 {
+                continue;
+            }
+
+            for stamp in stamps {
+                let reach = Vec2::splat(stamp.reach());
+                if (stamp.xy + reach).cmplt(min).any() || (stamp.xy - reach).cmpgt(max).any() {
+                    continue;
+                }
+
+                let mut instance = ParticleInstance::from_stamp(stamp);
+                instance.color[3] *= fade;
+                instances.push(instance);
+                if instances.len() == INSTANCE_BATCH_SIZE {
+                    self.draw_instances(particle_pipeline, particle_view, &instances);
+                    instances.clear();
+                }
+            }
+        }
+        self.draw_instances(particle_pipeline, particle_view, &instances);
+
+        if let Some(density) = &self.density {
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Tone Map Tile"),
+            });
+            density.tone_map(&mut encoder, &self.particle_view, wgpu::LoadOp::Load);
+            self.queue.submit(Some(encoder.finish()));
+        }
+
+        if !self.particles_only {
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Composite Tile"),
+            });
+            {
+                let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Composite Tile"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: self.post.input_view(),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    occlusion_query_set: None,
+                    timestamp_writes: None,
+                });
+                rpass.set_pipeline(&self.composite_pipeline);
+                rpass.set_bind_group(0, &self.composite_bind_group, &[]);
+                rpass.draw(0..3, 0..1);
+            }
+            let frame = PostFrame {
+                origin: [left, top],
+                image_size: [width, height],
+                scale: self.scale,
+                seed: 0,
+            };
+            self.post.apply(self.queue, &mut encoder, &self.recording.post, frame);
+            self.queue.submit(Some(encoder.finish()));
+        }
+    }
+
+    fn clear(&self, view: &wgpu::TextureView, color: wgpu::Color) {
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Clear Tile"),
+        });
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Clear Tile"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(color),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    fn draw(&self, pipeline: &wgpu::RenderPipeline, view: &wgpu::TextureView, vertices: &[Vertex]) {
+        if vertices.is_empty() {
+            return;
+        }
+
+        self.queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(vertices));
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Draw Tile"),
+        });
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Draw Tile"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, &self.uniform_bind_group, &[]);
+            rpass.set_bind_group(1, self.brush_texture.bind_group(), &[]);
+            rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            rpass.draw(0..vertices.len() as u32, 0..1);
+        }
+        // Submit before the vertex buffer is overwritten by the next batch
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    fn draw_instances(&self, pipeline: &wgpu::RenderPipeline, view: &wgpu::TextureView, instances: &[ParticleInstance]) {
+        if instances.is_empty() {
+            return;
+        }
+
+        self.queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(instances));
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Draw Tile Particles"),
+        });
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Draw Tile Particles"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, &self.uniform_bind_group, &[]);
+            rpass.set_bind_group(1, self.brush_texture.bind_group(), &[]);
+            self.shape_meshes.draw(&mut rpass, &self.instance_buffer, instances);
+        }
+        // Submit before the instance buffer is overwritten by the next batch
+        self.queue.submit(Some(encoder.finish()));
+    }
+}
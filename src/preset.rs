@@ -0,0 +1,286 @@
+use crate::{
+    attractor::Attractor,
+    brush::Brush,
+    density::DensitySettings,
+    expression::FieldExpression,
+    field_primitive::FieldPrimitive,
+    fluid::FluidSettings,
+    flow_vector::FlowVectorFieldBuilder,
+    guide_curve::{GuideCurve, GuideCurveSettings},
+    lifetime::LifetimeSettings,
+    model::{
+        constants::{DEFAULT_VECTOR_DISPLAY_SPACING, DEFAULT_VECTOR_SPACING},
+        enums::{Background, BlendMode, ParticleColorSource, ParticleShape},
+        Model,
+    },
+    post::PostSettings,
+    source_image::{ImageFieldMode, SourceImage},
+};
+use glam::DVec2;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const PRESET_EXTENSION: &str = "json";
+
+/// The settings needed to recreate a scene, stored as JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Preset {
+    pub flow_vector_field_builder_type: FlowVectorFieldBuilder,
+    pub noise_seed: u32,
+    pub noise_scale: f64,
+    #[serde(default)]
+    pub noise_offset: DVec2,
+    pub current_lut: String,
+    pub background: Background,
+    pub particle_shape: ParticleShape,
+    pub particle_lifetime: f32,
+    pub particle_min_weight: f32,
+    pub particle_max_weight: f32,
+    pub particle_step_length: f32,
+    #[serde(default)]
+    pub field_primitives: Vec<FieldPrimitive>,
+    #[serde(default)]
+    pub attractors: Vec<Attractor>,
+    #[serde(default)]
+    pub guide_curves: Vec<GuideCurve>,
+    #[serde(default)]
+    pub guide_curve_settings: GuideCurveSettings,
+    #[serde(default)]
+    pub source_image_path: Option<PathBuf>,
+    #[serde(default)]
+    pub image_field_mode: ImageFieldMode,
+    #[serde(default)]
+    pub particle_color_source: ParticleColorSource,
+    #[serde(default)]
+    pub particle_color_jitter: f32,
+    #[serde(default)]
+    pub quantize_image_colors: bool,
+    #[serde(default)]
+    pub field_expression: FieldExpression,
+    #[serde(default)]
+    pub fluid_settings: FluidSettings,
+    #[serde(default = "default_vector_spacing")]
+    pub vector_spacing: f32,
+    #[serde(default = "default_vector_display_spacing")]
+    pub vector_display_spacing: f32,
+    #[serde(default)]
+    pub trail_fade: f32,
+    #[serde(default)]
+    pub density: DensitySettings,
+    #[serde(default)]
+    pub blend_mode: BlendMode,
+    #[serde(default)]
+    pub post: PostSettings,
+    #[serde(default)]
+    pub lifetime: LifetimeSettings,
+    #[serde(default)]
+    pub particle_spin: f32,
+    #[serde(default)]
+    pub particle_rotation_jitter: f32,
+    /// The sprite brush, or None for the soft dot
+    #[serde(default)]
+    pub brush_path: Option<PathBuf>,
+}
+
+fn default_vector_spacing() -> f32 {
+    DEFAULT_VECTOR_SPACING
+}
+
+fn default_vector_display_spacing() -> f32 {
+    DEFAULT_VECTOR_DISPLAY_SPACING
+}
+
+impl Preset {
+    pub fn from_model(model: &Model) -> Self {
+        Self {
+            flow_vector_field_builder_type: model.flow_vector_field_builder_type.clone(),
+            noise_seed: model.noise_seed,
+            noise_offset: model.noise_offset,
+            noise_scale: model.noise_scale,
+            current_lut: model.current_lut.clone(),
+            background: model.background,
+            particle_shape: model.particle_shape,
+            particle_lifetime: model.particle_lifetime,
+            particle_min_weight: model.particle_min_weight,
+            particle_max_weight: model.particle_max_weight,
+            particle_step_length: model.particle_step_length,
+            field_primitives: model.field_primitives.clone(),
+            attractors: model.attractors.clone(),
+            guide_curves: model.guide_curves.clone(),
+            guide_curve_settings: model.guide_curve_settings.clone(),
+            source_image_path: model.source_image.as_ref().map(|image| image.path().to_path_buf()),
+            image_field_mode: model.image_field_mode,
+            particle_color_source: model.particle_color_source,
+            particle_color_jitter: model.particle_color_jitter,
+            quantize_image_colors: model.quantize_image_colors,
+            field_expression: model.field_expression.clone(),
+            fluid_settings: model.fluid_settings.clone(),
+            vector_spacing: model.vector_spacing,
+            vector_display_spacing: model.vector_display_spacing,
+            trail_fade: model.trail_fade,
+            density: model.density,
+            blend_mode: model.blend_mode,
+            post: model.post,
+            lifetime: model.lifetime,
+            particle_spin: model.particle_spin,
+            particle_rotation_jitter: model.particle_rotation_jitter,
+            brush_path: model.brush.path().map(|path| path.to_path_buf()),
+        }
+    }
+
+    pub fn apply(self, model: &mut Model) {
+        model.flow_vector_field_builder_type = self.flow_vector_field_builder_type;
+        model.new_flow_vector_fn = model.flow_vector_field_builder_type.as_fn();
+        model.noise_seed = self.noise_seed;
+        model.noise_offset = self.noise_offset;
+        model.noise_scale = self.noise_scale;
+        // Presets from a newer build may name a LUT this one doesn't have
+        if model.lut_manager.load_lut(&self.current_lut).is_ok() {
+            model.current_lut = self.current_lut;
+        }
+        model.particle_shape = self.particle_shape;
+        model.particle_lifetime = self.particle_lifetime;
+        model.particle_min_weight = self.particle_min_weight;
+        model.particle_max_weight = self.particle_max_weight;
+        model.particle_step_length = self.particle_step_length;
+        model.field_primitives = self.field_primitives;
+        model.attractors = self.attractors;
+        model.guide_curves = self.guide_curves;
+        model.guide_curve_settings = self.guide_curve_settings;
+        model.image_field_mode = self.image_field_mode;
+        model.particle_color_source = self.particle_color_source;
+        model.particle_color_jitter = self.particle_color_jitter;
+        model.quantize_image_colors = self.quantize_image_colors;
+        model.field_expression = self.field_expression;
+        model.fluid_settings = self.fluid_settings;
+        model.vector_spacing = self.vector_spacing;
+        model.vector_display_spacing = self.vector_display_spacing;
+        model.trail_fade = self.trail_fade;
+        model.density = self.density;
+        model.blend_mode = self.blend_mode;
+        model.post = self.post;
+        model.lifetime = self.lifetime;
+        model.particle_spin = self.particle_spin;
+        model.particle_rotation_jitter = self.particle_rotation_jitter;
+        model.brush = match &self.brush_path {
+            Some(path) => Brush::load(path).unwrap_or_else(|e| {
+                warn!("Failed to load the preset's brush {}: {}", path.display(), e);
+                Brush::default()
+            }),
+            None => Brush::default(),
+        };
+        model.resize_grid();
+        // The previous scene's image never carries over
+        model.source_image = self.source_image_path.and_then(|path| {
+            SourceImage::load(&path)
+                .map_err(|e| warn!("Failed to load the preset's source image {}: {}", path.display(), e))
+                .ok()
+        });
+        if model.source_image.is_none() && model.flow_vector_field_builder_type == FlowVectorFieldBuilder::Image {
+            let fallback = FlowVectorFieldBuilder::Billow;
+            warn!("The preset's field follows an image that isn't loaded, so it uses {:?} noise instead", fallback);
+            model.new_flow_vector_fn = fallback.as_fn();
+            model.flow_vector_field_builder_type = fallback;
+        }
+        model.regen_flow_vectors();
+        // Regenerating switches to the vector background so restore the saved one afterwards
+        model.background = self.background;
+    }
+
+    pub fn save(&self, directory: &Path, name: &str) -> io::Result<PathBuf> {
+        let path = preset_path(directory, name)?;
+        fs::create_dir_all(directory)?;
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(&path, json)?;
+        Ok(path)
+    }
+
+    pub fn load(directory: &Path, name: &str) -> io::Result<Self> {
+        let json = fs::read_to_string(preset_path(directory, name)?)?;
+        serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+// Where the preset called `name` is kept in `directory`. Characters that would reach another
+// directory or aren't allowed in file names are replaced, so the name is always one file there.
+fn preset_path(directory: &Path, name: &str) -> io::Result<PathBuf> {
+    let file_name: String = name
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    if file_name.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "the preset needs a name"));
+    }
+    Ok(directory.join(format!("{}.{}", file_name, PRESET_EXTENSION)))
+}
+
+/// Names of the presets saved in `directory`, sorted alphabetically
+pub fn list_presets(directory: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Vec::new();
+    };
+
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == PRESET_EXTENSION))
+        .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+        .collect();
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preset_names_stay_one_file_in_the_directory() {
+        let directory = Path::new("presets");
+        for (name, file_name) in [
+            ("calm", "calm.json"),
+            ("v1.2", "v1.2.json"),
+            ("../escape", ".._escape.json"),
+            ("a/b\\c", "a_b_c.json"),
+            ("..", "...json"),
+            ("  spaced  ", "spaced.json"),
+        ] {
+            assert_eq!(preset_path(directory, name).unwrap(), directory.join(file_name), "{name:?}");
+        }
+        assert!(preset_path(directory, "   ").is_err());
+    }
+
+    #[test]
+    fn applying_a_preset_replaces_the_source_image() {
+        let path = std::env::temp_dir().join(format!("flow-preset-test-{}.png", std::process::id()));
+        image::RgbImage::from_pixel(4, 4, image::Rgb([200, 40, 40])).save(&path).unwrap();
+        let mut model = Model::new(glam::Vec2::new(64.0, 48.0));
+        model.load_source_image(&path).unwrap();
+
+        // A preset without an image clears the old one
+        let mut preset = Preset::from_model(&model);
+        preset.source_image_path = None;
+        preset.apply(&mut model);
+        assert!(model.source_image.is_none());
+
+        // An image-driven field whose image is gone falls back to noise
+        model.load_source_image(&path).unwrap();
+        let mut preset = Preset::from_model(&model);
+        preset.flow_vector_field_builder_type = FlowVectorFieldBuilder::Image;
+        preset.source_image_path = Some(path.with_extension("missing.png"));
+        preset.apply(&mut model);
+        assert!(model.source_image.is_none());
+        assert_eq!(model.flow_vector_field_builder_type, FlowVectorFieldBuilder::Billow);
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{
+    brush::Brush,
+    density::DensitySettings,
+    flow_particle::FlowParticle,
+    flow_vector::FlowVector,
+    lut_manager::LutData,
+    model::{
+        constants::{MAX_RECORDED_FRAMES, MAX_RECORDED_PARTICLE_STAMPS, MAX_SIMULATED_PARTICLE_STAMPS},
+        enums::{Background, BlendMode, ParticleShape},
+        update::update,
+        Model,
+    },
+    post::PostSettings,
+    preset::Preset,
+};
+use glam::Vec2;
+use log::warn;
+use rand::{rngs::StdRng, SeedableRng};
+use std::collections::{vec_deque, VecDeque};
+
+// How much longer than wide a stretched shape gets per pixel moved each frame, and at most
+const STRETCH_PER_SPEED: f32 = 1.5;
+const MAX_STRETCH: f32 = 6.0;
+
+/// A particle as it was drawn in one frame
+#[derive(Debug, Clone, Copy)]
+pub struct ParticleStamp {
+    pub xy: Vec2,
+    pub size: f32,
+    pub color: [f32; 4],
+    pub shape: ParticleShape,
+    /// The direction the shape points in radians, counterclockwise from +x
+    pub angle: f32,
+    /// How much longer than wide speed stretches directional shapes, at least 1
+    pub stretch: f32,
+    /// Width over height of the brush, for sprites
+    pub aspect: f32,
+}
+
+impl ParticleStamp {
+    /// The particle as `model` draws it now
+    pub fn from_particle(particle: &FlowParticle, model: &Model) -> Self {
+        let lifetime = &model.lifetime;
+        let life = particle.life(model.particle_lifetime);
+        let step = particle.xy - particle.previous_xy;
+        let spin = (model.particle_spin * particle.frames_alive()).to_radians();
+        Self {
+            xy: particle.xy,
+            // Scale size based on weight
+            size: particle.weight() * 2.0 * lifetime.size_at(life),
+            color: [
+                particle.color.r() as f32 / 255.0,
+                particle.color.g() as f32 / 255.0,
+                particle.color.b() as f32 / 255.0,
+                particle.color.a() as f32 / 255.0 * lifetime.opacity_at(life),
+            ],
+            shape: particle.shape,
+            angle: step.y.atan2(step.x) + spin + particle.rotation_jitter,
+            stretch: (1.0 + step.length() * STRETCH_PER_SPEED).min(MAX_STRETCH),
+            aspect: {
+                let (width, height) = model.brush.dimensions();
+                width as f32 / height as f32
+            },
+        }
+    }
+
+    /// How far from `xy` the stamp's shape can reach
+    pub fn reach(&self) -> f32 {
+        match self.shape {
+            ParticleShape::Comet => self.size * 2.0 * self.stretch,
+            ParticleShape::Ellipse => self.size * self.stretch,
+            // Triangle corners stick out the furthest, at about 1.32 times the size
+            _ => self.size * 1.5,
+        }
+    }
+}
+
+// How many of the latest frames still show once trails fade by `trail_fade` every frame. Older
+// frames have faded below 1/255. Trails that never fade show every frame.
+fn visible_frames(trail_fade: f32) -> usize {
+    let keep = 1.0 - trail_fade.clamp(0.0, 1.0);
+    if keep >= 1.0 {
+        return usize::MAX;
+    }
+    let oldest_visible_age = (1.0f32 / 255.0).ln() / keep.ln();
+    (oldest_visible_age.floor() as usize).saturating_add(1)
+}
+
+/// Everything drawn onto the canvas, kept as geometry so it can be redrawn at any resolution. Only
+/// the latest frames are kept once there are too many to hold, and the recording is then
+/// truncated: it no longer matches what was drawn.
+pub struct Recording {
+    pub canvas_size: Vec2,
+    pub background: Background,
+    pub flow_vectors: Vec<FlowVector>,
+    pub grid_width: usize,
+    pub vector_display_stride: usize,
+    // Every particle of every frame kept, oldest first
+    stamps: VecDeque<ParticleStamp>,
+    // How many stamps each frame kept has, oldest first
+    frame_lengths: VecDeque<usize>,
+    // The most stamps kept before the oldest frames are dropped
+    max_stamps: usize,
+    // Whether frames that still showed have been dropped
+    truncated: bool,
+    pub trail_fade: f32,
+    pub density: DensitySettings,
+    pub blend_mode: BlendMode,
+    /// The LUT density mode colors with
+    pub lut: Option<LutData>,
+    pub post: PostSettings,
+    pub brush: Brush,
+}
+
+impl Recording {
+    pub fn new(model: &Model) -> Self {
+        let mut recording = Self {
+            canvas_size: Vec2::new(model.window_rect.width(), model.window_rect.height()),
+            background: model.background,
+            flow_vectors: Vec::new(),
+            grid_width: 0,
+            vector_display_stride: 1,
+            stamps: VecDeque::new(),
+            frame_lengths: VecDeque::new(),
+            max_stamps: MAX_RECORDED_PARTICLE_STAMPS,
+            truncated: false,
+            trail_fade: 0.0,
+            density: DensitySettings::default(),
+            blend_mode: BlendMode::default(),
+            lut: None,
+            post: PostSettings::default(),
+            brush: Brush::default(),
+        };
+        recording.capture_field(model);
+        recording
+    }
+
+    /// Record the particles as they're drawn this frame, dropping the oldest frames once they've
+    /// faded away or there are too many to keep
+    pub fn record_frame(&mut self, model: &Model) {
+        let length = model.flow_particles.len().min(self.max_stamps);
+        self.stamps.extend(
+            model.flow_particles[..length]
+                .iter()
+                .map(|particle| ParticleStamp::from_particle(particle, model)),
+        );
+        self.frame_lengths.push_back(length);
+
+        // Frames that have faded away are of no more use
+        let max_frames = visible_frames(model.trail_fade);
+        while self.frame_lengths.len() > max_frames {
+            let oldest = self.frame_lengths.pop_front().unwrap_or_default();
+            self.stamps.drain(..oldest);
+        }
+
+        let mut dropped = false;
+        while self.stamps.len() > self.max_stamps || self.frame_lengths.len() > MAX_RECORDED_FRAMES {
+            let oldest = self.frame_lengths.pop_front().unwrap_or_default();
+            self.stamps.drain(..oldest);
+            dropped = true;
+        }
+        if dropped && !self.truncated {
+            warn!(
+                "Recorded more than {} particles or {} frames, so the oldest frames are being dropped",
+                self.max_stamps, MAX_RECORDED_FRAMES
+            );
+            self.truncated = true;
+        }
+    }
+
+    /// True once frames that still showed have been dropped, so redrawing the recording would
+    /// leave out some of what's on the canvas
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Record the background, the field underneath the particles and how the particles are drawn
+    pub fn capture_field(&mut self, model: &Model) {
+        self.background = model.background;
+        self.flow_vectors = model.flow_vectors.clone();
+        self.grid_width = model.grid_width;
+        self.vector_display_stride = model.vector_display_stride();
+        self.trail_fade = model.trail_fade;
+        self.density = model.density;
+        self.blend_mode = model.blend_mode;
+        self.lut = model.lut_manager.load_lut(&model.current_lut).ok();
+        self.post = model.post;
+        self.brush = model.brush.clone();
+    }
+
+    /// The stamps of each frame kept in turn, oldest first
+    pub fn frames(&self) -> impl Iterator<Item = vec_deque::Iter<'_, ParticleStamp>> {
+        self.frame_lengths.iter().scan(0, |start, &length| {
+            let frame = self.stamps.range(*start..*start + length);
+            *start += length;
+            Some(frame)
+        })
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_lengths.len()
+    }
+
+    /// A copy of `model`'s scene to run from scratch. The same seed always produces the same
+    /// simulation.
+    pub fn simulation(model: &Model, seed: u64) -> Model {
+        let canvas_size = Vec2::new(model.window_rect.width(), model.window_rect.height());
+        let mut simulation = Model::new(canvas_size);
+        Preset::from_model(model).apply(&mut simulation);
+        simulation.rng = StdRng::seed_from_u64(seed);
+        simulation.automatically_spawn_particles = model.automatically_spawn_particles;
+        simulation.particle_auto_spawn_limit = model.particle_auto_spawn_limit;
+        // Keep sculpted edits and the fluid's current state, which presets don't capture
+        if simulation.flow_vectors.len() == model.flow_vectors.len() {
+            simulation.flow_vectors = model.flow_vectors.clone();
+        }
+        simulation.fluid = model.fluid.clone();
+        simulation
+    }
+
+    /// Run `simulation` for `frames` frames, recording each of them
+    pub fn simulate(mut simulation: Model, frames: u32) -> Self {
+        let mut recording = Self::new(&simulation);
+        recording.max_stamps = MAX_SIMULATED_PARTICLE_STAMPS;
+        for _ in 0..frames {
+            update(&mut simulation);
+            recording.record_frame(&simulation);
+        }
+        recording.capture_field(&simulation);
+        recording
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_the_oldest_frames_to_stay_within_bounds() {
+        let mut model = Model::new(Vec2::new(320.0, 180.0));
+        model.automatically_spawn_particles = true;
+        model.rng = StdRng::seed_from_u64(1);
+        let mut recording = Recording::new(&model);
+        recording.max_stamps = 500;
+        let mut lengths = Vec::new();
+        for _ in 0..200 {
+            update(&mut model);
+            recording.record_frame(&model);
+            lengths.push(model.flow_particles.len().min(500));
+        }
+        assert!(lengths.iter().sum::<usize>() > 500, "not enough particles spawned to test with");
+
+        // Only whole frames are dropped, the oldest first
+        assert!(recording.stamps.len() <= 500);
+        let kept: Vec<usize> = recording.frames().map(|frame| frame.len()).collect();
+        assert_eq!(kept, lengths[lengths.len() - kept.len()..]);
+        assert_eq!(kept.iter().sum::<usize>(), recording.stamps.len());
+        assert!(recording.stamps.len() + lengths[lengths.len() - kept.len() - 1] > 500);
+        assert!(recording.is_truncated());
+    }
+
+    #[test]
+    fn drops_frames_once_they_fade_away() {
+        assert_eq!(visible_frames(0.0), usize::MAX);
+        assert_eq!(visible_frames(1.0), 1);
+        // 0.5^7 is 1/128 and 0.5^8 is 1/256
+        assert_eq!(visible_frames(0.5), 8);
+        let keep = 1.0f32 - 0.02;
+        let frames = visible_frames(0.02);
+        assert!(keep.powi(frames as i32 - 1) >= 1.0 / 255.0 && keep.powi(frames as i32) < 1.0 / 255.0);
+
+        let mut model = Model::new(Vec2::new(320.0, 180.0));
+        model.trail_fade = 0.5;
+        let mut recording = Recording::new(&model);
+        for _ in 0..20 {
+            recording.record_frame(&model);
+        }
+        assert_eq!(recording.frame_count(), 8);
+        // Those frames had faded out of sight anyway
+        assert!(!recording.is_truncated());
+    }
+}
@@ -1,17 +1,28 @@
 use egui_wgpu::wgpu;
-use crate::model::enums::ParticleShape;
+use crate::model::enums::BlendMode;
+use crate::brush::{Brush, BrushTexture};
+use crate::density::DensityBuffer;
+use crate::post::{PostFrame, PostProcessor};
+use crate::particle_mesh::{InstanceBuffer, ParticleInstance, ShapeMeshes, ShapeVertex};
+use crate::recording::ParticleStamp;
 
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
     pub position: [f32; 2],
     pub color: [f32; 4],
+    /// Where on the brush texture the vertex lies, or `NO_UV` for flat color
+    pub uv: [f32; 2],
 }
 
+/// Marks vertices drawn in flat color, without the brush
+pub const NO_UV: [f32; 2] = [-1.0, -1.0];
+
 impl Vertex {
-    pub const ATTRIBS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+    pub const ATTRIBS: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
         0 => Float32x2, // position
         1 => Float32x4, // color
+        2 => Float32x2, // uv
     ];
 
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
@@ -23,13 +34,343 @@ impl Vertex {
     }
 }
 
+// `shader.wgsl` premultiplies the vertex colors, and the layers hold premultiplied color as the
+// composite expects
+pub const LAYER_BLENDING: wgpu::BlendState = wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING;
+
+const OVER: wgpu::BlendComponent = wgpu::BlendComponent {
+    src_factor: wgpu::BlendFactor::One,
+    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+    operation: wgpu::BlendOperation::Add,
+};
+
+const ADD: wgpu::BlendComponent = wgpu::BlendComponent {
+    src_factor: wgpu::BlendFactor::One,
+    dst_factor: wgpu::BlendFactor::One,
+    operation: wgpu::BlendOperation::Add,
+};
+
+const MAX: wgpu::BlendComponent = wgpu::BlendComponent {
+    src_factor: wgpu::BlendFactor::One,
+    dst_factor: wgpu::BlendFactor::One,
+    operation: wgpu::BlendOperation::Max,
+};
+
+/// How particles drawn in `mode` blend with each other on their own transparent layer. Multiply
+/// needs what's underneath to mean anything, so it only applies when the layer is composited.
+pub fn particle_layer_blending(mode: BlendMode) -> wgpu::BlendState {
+    let color = match mode {
+        BlendMode::Normal | BlendMode::Multiply => OVER,
+        BlendMode::Additive | BlendMode::Subtractive => ADD,
+        BlendMode::Screen => wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::OneMinusSrc,
+            operation: wgpu::BlendOperation::Add,
+        },
+        BlendMode::Lighten => MAX,
+    };
+    let alpha = match mode {
+        BlendMode::Additive | BlendMode::Subtractive => ADD,
+        BlendMode::Lighten => MAX,
+        _ => OVER,
+    };
+    wgpu::BlendState { color, alpha }
+}
+
+/// How a particle layer drawn in `mode` blends onto the layers underneath. `composite_particles`
+/// in `quad.wgsl` does the same in the shader.
+pub fn particle_composite_blending(mode: BlendMode) -> wgpu::BlendState {
+    let color = match mode {
+        BlendMode::Normal => OVER,
+        BlendMode::Additive => ADD,
+        BlendMode::Screen => particle_layer_blending(BlendMode::Screen).color,
+        BlendMode::Multiply => wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Dst,
+            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+            operation: wgpu::BlendOperation::Add,
+        },
+        BlendMode::Lighten => MAX,
+        BlendMode::Subtractive => wgpu::BlendComponent {
+            operation: wgpu::BlendOperation::ReverseSubtract,
+            ..ADD
+        },
+    };
+    wgpu::BlendState { color, alpha: OVER }
+}
+
+/// A pipeline that draws `Vertex` geometry through the view transform in `shader.wgsl`
+pub fn create_geometry_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    topology: wgpu::PrimitiveTopology,
+    blend: wgpu::BlendState,
+) -> wgpu::RenderPipeline {
+    let vertex = wgpu::VertexState {
+        module: shader,
+        entry_point: "vs_main",
+        buffers: &[Vertex::desc()],
+    };
+    create_shader_pipeline(device, label, layout, vertex, format, topology, blend)
+}
+
+/// A pipeline that draws `ParticleInstance`s of the `ShapeMeshes` through the view transform in
+/// `shader.wgsl`
+pub fn create_particle_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    blend: wgpu::BlendState,
+) -> wgpu::RenderPipeline {
+    let vertex = wgpu::VertexState {
+        module: shader,
+        entry_point: "vs_particle",
+        buffers: &[ShapeVertex::desc(), ParticleInstance::desc()],
+    };
+    create_shader_pipeline(device, label, layout, vertex, format, wgpu::PrimitiveTopology::TriangleList, blend)
+}
+
+// Either of the above, which share the fragment shader
+fn create_shader_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    vertex: wgpu::VertexState,
+    format: wgpu::TextureFormat,
+    topology: wgpu::PrimitiveTopology,
+    blend: wgpu::BlendState,
+) -> wgpu::RenderPipeline {
+    let shader = vertex.module;
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex,
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+/// A pipeline that fades a layer by the blend constant, less the small floor from `fade.wgsl` when
+/// `floor` is set. Only 8-bit layers need the floor, and float layers would go negative with it.
+pub fn create_fade_pipeline(device: &wgpu::Device, format: wgpu::TextureFormat, floor: bool) -> wgpu::RenderPipeline {
+    let fade_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Fade Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("shaders/fade.wgsl").into()),
+    });
+    let fade_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Fade Pipeline Layout"),
+        bind_group_layouts: &[],
+        push_constant_ranges: &[],
+    });
+    let fade_blend = wgpu::BlendComponent {
+        src_factor: if floor { wgpu::BlendFactor::One } else { wgpu::BlendFactor::Zero },
+        dst_factor: wgpu::BlendFactor::Constant,
+        operation: wgpu::BlendOperation::ReverseSubtract,
+    };
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Fade Pipeline"),
+        layout: Some(&fade_pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &fade_shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &fade_shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState {
+                    color: fade_blend,
+                    alpha: fade_blend,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+/// Read a texture back from the GPU as tightly packed RGBA rows, swapping the channels of BGRA
+/// textures
+pub fn read_texture_rgba(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> std::io::Result<Vec<u8>> {
+    // Copy at most this many bytes at a time so large textures stay under the buffer size limit
+    const BAND_BYTES: u64 = 64 * 1024 * 1024;
+
+    let (width, height) = (texture.width(), texture.height());
+    let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_row_bytes = (width * 4).div_ceil(alignment) * alignment;
+    let band_rows = (BAND_BYTES.min(device.limits().max_buffer_size) / padded_row_bytes as u64).clamp(1, height as u64) as u32;
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: (padded_row_bytes * band_rows) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for band_top in (0..height).step_by(band_rows as usize) {
+        let rows = band_rows.min(height - band_top);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: band_top, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(rows),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height: rows,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..(padded_row_bytes * rows) as wgpu::BufferAddress);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .map_err(std::io::Error::other)?
+            .map_err(std::io::Error::other)?;
+        for row in slice.get_mapped_range().chunks(padded_row_bytes as usize) {
+            pixels.extend_from_slice(&row[..width as usize * 4]);
+        }
+        buffer.unmap();
+    }
+
+    if matches!(texture.format(), wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb) {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+    Ok(pixels)
+}
+
+/// The layers composited into the final image, bottom to top
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layer {
+    Background,
+    Vectors,
+    Particles,
+}
+
+impl Layer {
+    pub const ALL: [Self; 3] = [Self::Background, Self::Vectors, Self::Particles];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Background => "background",
+            Self::Vectors => "vectors",
+            Self::Particles => "particles",
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct CompositeUniforms {
+    particle_blend: u32,
+    _padding: [u32; 3],
+}
+
+// Samples the finished canvas for the present pass
+fn create_present_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    view: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("Present Sampler"),
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        ..Default::default()
+    });
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Present Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&sampler),
+            },
+        ],
+    })
+}
+
+// What the vector field's geometry was built from: the model's field revision and the stride
+// between the vectors drawn
+#[derive(Clone, Copy, PartialEq)]
+struct VectorGeometry {
+    field_revision: u64,
+    stride: usize,
+}
+
 pub struct Renderer {
-    // Particle rendering pipeline
-    particle_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
+    // Particle rendering pipelines, one for each blend mode in `BlendMode::ALL` order
+    particle_pipelines: Vec<wgpu::RenderPipeline>,
+    // Particles are drawn as instances of their shape's mesh, gathered anew every frame
+    shape_meshes: ShapeMeshes,
+    instance_buffer: InstanceBuffer,
+    instances: Vec<ParticleInstance>,
     uniform_buffer: wgpu::Buffer,
     uniform_bind_group: wgpu::BindGroup,
-    num_vertices: u32,
     
     // Layer textures
     background_texture: wgpu::Texture,
@@ -45,19 +386,43 @@ pub struct Renderer {
     particle_bind_group: wgpu::BindGroup,
     particle_texture_bind_group: wgpu::BindGroup,
     
-    // MSAA textures
-    msaa_texture: wgpu::Texture,
-    msaa_view: wgpu::TextureView,
-    resolve_texture: wgpu::Texture,
-    resolve_view: wgpu::TextureView,
-    
     // Background quad rendering
     quad_pipeline: wgpu::RenderPipeline,
+    fade_pipeline: wgpu::RenderPipeline,
+    // Trail fading saved up from frames too subtle to fade on their own, as -ln(fraction kept)
+    pending_fade: f64,
+    // Where particles accumulate in density mode, and the LUT it was last colored with
+    density: DensityBuffer,
+    density_lut: Option<String>,
+    // What sprite particles are stamped with, and where it was loaded from
+    brush_texture: BrushTexture,
+    brush_bind_group_layout: wgpu::BindGroupLayout,
+    brush_path: Option<std::path::PathBuf>,
+    composite_buffer: wgpu::Buffer,
+    composite_bind_group: wgpu::BindGroup,
 
-    // Vector field rendering
+    // The layers are composited into the post-processing chain at canvas size, and its result is
+    // fitted into the window
+    post: PostProcessor,
+    present_pipeline: wgpu::RenderPipeline,
+    present_buffer: wgpu::Buffer,
+    present_bind_group: wgpu::BindGroup,
+    present_texture_bind_group: wgpu::BindGroup,
+    present_texture_bind_group_layout: wgpu::BindGroupLayout,
+
+    // Vector field and fluid dye rendering
     vector_pipeline: wgpu::RenderPipeline,
+    dye_pipeline: wgpu::RenderPipeline,
+    // Regrown when the dye covers more cells than it has room for
+    dye_buffer: wgpu::Buffer,
+    dye_capacity: usize,
+    num_dye_vertices: u32,
+    // The vector field's geometry, rebuilt only when the field changes, and what the vector layer
+    // shows, None when it's transparent. The layer is only redrawn when the two differ.
     vector_buffer: wgpu::Buffer,
     num_vector_vertices: u32,
+    vector_geometry: Option<VectorGeometry>,
+    vector_layer: Option<VectorGeometry>,
 
     // Bind group layouts
     background_bind_group_layout: wgpu::BindGroupLayout,
@@ -65,14 +430,15 @@ pub struct Renderer {
     particle_bind_group_layout: wgpu::BindGroupLayout,
     particle_texture_bind_group_layout: wgpu::BindGroupLayout,
 
-    // Size
+    // Canvas size and the layers' texture format
     size: (u32, u32),
+    format: wgpu::TextureFormat,
 
     particle_uniform_buffer: wgpu::Buffer,
 }
 
 impl Renderer {
-    pub fn new(device: &wgpu::Device, surface_format: wgpu::TextureFormat, size: (u32, u32)) -> Self {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, surface_format: wgpu::TextureFormat, size: (u32, u32)) -> Self {
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Particle Shader"),
             source: wgpu::ShaderSource::Wgsl(include_str!("shaders/shader.wgsl").into()),
@@ -83,10 +449,10 @@ impl Renderer {
             source: wgpu::ShaderSource::Wgsl(include_str!("shaders/quad.wgsl").into()),
         });
 
-        // Create uniform buffer for resolution
+        // Create uniform buffer for the view transform
         let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Uniform Buffer"),
-            size: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+            size: std::mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
@@ -183,6 +549,35 @@ impl Renderer {
             ],
         });
 
+        // Create the uniform that picks the particles' blend mode
+        let composite_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Composite Buffer"),
+            size: std::mem::size_of::<CompositeUniforms>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let composite_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Composite Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let composite_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Composite Bind Group"),
+            layout: &composite_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: composite_buffer.as_entire_binding(),
+            }],
+        });
+
         // Create quad pipeline layout
         let quad_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Quad Pipeline Layout"),
@@ -190,6 +585,7 @@ impl Renderer {
                 &background_bind_group_layout,
                 &vector_bind_group_layout,
                 &particle_texture_bind_group_layout,
+                &composite_bind_group_layout,
             ],
             push_constant_ranges: &[],
         });
@@ -228,128 +624,15 @@ impl Renderer {
             multiview: None,
         });
 
-        // Particle pipeline bind group layout (for uniforms)
-        let particle_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Particle Bind Group Layout"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-            ],
-        });
-        let particle_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Particle Pipeline Layout"),
-            bind_group_layouts: &[&particle_bind_group_layout],
-            push_constant_ranges: &[],
-        });
-        let particle_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Particle Pipeline"),
-            layout: Some(&particle_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[wgpu::VertexBufferLayout {
-                    array_stride: std::mem::size_of::<[f32; 6]>() as u64,
-                    step_mode: wgpu::VertexStepMode::Vertex,
-                    attributes: &[
-                        wgpu::VertexAttribute {
-                            offset: 0,
-                            shader_location: 0,
-                            format: wgpu::VertexFormat::Float32x2,
-                        },
-                        wgpu::VertexAttribute {
-                            offset: std::mem::size_of::<[f32; 2]>() as u64,
-                            shader_location: 1,
-                            format: wgpu::VertexFormat::Float32x4,
-                        },
-                    ],
-                }],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
-        
-        // Create MSAA texture for supersampling
-        let msaa_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("MSAA Texture"),
-            size: wgpu::Extent3d {
-                width: size.0,
-                height: size.1,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 4, // 4x MSAA
-            dimension: wgpu::TextureDimension::D2,
-            format: surface_format,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            view_formats: &[],
-        });
-        
-        let msaa_view = msaa_texture.create_view(&wgpu::TextureViewDescriptor::default());
-        
-        // Create resolve texture for MSAA resolution
-        let resolve_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Resolve Texture"),
-            size: wgpu::Extent3d {
-                width: size.0,
-                height: size.1,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: surface_format,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-            view_formats: &[],
-        });
-        
-        let resolve_view = resolve_texture.create_view(&wgpu::TextureViewDescriptor::default());
-        
-        // Create sampler for resolve texture
-        let resolve_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: Some("Resolve Sampler"),
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+        let post = PostProcessor::new(device, surface_format, size);
 
-        // Create bind group for resolve texture
-        let resolve_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Resolve Bind Group Layout"),
+        // Create the pipeline that fits the finished canvas into the window
+        let present_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Present Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/present.wgsl").into()),
+        });
+        let present_texture_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Present Texture Bind Group Layout"),
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
@@ -369,126 +652,111 @@ impl Renderer {
                 },
             ],
         });
-
-        device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Resolve Bind Group"),
-            layout: &resolve_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&resolve_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&resolve_sampler),
-                },
-            ],
-        });
-        
-        // Create sampler for trail texture
-        let trail_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: Some("Trail Sampler"),
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
+        let present_texture_bind_group =
+            create_present_bind_group(device, &present_texture_bind_group_layout, post.output_view());
+        let present_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Present Buffer"),
+            size: std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
-        
-        // Create bind group layout for quad rendering
-        let quad_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Quad Bind Group Layout"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-            ],
+        let present_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Present Uniform Bind Group"),
+            layout: &composite_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: present_buffer.as_entire_binding(),
+            }],
         });
-        
-       device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Quad Bind Group"),
-            layout: &quad_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&resolve_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&trail_sampler),
-                },
-            ],
+        let present_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Present Pipeline Layout"),
+            bind_group_layouts: &[&present_texture_bind_group_layout, &composite_bind_group_layout],
+            push_constant_ranges: &[],
         });
-        
-        // Create vector pipeline
-        let vector_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Vector Pipeline"),
-            layout: Some(&particle_pipeline_layout),
+        let present_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Present Pipeline"),
+            layout: Some(&present_pipeline_layout),
             vertex: wgpu::VertexState {
-                module: &shader,
+                module: &present_shader,
                 entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
+                buffers: &[],
             },
             fragment: Some(wgpu::FragmentState {
-                module: &shader,
+                module: &present_shader,
                 entry_point: "fs_main",
                 targets: &[Some(wgpu::ColorTargetState {
                     format: surface_format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    blend: None,
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
             }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::LineList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
+            primitive: wgpu::PrimitiveState::default(),
             depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
+            multisample: wgpu::MultisampleState::default(),
             multiview: None,
         });
 
-        // Get device limits
-        let max_buffer_size = device.limits().max_buffer_size;
+        let fade_pipeline = create_fade_pipeline(device, surface_format, true);
 
-        // Create empty vertex buffer
-        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Vertex Buffer"),
-            size: max_buffer_size,
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
+        // Particle pipeline bind group layout (for uniforms)
+        let particle_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Particle Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
         });
-
-        // Create empty vector buffer
-        let vector_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Vector Buffer"),
-            size: max_buffer_size,
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
+        let brush_bind_group_layout = BrushTexture::bind_group_layout(device);
+        let brush_texture = BrushTexture::new(device, queue, &brush_bind_group_layout, &Brush::soft_dot());
+        let particle_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Pipeline Layout"),
+            bind_group_layouts: &[&particle_bind_group_layout, &brush_bind_group_layout],
+            push_constant_ranges: &[],
         });
+        let particle_pipelines = BlendMode::ALL
+            .iter()
+            .map(|mode| {
+                create_particle_pipeline(
+                    device,
+                    &format!("{} Particle Pipeline", mode),
+                    &particle_pipeline_layout,
+                    &shader,
+                    surface_format,
+                    particle_layer_blending(*mode),
+                )
+            })
+            .collect();
+        let density = DensityBuffer::new(device, size, &particle_pipeline_layout, &shader, surface_format);
+        
+        // Create vector pipeline
+        let vector_pipeline = create_geometry_pipeline(
+            device,
+            "Vector Pipeline",
+            &particle_pipeline_layout,
+            &shader,
+            surface_format,
+            wgpu::PrimitiveTopology::LineList,
+            LAYER_BLENDING,
+        );
+
+        // The fluid's dye is drawn onto the background
+        let dye_pipeline = create_geometry_pipeline(
+            device,
+            "Dye Pipeline",
+            &particle_pipeline_layout,
+            &shader,
+            surface_format,
+            wgpu::PrimitiveTopology::TriangleList,
+            LAYER_BLENDING,
+        );
 
 
         // Create layer textures
@@ -503,7 +771,7 @@ impl Renderer {
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: surface_format,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         });
         
@@ -520,7 +788,7 @@ impl Renderer {
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: surface_format,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         });
         
@@ -537,7 +805,7 @@ impl Renderer {
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: surface_format,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         });
         
@@ -745,25 +1013,35 @@ impl Renderer {
         });
 
         Self {
-            particle_pipeline,
-            vertex_buffer,
+            particle_pipelines,
+            shape_meshes: ShapeMeshes::new(device, queue),
+            instance_buffer: InstanceBuffer::new(device),
+            instances: Vec::new(),
             uniform_buffer,
             uniform_bind_group,
-            num_vertices: 0,
             background_texture,
             background_view,
             vector_texture,
             vector_view,
             particle_texture,
             particle_view,
-            msaa_texture,
-            msaa_view,
-            resolve_texture,
-            resolve_view,
             quad_pipeline,
+            fade_pipeline,
+            pending_fade: 0.0,
+            density,
+            density_lut: None,
+            brush_texture,
+            brush_bind_group_layout,
+            brush_path: None,
             vector_pipeline,
-            vector_buffer,
+            dye_pipeline,
+            dye_buffer: Self::create_vertex_buffer(device, "Dye Buffer", 1),
+            dye_capacity: 1,
+            num_dye_vertices: 0,
+            vector_buffer: Self::create_vertex_buffer(device, "Vector Buffer", 1),
             num_vector_vertices: 0,
+            vector_geometry: None,
+            vector_layer: None,
             background_bind_group,
             vector_bind_group,
             particle_bind_group,
@@ -773,106 +1051,40 @@ impl Renderer {
             particle_bind_group_layout,
             particle_texture_bind_group_layout,
             size,
+            format: surface_format,
             particle_uniform_buffer,
+            composite_buffer,
+            composite_bind_group,
+            post,
+            present_pipeline,
+            present_buffer,
+            present_bind_group,
+            present_texture_bind_group,
+            present_texture_bind_group_layout,
         }
     }
 
-    fn generate_shape_vertices(
-        &self,
-        center_x: f32,
-        center_y: f32,
-        size: f32,
-        color: [f32; 4],
-        shape: ParticleShape,
+    // Draws every `stride`th vector along each axis of a grid `grid_width` vectors wide
+    pub fn generate_vector_vertices(
+        flow_vectors: &[crate::flow_vector::FlowVector],
+        grid_width: usize,
+        stride: usize,
     ) -> Vec<Vertex> {
-        match shape {
-            ParticleShape::Circle => {
-                // Generate a circle using multiple triangles
-                let segments = 16;
-                let mut vertices = Vec::with_capacity(segments * 3);
-                let angle_step = std::f32::consts::TAU / segments as f32;
-                
-                for i in 0..segments {
-                    let angle1 = i as f32 * angle_step;
-                    let angle2 = (i + 1) as f32 * angle_step;
-                    
-                    let x1 = center_x + size * angle1.cos();
-                    let y1 = center_y + size * angle1.sin();
-                    let x2 = center_x + size * angle2.cos();
-                    let y2 = center_y + size * angle2.sin();
-                    
-                    vertices.push(Vertex { position: [center_x, center_y], color });
-                    vertices.push(Vertex { position: [x1, y1], color });
-                    vertices.push(Vertex { position: [x2, y2], color });
-                }
-                vertices
-            }
-            ParticleShape::Square => {
-                let half_size = size * 0.5;
-                vec![
-                    Vertex { position: [center_x - half_size, center_y - half_size], color },
-                    Vertex { position: [center_x + half_size, center_y - half_size], color },
-                    Vertex { position: [center_x - half_size, center_y + half_size], color },
-                    Vertex { position: [center_x + half_size, center_y - half_size], color },
-                    Vertex { position: [center_x + half_size, center_y + half_size], color },
-                    Vertex { position: [center_x - half_size, center_y + half_size], color },
-                ]
-            }
-            ParticleShape::Triangle => {
-                let height = size * 0.866; // sqrt(3)/2
-                vec![
-                    Vertex { position: [center_x, center_y - size], color },
-                    Vertex { position: [center_x - size, center_y + height], color },
-                    Vertex { position: [center_x + size, center_y + height], color },
-                ]
-            }
-            ParticleShape::Star => {
-                let mut vertices = Vec::with_capacity(15);
-                let outer_radius = size;
-                let inner_radius = size * 0.4;
-                let points = 5;
-                let angle_step = std::f32::consts::TAU / points as f32;
-                
-                for i in 0..points {
-                    let angle1 = i as f32 * angle_step;
-                    let angle2 = angle1 + angle_step * 0.5;
-                    
-                    // Outer point
-                    let x1 = center_x + outer_radius * angle1.cos();
-                    let y1 = center_y + outer_radius * angle1.sin();
-                    
-                    // Inner point
-                    let x2 = center_x + inner_radius * angle2.cos();
-                    let y2 = center_y + inner_radius * angle2.sin();
-                    
-                    vertices.push(Vertex { position: [center_x, center_y], color });
-                    vertices.push(Vertex { position: [x1, y1], color });
-                    vertices.push(Vertex { position: [x2, y2], color });
-                }
-                vertices
-            }
-            ParticleShape::Diamond => {
-                let half_size = size * 0.5;
-                vec![
-                    Vertex { position: [center_x, center_y - size], color },
-                    Vertex { position: [center_x + half_size, center_y], color },
-                    Vertex { position: [center_x, center_y + size], color },
-                    Vertex { position: [center_x, center_y - size], color },
-                    Vertex { position: [center_x, center_y + size], color },
-                    Vertex { position: [center_x - half_size, center_y], color },
-                ]
-            }
-        }
-    }
-
-    fn generate_vector_vertices(&self, flow_vectors: &[crate::flow_vector::FlowVector]) -> Vec<Vertex> {
         let mut vertices = Vec::new();
         let white = [1.0, 1.0, 1.0, 1.0]; // White color with full opacity
         let line_width = 2.0; // Width of the line
         let num_segments = 8; // Number of segments for the rounded caps
         let length_scale = 2.0; // Scale factor for line length
 
-        for vector in flow_vectors {
+        let grid_width = grid_width.max(1);
+        let displayed = flow_vectors
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| {
+                (index % grid_width) % stride == 0 && (index / grid_width) % stride == 0
+            })
+            .map(|(_, vector)| vector);
+        for vector in displayed {
             let pos = vector.position();
             let dir = vector.direction();
             let mag = dir.length();
@@ -897,13 +1109,13 @@ impl Renderer {
             let p4 = [end_x + perp_x * half_width, end_y + perp_y * half_width];
             
             // Add the main rectangle
-            vertices.push(Vertex { position: p1, color: white });
-            vertices.push(Vertex { position: p2, color: white });
-            vertices.push(Vertex { position: p3, color: white });
+            vertices.push(Vertex { position: p1, color: white, uv: NO_UV });
+            vertices.push(Vertex { position: p2, color: white, uv: NO_UV });
+            vertices.push(Vertex { position: p3, color: white, uv: NO_UV });
             
-            vertices.push(Vertex { position: p1, color: white });
-            vertices.push(Vertex { position: p3, color: white });
-            vertices.push(Vertex { position: p4, color: white });
+            vertices.push(Vertex { position: p1, color: white, uv: NO_UV });
+            vertices.push(Vertex { position: p3, color: white, uv: NO_UV });
+            vertices.push(Vertex { position: p4, color: white, uv: NO_UV });
             
             // Add rounded caps at both ends
             for i in 0..num_segments {
@@ -916,9 +1128,9 @@ impl Renderer {
                 let start_cap_x2 = pos.x + perp_x * half_width * angle2.cos();
                 let start_cap_y2 = pos.y + perp_y * half_width * angle2.cos();
                 
-                vertices.push(Vertex { position: [pos.x, pos.y], color: white });
-                vertices.push(Vertex { position: [start_cap_x1, start_cap_y1], color: white });
-                vertices.push(Vertex { position: [start_cap_x2, start_cap_y2], color: white });
+                vertices.push(Vertex { position: [pos.x, pos.y], color: white, uv: NO_UV });
+                vertices.push(Vertex { position: [start_cap_x1, start_cap_y1], color: white, uv: NO_UV });
+                vertices.push(Vertex { position: [start_cap_x2, start_cap_y2], color: white, uv: NO_UV });
                 
                 // End cap
                 let end_cap_x1 = end_x + perp_x * half_width * angle1.cos();
@@ -926,28 +1138,133 @@ impl Renderer {
                 let end_cap_x2 = end_x + perp_x * half_width * angle2.cos();
                 let end_cap_y2 = end_y + perp_y * half_width * angle2.cos();
                 
-                vertices.push(Vertex { position: [end_x, end_y], color: white });
-                vertices.push(Vertex { position: [end_cap_x1, end_cap_y1], color: white });
-                vertices.push(Vertex { position: [end_cap_x2, end_cap_y2], color: white });
+                vertices.push(Vertex { position: [end_x, end_y], color: white, uv: NO_UV });
+                vertices.push(Vertex { position: [end_cap_x1, end_cap_y1], color: white, uv: NO_UV });
+                vertices.push(Vertex { position: [end_cap_x2, end_cap_y2], color: white, uv: NO_UV });
             }
         }
 
         vertices
     }
 
+    // One quad per fluid cell, colored from the current LUT by how much dye it holds
+    fn generate_dye_vertices(&self, model: &crate::model::Model) -> Vec<Vertex> {
+        let Some(fluid) = &model.fluid else {
+            return Vec::new();
+        };
+        if model.flow_vector_field_builder_type != crate::flow_vector::FlowVectorFieldBuilder::Fluid
+            || fluid.dimensions() != (model.grid_width, model.grid_height)
+        {
+            return Vec::new();
+        }
+        let Ok(lut_data) = model.lut_manager.load_lut(&model.current_lut) else {
+            return Vec::new();
+        };
+
+        let (origin_x, origin_y) = model.get_origin();
+        let half_size = model.vector_spacing / 2.0;
+        let mut vertices = Vec::new();
+        for column_index in 0..model.grid_height {
+            for row_index in 0..model.grid_width {
+                let dye = fluid.dye(row_index, column_index).clamp(0.0, 1.0);
+                // Skip cells too faint to see
+                if dye < 0.01 {
+                    continue;
+                }
+
+                let color_index = (dye * (lut_data.red.len() - 1) as f32) as usize;
+                let color = [
+                    lut_data.red[color_index] as f32 / 255.0,
+                    lut_data.green[color_index] as f32 / 255.0,
+                    lut_data.blue[color_index] as f32 / 255.0,
+                    dye,
+                ];
+                let x = row_index as f32 * model.vector_spacing + origin_x;
+                let y = column_index as f32 * model.vector_spacing + origin_y;
+                let (left, right) = (x - half_size, x + half_size);
+                let (bottom, top) = (y - half_size, y + half_size);
+                vertices.extend([
+                    Vertex { position: [left, bottom], color, uv: NO_UV },
+                    Vertex { position: [right, bottom], color, uv: NO_UV },
+                    Vertex { position: [right, top], color, uv: NO_UV },
+                    Vertex { position: [left, bottom], color, uv: NO_UV },
+                    Vertex { position: [right, top], color, uv: NO_UV },
+                    Vertex { position: [left, top], color, uv: NO_UV },
+                ]);
+            }
+        }
+
+        vertices
+    }
+
+    /// Upload `brush` for sprite particles, unless it's the one already in use
+    pub fn update_brush(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, brush: &Brush) {
+        if brush.path() == self.brush_path.as_deref() {
+            return;
+        }
+        self.brush_texture = BrushTexture::new(device, queue, &self.brush_bind_group_layout, brush);
+        self.brush_path = brush.path().map(|path| path.to_path_buf());
+    }
+
+    /// Upload what `render` draws this frame: the particles, the fluid's dye, and the vector
+    /// field's geometry whenever the field has changed since it was last built
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, model: &crate::model::Model) {
+        let dye_vertices = self.generate_dye_vertices(model);
+        if dye_vertices.len() > self.dye_capacity {
+            self.dye_capacity = dye_vertices.len().next_power_of_two();
+            self.dye_buffer = Self::create_vertex_buffer(device, "Dye Buffer", self.dye_capacity);
+        }
+        queue.write_buffer(&self.dye_buffer, 0, bytemuck::cast_slice(&dye_vertices));
+        self.num_dye_vertices = dye_vertices.len() as u32;
+
+        if model.background == crate::model::enums::Background::Vectors {
+            let geometry = VectorGeometry {
+                field_revision: model.field_revision,
+                stride: model.vector_display_stride(),
+            };
+            if self.vector_geometry != Some(geometry) {
+                let vertices = Self::generate_vector_vertices(&model.flow_vectors, model.grid_width, geometry.stride);
+                self.vector_buffer = Self::create_vertex_buffer(device, "Vector Buffer", vertices.len());
+                queue.write_buffer(&self.vector_buffer, 0, bytemuck::cast_slice(&vertices));
+                self.num_vector_vertices = vertices.len() as u32;
+                self.vector_geometry = Some(geometry);
+            }
+        }
+
+        self.instances.clear();
+        self.instances.extend(
+            model
+                .flow_particles
+                .iter()
+                .map(|particle| ParticleInstance::from_stamp(&ParticleStamp::from_particle(particle, model))),
+        );
+        let uploaded = self.instance_buffer.write(device, queue, &self.instances).len();
+        self.instances.truncate(uploaded);
+    }
+
     pub fn render(
         &mut self,
         queue: &wgpu::Queue,
         encoder: &mut wgpu::CommandEncoder,
         view: &wgpu::TextureView,
-        background: crate::model::enums::Background,
-        flow_vectors: &[crate::flow_vector::FlowVector],
-        flow_particles: &[crate::flow_particle::FlowParticle],
-        size: (u32, u32),
+        surface_size: (u32, u32),
+        model: &crate::model::Model,
         should_clear: bool,
     ) {
-        // Update uniform buffer with new resolution
-        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[size.0 as f32, size.1 as f32]));
+        let background = model.background;
+
+        // Draw into the layers at canvas size. The camera only applies when presenting them.
+        let canvas_size = glam::Vec2::new(self.size.0 as f32, self.size.1 as f32);
+        let window_size = glam::Vec2::new(surface_size.0 as f32, surface_size.1 as f32);
+        let projection = crate::camera::canvas_projection(canvas_size);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&projection.to_cols_array()));
+        let composite = CompositeUniforms {
+            particle_blend: model.blend_mode as u32,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.composite_buffer, 0, bytemuck::bytes_of(&composite));
+        let present = model.camera.present_transform(canvas_size, window_size);
+        queue.write_buffer(&self.present_buffer, 0, bytemuck::cast_slice(&present));
 
         // Clear all textures if needed
         if should_clear {
@@ -1005,9 +1322,24 @@ impl Renderer {
                     occlusion_query_set: None,
                     timestamp_writes: None,
                 });
+
+            self.density.clear(encoder);
         }
 
-        // Render background color or vectors
+        // In density mode the particles accumulate in the density buffer, which is then tone
+        // mapped onto the particle layer
+        let density_mode = model.density.enabled;
+        if density_mode {
+            self.density.set_settings(queue, &model.density);
+            if self.density_lut.as_ref() != Some(&model.current_lut) {
+                if let Ok(lut_data) = model.lut_manager.load_lut(&model.current_lut) {
+                    self.density.set_lut(queue, &lut_data);
+                }
+                self.density_lut = Some(model.current_lut.clone());
+            }
+        }
+
+        // Render the background color and the fluid's dye
         {
             let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Background"),
@@ -1043,45 +1375,91 @@ impl Renderer {
                 timestamp_writes: None,
             });
 
-            // If in vector mode, render the vector field
-            if background == crate::model::enums::Background::Vectors {
-                // Generate vector vertices with white color
-                let vector_vertices = self.generate_vector_vertices(flow_vectors);
-                queue.write_buffer(&self.vector_buffer, 0, bytemuck::cast_slice(&vector_vertices));
-                self.num_vector_vertices = vector_vertices.len() as u32;
+            if self.num_dye_vertices > 0 {
+                rpass.set_pipeline(&self.dye_pipeline);
+                rpass.set_bind_group(0, &self.uniform_bind_group, &[]);
+                rpass.set_bind_group(1, self.brush_texture.bind_group(), &[]);
+                rpass.set_vertex_buffer(0, self.dye_buffer.slice(..));
+                rpass.draw(0..self.num_dye_vertices, 0..1);
+            }
+        }
 
+        // Render the vector field onto its own layer, unless it's there already
+        let vector_layer = if background == crate::model::enums::Background::Vectors {
+            self.vector_geometry
+        } else {
+            None
+        };
+        if should_clear {
+            self.vector_layer = None;
+        }
+        if vector_layer != self.vector_layer {
+            self.vector_layer = vector_layer;
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Vectors"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.vector_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            if vector_layer.is_some() {
                 rpass.set_pipeline(&self.vector_pipeline);
                 rpass.set_bind_group(0, &self.uniform_bind_group, &[]);
+                rpass.set_bind_group(1, self.brush_texture.bind_group(), &[]);
                 rpass.set_vertex_buffer(0, self.vector_buffer.slice(..));
                 rpass.draw(0..self.num_vector_vertices, 0..1);
             }
         }
 
-        // Update vertex buffer with particle data
-        if !flow_particles.is_empty() {
-            let mut vertices = Vec::new();
-            for particle in flow_particles {
-                let color = [
-                    particle.color.r() as f32 / 255.0,
-                    particle.color.g() as f32 / 255.0,
-                    particle.color.b() as f32 / 255.0,
-                    particle.color.a() as f32 / 255.0,
-                ];
-                
-                // Add vertices for the particle shape
-                let shape_vertices = self.generate_shape_vertices(
-                    particle.xy.x,
-                    particle.xy.y,
-                    particle.weight() * 2.0, // Scale size based on weight
-                    color,
-                    particle.shape,
-                );
-                vertices.extend(shape_vertices);
-            }
+        let (trail_view, trail_fade_pipeline, trail_pipeline) = if density_mode {
+            (self.density.view(), self.density.fade_pipeline(), self.density.accumulate_pipeline())
+        } else {
+            (&self.particle_view, &self.fade_pipeline, &self.particle_pipelines[model.blend_mode as usize])
+        };
 
-            // Update vertex buffer with new data
-            queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
-            self.num_vertices = vertices.len() as u32;
+        // Fade the trails drawn so far toward the background. Each fade takes at least one 8-bit
+        // step off every pixel, so small fades are saved up and applied a few frames at a time.
+        // Otherwise slow fades would all end up as fast as that one step per frame.
+        const MIN_FADE: f64 = 0.008;
+        if should_clear || model.trail_fade <= 0.0 {
+            self.pending_fade = 0.0;
+        } else {
+            self.pending_fade -= (1.0 - model.trail_fade.clamp(0.0, 1.0) as f64).ln();
+        }
+        if self.pending_fade >= MIN_FADE {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Fade Particles"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: trail_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            let keep = (-self.pending_fade).exp();
+            self.pending_fade = 0.0;
+            rpass.set_pipeline(trail_fade_pipeline);
+            rpass.set_blend_constant(wgpu::Color {
+                r: keep,
+                g: keep,
+                b: keep,
+                a: keep,
+            });
+            rpass.draw(0..3, 0..1);
         }
 
         // Render particles to particle texture
@@ -1089,7 +1467,7 @@ impl Renderer {
             let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Particles"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &self.particle_view,
+                    view: trail_view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Load,
@@ -1101,20 +1479,23 @@ impl Renderer {
                 timestamp_writes: None,
             });
 
-            rpass.set_pipeline(&self.particle_pipeline);
+            rpass.set_pipeline(trail_pipeline);
             rpass.set_bind_group(0, &self.uniform_bind_group, &[]);
-            rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            if self.num_vertices > 0 {
-                rpass.draw(0..self.num_vertices, 0..1);
-            }
+            rpass.set_bind_group(1, self.brush_texture.bind_group(), &[]);
+            self.shape_meshes.draw(&mut rpass, self.instance_buffer.buffer(), &self.instances);
+        }
+
+        if density_mode {
+            self.density
+                .tone_map(encoder, &self.particle_view, wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT));
         }
 
-        // Composite all layers to the final view
+        // Composite all layers for post-processing
         {
             let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Composite Layers"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view,
+                    view: self.post.input_view(),
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
@@ -1135,11 +1516,71 @@ impl Renderer {
             rpass.set_bind_group(0, &self.background_bind_group, &[]);
             rpass.set_bind_group(1, &self.vector_bind_group, &[]);
             rpass.set_bind_group(2, &self.particle_texture_bind_group, &[]);
+            rpass.set_bind_group(3, &self.composite_bind_group, &[]);
+            rpass.draw(0..3, 0..1);
+        }
+
+        let frame = PostFrame {
+            origin: [0.0, 0.0],
+            image_size: [canvas_size.x, canvas_size.y],
+            scale: 1.0,
+            seed: model.frame_count as u32,
+        };
+        self.post.apply(queue, encoder, &model.post, frame);
+
+        // Fit the finished canvas into the window
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Present Canvas"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            rpass.set_pipeline(&self.present_pipeline);
+            rpass.set_bind_group(0, &self.present_texture_bind_group, &[]);
+            rpass.set_bind_group(1, &self.present_bind_group, &[]);
             rpass.draw(0..3, 0..1);
         }
     }
     
+    /// One layer at canvas size as RGBA rows, with color premultiplied by alpha
+    pub fn read_layer(&self, device: &wgpu::Device, queue: &wgpu::Queue, layer: Layer) -> std::io::Result<Vec<u8>> {
+        let texture = match layer {
+            Layer::Background => &self.background_texture,
+            Layer::Vectors => &self.vector_texture,
+            Layer::Particles => &self.particle_texture,
+        };
+        read_texture_rgba(device, queue, texture)
+    }
+
+    /// The layers composited and post-processed at canvas size, without the letterbox or the UI,
+    /// as RGBA rows. This is the last frame rendered.
+    pub fn read_composite(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> std::io::Result<Vec<u8>> {
+        read_texture_rgba(device, queue, self.post.output_texture())
+    }
+
+    // Room for `vertices` of geometry, and at least one
+    fn create_vertex_buffer(device: &wgpu::Device, label: &str, vertices: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (vertices.max(1) * std::mem::size_of::<Vertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
     pub fn resize(&mut self, device: &wgpu::Device, new_size: (u32, u32)) {
+        // The new vector layer starts out transparent
+        self.vector_layer = None;
         // Recreate layer textures with new size
         self.background_texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("Background Texture"),
@@ -1151,8 +1592,8 @@ impl Renderer {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            format: self.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         });
         
@@ -1168,8 +1609,8 @@ impl Renderer {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            format: self.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         });
         
@@ -1185,48 +1626,13 @@ impl Renderer {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            format: self.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         });
         
         self.particle_view = self.particle_texture.create_view(&wgpu::TextureViewDescriptor::default());
-
-        // Recreate MSAA texture with new size
-        self.msaa_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("MSAA Texture"),
-            size: wgpu::Extent3d {
-                width: new_size.0,
-                height: new_size.1,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 4,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            view_formats: &[],
-        });
-        
-        self.msaa_view = self.msaa_texture.create_view(&wgpu::TextureViewDescriptor::default());
-        
-        // Recreate resolve texture with new size
-        self.resolve_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Resolve Texture"),
-            size: wgpu::Extent3d {
-                width: new_size.0,
-                height: new_size.1,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-            view_formats: &[],
-        });
-        
-        self.resolve_view = self.resolve_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.density.resize(device, new_size);
         
         // Create samplers for each layer
         let background_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
@@ -1319,6 +1725,10 @@ impl Renderer {
             ],
         });
 
+        self.post.resize(device, new_size);
+        self.present_texture_bind_group =
+            create_present_bind_group(device, &self.present_texture_bind_group_layout, self.post.output_view());
+
         self.size = new_size;
     }
 } 
\ No newline at end of file
@@ -0,0 +1,223 @@
+use crate::{
+    export::{timestamped_path, write_png},
+    model::constants::SIMULATION_FRAMES_PER_SECOND,
+};
+use log::info;
+use std::fmt::{self, Display};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SequenceFormat {
+    #[default]
+    PngSequence,
+    Gif,
+}
+
+impl SequenceFormat {
+    pub const ALL: [Self; 2] = [Self::PngSequence, Self::Gif];
+}
+
+impl Display for SequenceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PngSequence => write!(f, "Numbered PNGs"),
+            Self::Gif => write!(f, "Animated GIF"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SequenceSettings {
+    pub format: SequenceFormat,
+    /// Capture one frame every this many simulation frames
+    pub frame_step: u32,
+    /// Stop after capturing this many frames
+    pub frame_limit: u32,
+}
+
+impl Default for SequenceSettings {
+    fn default() -> Self {
+        Self {
+            format: SequenceFormat::default(),
+            frame_step: 2,
+            frame_limit: 300,
+        }
+    }
+}
+
+impl SequenceSettings {
+    /// How long each captured frame lasts in an animated GIF, in hundredths of a second. GIF delays
+    /// can't be any finer, so the playback rate is rounded.
+    pub fn gif_delay(&self) -> u16 {
+        let delay = self.frame_step as f64 * 100.0 / SIMULATION_FRAMES_PER_SECOND;
+        // Most viewers play anything shorter than 2/100 of a second much slower
+        (delay.round() as u16).max(2)
+    }
+}
+
+enum Output {
+    Pngs { directory: PathBuf },
+    Gif { encoder: gif::Encoder<BufWriter<File>>, path: PathBuf },
+}
+
+/// Captures composited frames as the simulation steps, so the output plays back at the simulation's
+/// rate however long each frame took to draw
+pub struct SequenceRecorder {
+    settings: SequenceSettings,
+    size: (u32, u32),
+    output: Output,
+    frames_captured: u32,
+    frames_since_capture: u32,
+}
+
+impl SequenceRecorder {
+    /// Start a new sequence of `size` frames in `directory`
+    pub fn start(directory: &Path, settings: SequenceSettings, size: (u32, u32)) -> io::Result<Self> {
+        let output = match settings.format {
+            SequenceFormat::PngSequence => {
+                let directory = timestamped_path(directory, "sequence", "")?;
+                fs::create_dir_all(&directory)?;
+                Output::Pngs { directory }
+            }
+            SequenceFormat::Gif => {
+                let (Ok(width), Ok(height)) = (u16::try_from(size.0), u16::try_from(size.1)) else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{}x{} is too large for a GIF", size.0, size.1),
+                    ));
+                };
+                let path = timestamped_path(directory, "flow", "gif")?;
+                let file = BufWriter::new(File::create(&path)?);
+                let mut encoder = gif::Encoder::new(file, width, height, &[]).map_err(io::Error::other)?;
+                encoder.set_repeat(gif::Repeat::Infinite).map_err(io::Error::other)?;
+                Output::Gif { encoder, path }
+            }
+        };
+        info!("Started recording {} frames of {}", settings.frame_limit, settings.format);
+
+        Ok(Self {
+            settings,
+            size,
+            output,
+            frames_captured: 0,
+            // Capture the first frame straight away
+            frames_since_capture: u32::MAX,
+        })
+    }
+
+    /// Call once per simulation frame. Returns true when this frame should be captured.
+    pub fn wants_frame(&mut self) -> bool {
+        self.frames_since_capture = self.frames_since_capture.saturating_add(1);
+        if self.is_finished() || self.frames_since_capture < self.settings.frame_step {
+            return false;
+        }
+        self.frames_since_capture = 0;
+        true
+    }
+
+    /// Add a frame of tightly packed RGBA rows
+    pub fn add_frame(&mut self, mut pixels: Vec<u8>) -> io::Result<()> {
+        let (width, height) = self.size;
+        if pixels.len() != width as usize * height as usize * 4 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "frame size changed while recording",
+            ));
+        }
+
+        match &mut self.output {
+            Output::Pngs { directory } => {
+                let path = directory.join(format!("frame-{:05}.png", self.frames_captured));
+                write_png(&path, self.size, pixels)?;
+            }
+            Output::Gif { encoder, .. } => {
+                // Quantize to a 256 color palette. 10 is the usual tradeoff between speed and quality.
+                let mut frame = gif::Frame::from_rgba_speed(width as u16, height as u16, &mut pixels, 10);
+                frame.delay = self.settings.gif_delay();
+                encoder.write_frame(&frame).map_err(io::Error::other)?;
+            }
+        }
+        self.frames_captured += 1;
+        Ok(())
+    }
+
+    pub fn frames_captured(&self) -> u32 {
+        self.frames_captured
+    }
+
+    pub fn frame_limit(&self) -> u32 {
+        self.settings.frame_limit
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frames_captured >= self.settings.frame_limit
+    }
+
+    /// Stop recording, returning where the frames went
+    pub fn finish(self) -> io::Result<PathBuf> {
+        let path = match self.output {
+            Output::Pngs { directory } => directory,
+            Output::Gif { encoder, path } => {
+                // Writes the GIF's trailer
+                encoder.into_inner()?.flush()?;
+                path
+            }
+        };
+        info!("Recorded {} frames to {}", self.frames_captured, path.display());
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(frame_step: u32, frame_limit: u32) -> SequenceSettings {
+        SequenceSettings { format: SequenceFormat::PngSequence, frame_step, frame_limit }
+    }
+
+    // A recorder that never writes, since these tests only capture frames in name
+    fn recorder(settings: SequenceSettings) -> SequenceRecorder {
+        SequenceRecorder {
+            settings,
+            size: (1, 1),
+            output: Output::Pngs { directory: PathBuf::new() },
+            frames_captured: 0,
+            frames_since_capture: u32::MAX,
+        }
+    }
+
+    #[test]
+    fn gif_delay_follows_the_step() {
+        assert_eq!(settings(6, 1).gif_delay(), 10);
+        assert_eq!(settings(3, 1).gif_delay(), 5);
+        // 2/60 of a second rounds to 3 hundredths
+        assert_eq!(settings(2, 1).gif_delay(), 3);
+        assert_eq!(settings(1, 1).gif_delay(), 2);
+    }
+
+    #[test]
+    fn wants_every_step_frames_until_finished() {
+        let mut recorder = recorder(settings(3, 2));
+        let mut wanted = Vec::new();
+        for _ in 0..10 {
+            let wants = recorder.wants_frame();
+            if wants {
+                recorder.frames_captured += 1;
+            }
+            wanted.push(wants);
+        }
+        assert_eq!(
+            wanted,
+            [true, false, false, true, false, false, false, false, false, false]
+        );
+    }
+
+    #[test]
+    fn a_step_of_one_wants_every_frame() {
+        let mut recorder = recorder(settings(1, 100));
+        assert!((0..5).all(|_| recorder.wants_frame()));
+    }
+}
@@ -0,0 +1,63 @@
+// One direction of a separable Gaussian blur. Bloom runs the horizontal pass with a threshold so
+// only the bright parts of the image spread.
+
+@group(0) @binding(0)
+var source_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+
+struct Blur {
+    // (1, 0) or (0, 1)
+    direction: vec2<f32>,
+    // How far the blur reaches, in pixels
+    radius: f32,
+    // Pixels darker than this are left out, or nothing is when negative
+    threshold: f32,
+};
+
+@group(0) @binding(2)
+var<uniform> blur: Blur;
+
+// Taps on either side of the center before they start spreading out to cover the radius
+const MAX_TAPS: f32 = 48.0;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    var pos = array<vec2<f32>, 3>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(3.0, -1.0),
+        vec2<f32>(-1.0, 3.0)
+    );
+    return vec4<f32>(pos[vertex_index], 0.0, 1.0);
+}
+
+fn bright(color: vec4<f32>) -> vec4<f32> {
+    if blur.threshold < 0.0 {
+        return color;
+    }
+    // Scale rather than clip, so hues survive the threshold
+    let luma = dot(color.rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+    return color * (max(luma - blur.threshold, 0.0) / max(luma, 1e-4));
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(source_texture));
+    let uv = position.xy / size;
+
+    let spacing = max(1.0, blur.radius / MAX_TAPS);
+    let taps = i32(ceil(blur.radius / spacing));
+    // The radius covers three standard deviations
+    let sigma = max(blur.radius / 3.0, 0.5);
+
+    var sum = vec4<f32>(0.0);
+    var total = 0.0;
+    for (var i = -taps; i <= taps; i++) {
+        let offset = f32(i) * spacing;
+        let weight = exp(-offset * offset / (2.0 * sigma * sigma));
+        let color = textureSampleLevel(source_texture, source_sampler, uv + blur.direction * offset / size, 0.0);
+        sum += bright(color) * weight;
+        total += weight;
+    }
+    return sum / total;
+}
@@ -0,0 +1,62 @@
+// Tone maps the particle density onto a layer. The density texture holds the sum of the
+// particles' alpha-weighted colors in rgb and the sum of their alphas, the hits, in a.
+
+@group(0) @binding(0)
+var density_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var lut_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var lut_sampler: sampler;
+
+struct ToneMap {
+    exposure: f32,
+    gamma: f32,
+    // 0 is log, 1 is exposure
+    curve: u32,
+    // 0 is the particle colors, 1 is white, 2 is the LUT
+    color: u32,
+};
+
+@group(0) @binding(3)
+var<uniform> tone_map: ToneMap;
+
+// Hits the log curve counts as full brightness at an exposure of 1
+const LOG_WHITE: f32 = 255.0;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    var pos = array<vec2<f32>, 3>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(3.0, -1.0),
+        vec2<f32>(-1.0, 3.0)
+    );
+    return vec4<f32>(pos[vertex_index], 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let density = textureLoad(density_texture, vec2<i32>(position.xy), 0);
+    let hits = max(density.a, 0.0) * tone_map.exposure;
+
+    var level: f32;
+    if tone_map.curve == 0u {
+        level = log(1.0 + hits) / log(1.0 + LOG_WHITE);
+    } else {
+        level = 1.0 - exp(-hits);
+    }
+    level = pow(clamp(level, 0.0, 1.0), 1.0 / tone_map.gamma);
+
+    // Premultiplied, to blend over whatever lies underneath
+    if tone_map.color == 2u {
+        // Sample texel centers so both ends of the LUT are reachable
+        let u = (0.5 + level * 255.0) / 256.0;
+        let color = textureSampleLevel(lut_texture, lut_sampler, vec2<f32>(u, 0.5), 0.0).rgb;
+        let coverage = clamp(hits, 0.0, 1.0);
+        return vec4<f32>(color * coverage, coverage);
+    }
+    var color = vec3<f32>(1.0);
+    if tone_map.color == 0u {
+        color = density.rgb / max(density.a, 1e-6);
+    }
+    return vec4<f32>(color * level, level);
+}
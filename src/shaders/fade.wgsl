@@ -0,0 +1,21 @@
+// Fades the particle layer toward transparent. The pipeline's blend state multiplies the layer by
+// the blend constant and then subtracts this fragment's color.
+
+// 8-bit layers round small fades back up, which would leave faint ghosts of old trails behind
+// forever. Subtracting one step as well makes sure everything fades out eventually.
+const FLOOR: f32 = 1.0 / 255.0;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    var pos = array<vec2<f32>, 3>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(3.0, -1.0),
+        vec2<f32>(-1.0, 3.0)
+    );
+    return vec4<f32>(pos[vertex_index], 0.0, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(FLOOR);
+}
@@ -0,0 +1,65 @@
+// The last post-processing pass: adds the bloom to the scene, then darkens the corners and adds
+// film grain. Both work in image pixels so tiles of a larger image line up.
+
+@group(0) @binding(0)
+var scene_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var bloom_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var post_sampler: sampler;
+
+struct Effects {
+    // The whole image, which this texture may be a tile of
+    image_size: vec2<f32>,
+    // This texture's top left corner in the image
+    origin: vec2<f32>,
+    bloom_intensity: f32,
+    grain: f32,
+    // Image pixels per grain
+    grain_size: f32,
+    vignette: f32,
+    seed: u32,
+};
+
+@group(0) @binding(3)
+var<uniform> effects: Effects;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    var pos = array<vec2<f32>, 3>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(3.0, -1.0),
+        vec2<f32>(-1.0, 3.0)
+    );
+    return vec4<f32>(pos[vertex_index], 0.0, 1.0);
+}
+
+// A random number in 0..1 for each grain and frame
+fn hash(cell: vec2<u32>, seed: u32) -> f32 {
+    var h = cell.x * 1664525u + cell.y * 22695477u + seed * 2891336453u;
+    h ^= h >> 16u;
+    h *= 2246822519u;
+    h ^= h >> 13u;
+    h *= 3266489917u;
+    h ^= h >> 16u;
+    return f32(h) / 4294967295.0;
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let uv = position.xy / vec2<f32>(textureDimensions(scene_texture));
+    let scene = textureSampleLevel(scene_texture, post_sampler, uv, 0.0);
+    let bloom = textureSampleLevel(bloom_texture, post_sampler, uv, 0.0);
+    var color = scene.rgb + bloom.rgb * effects.bloom_intensity;
+
+    let pixel = effects.origin + position.xy;
+
+    // 0 at the center of the image and 1 in its corners
+    let from_center = length(pixel / effects.image_size - 0.5) * sqrt(2.0);
+    color *= 1.0 - effects.vignette * from_center * from_center;
+
+    let cell = vec2<u32>(max(pixel / effects.grain_size, vec2<f32>(0.0)));
+    color += (hash(cell, effects.seed) - 0.5) * effects.grain;
+
+    return vec4<f32>(clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)), scene.a);
+}
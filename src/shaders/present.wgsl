@@ -0,0 +1,50 @@
+// Fits the finished canvas into the window through the camera, letterboxed along whichever side
+// has room to spare
+
+@group(0) @binding(0)
+var canvas_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var canvas_sampler: sampler;
+
+struct Present {
+    // Maps NDC to canvas coordinates normalized to -0.5..0.5 as `ndc * scale + offset`
+    scale: vec2<f32>,
+    offset: vec2<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> present: Present;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var pos = array<vec2<f32>, 3>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(3.0, -1.0),
+        vec2<f32>(-1.0, 3.0)
+    );
+
+    var output: VertexOutput;
+    output.position = vec4<f32>(pos[vertex_index], 0.0, 1.0);
+    output.ndc = pos[vertex_index];
+    return output;
+}
+
+@fragment
+fn fs_main(input: VertexOutput) -> @location(0) vec4<f32> {
+    // Texture rows run top to bottom while canvas y points up
+    let canvas_xy = input.ndc * present.scale + present.offset;
+    let tex_coords = vec2<f32>(0.5 + canvas_xy.x, 0.5 - canvas_xy.y);
+
+    let color = textureSample(canvas_texture, canvas_sampler, tex_coords);
+
+    // Letterbox whatever lies outside the canvas
+    if any(tex_coords < vec2<f32>(0.0)) || any(tex_coords > vec2<f32>(1.0)) {
+        return vec4<f32>(0.08, 0.08, 0.08, 1.0);
+    }
+    return color;
+}
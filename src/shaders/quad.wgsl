@@ -13,29 +13,22 @@ var particle_texture: texture_2d<f32>;
 @group(2) @binding(1)
 var particle_sampler: sampler;
 
-struct VertexOutput {
-    @builtin(position) position: vec4<f32>,
-    @location(0) tex_coords: vec2<f32>,
+struct Composite {
+    // The particles' `BlendMode`, in declaration order
+    particle_blend: u32,
 };
 
+@group(3) @binding(0)
+var<uniform> composite: Composite;
+
 @vertex
-fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
     var pos = array<vec2<f32>, 3>(
         vec2<f32>(-1.0, -1.0),
         vec2<f32>(3.0, -1.0),
         vec2<f32>(-1.0, 3.0)
     );
-
-    var tex_coords = array<vec2<f32>, 3>(
-        vec2<f32>(0.0, 1.0),
-        vec2<f32>(2.0, 1.0),
-        vec2<f32>(0.0, -1.0)
-    );
-
-    var output: VertexOutput;
-    output.position = vec4<f32>(pos[vertex_index], 0.0, 1.0);
-    output.tex_coords = tex_coords[vertex_index];
-    return output;
+    return vec4<f32>(pos[vertex_index], 0.0, 1.0);
 }
 
 fn over(a: vec4<f32>, b: vec4<f32>) -> vec4<f32> {
@@ -43,14 +36,49 @@ fn over(a: vec4<f32>, b: vec4<f32>) -> vec4<f32> {
     return a + b * (1.0 - a.a);
 }
 
+// The particle layer p blended onto the layers below it, b, as `particle_composite_blending` does
+fn composite_particles(p: vec4<f32>, b: vec4<f32>) -> vec4<f32> {
+    var color: vec3<f32>;
+    switch composite.particle_blend {
+        // Additive
+        case 1u: {
+            color = b.rgb + p.rgb;
+        }
+        // Screen
+        case 2u: {
+            color = p.rgb + b.rgb * (1.0 - p.rgb);
+        }
+        // Multiply
+        case 3u: {
+            color = p.rgb * b.rgb + b.rgb * (1.0 - p.a);
+        }
+        // Lighten
+        case 4u: {
+            color = max(p.rgb, b.rgb);
+        }
+        // Subtractive
+        case 5u: {
+            color = max(b.rgb - p.rgb, vec3<f32>(0.0));
+        }
+        default: {
+            color = over(p, b).rgb;
+        }
+    }
+    return vec4<f32>(min(color, vec3<f32>(1.0)), over(p, b).a);
+}
+
 @fragment
-fn fs_main(input: VertexOutput) -> @location(0) vec4<f32> {
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    // The layers are the size of the target
+    let tex_coords = position.xy / vec2<f32>(textureDimensions(background_texture));
+
     // Sample all layers
-    let background = textureSample(background_texture, background_sampler, input.tex_coords);
-    let vector = textureSample(vector_texture, vector_sampler, input.tex_coords);
-    let particle = textureSample(particle_texture, particle_sampler, input.tex_coords);
+    let background = textureSample(background_texture, background_sampler, tex_coords);
+    let vector = textureSample(vector_texture, vector_sampler, tex_coords);
+    let particle = textureSample(particle_texture, particle_sampler, tex_coords);
 
-    // Composite layers with correct alpha blending: background -> vector -> particle
-    let color = over(particle, over(vector, background));
+    // Composite layers with correct alpha blending: background -> vector -> particle, the
+    // particles in their blend mode
+    let color = composite_particles(particle, over(vector, background));
     return color;
 }
\ No newline at end of file
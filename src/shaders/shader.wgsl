@@ -1,32 +1,69 @@
 struct Uniforms {
-    resolution: vec2<f32>,
+    // Canvas position to clip space
+    view_projection: mat4x4<f32>,
 };
 
 @group(0) @binding(0)
 var<uniform> uniforms: Uniforms;
 
+// Sprite particles are stamped with this, tinted by their color
+@group(1) @binding(0)
+var brush_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var brush_sampler: sampler;
+
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) color: vec4<f32>,
+    @location(1) uv: vec2<f32>,
 };
 
 @vertex
 fn vs_main(@location(0) position: vec2<f32>,
-           @location(1) color: vec4<f32>) -> VertexOutput {
+           @location(1) color: vec4<f32>,
+           @location(2) uv: vec2<f32>) -> VertexOutput {
     var output: VertexOutput;
     
-    // Convert from centered coordinates to normalized device coordinates
-    // Input: (-width/2, -height/2) to (width/2, height/2) with (0,0) at center
-    // NDC: (-1, -1) to (1, 1) with (0,0) at center
-    let ndc_x = position.x / (uniforms.resolution.x * 0.5);
-    let ndc_y = position.y / (uniforms.resolution.y * 0.5);
-    
-    output.position = vec4<f32>(ndc_x, ndc_y, 0.0, 1.0);
+    // Positions are in centered canvas coordinates with (0,0) at the center
+    output.position = uniforms.view_projection * vec4<f32>(position, 0.0, 1.0);
     output.color = color;
+    output.uv = uv;
+    return output;
+}
+
+// Particles are instances of their shape's mesh, which is one unit in size and points along +x.
+// Each is scaled, stretched along its heading, turned to face it and moved into place.
+@vertex
+fn vs_particle(@location(0) corner: vec2<f32>,
+               @location(1) uv: vec2<f32>,
+               @location(2) stretches: f32,
+               @location(3) alpha: f32,
+               @location(4) center: vec2<f32>,
+               @location(5) size: f32,
+               @location(6) angle: f32,
+               @location(7) stretch: f32,
+               @location(8) aspect: f32,
+               @location(9) color: vec4<f32>) -> VertexOutput {
+    var output: VertexOutput;
+
+    // The longer side of a sprite's brush spans the size
+    let extent = size * vec2<f32>(min(aspect, 1.0), min(1.0 / aspect, 1.0));
+    var local = corner * extent;
+    local.x *= mix(1.0, stretch, stretches);
+    let turn = vec2<f32>(cos(angle), sin(angle));
+    let position = center + vec2<f32>(local.x * turn.x - local.y * turn.y, local.x * turn.y + local.y * turn.x);
+
+    output.position = uniforms.view_projection * vec4<f32>(position, 0.0, 1.0);
+    output.color = vec4<f32>(color.rgb, color.a * alpha);
+    output.uv = uv;
     return output;
 }
 
+// Vertex colors have straight alpha, and the layers premultiplied
 @fragment
 fn fs_main(input: VertexOutput) -> @location(0) vec4<f32> {
-    return input.color;
-} 
\ No newline at end of file
+    // Sampled for every fragment to keep control flow uniform, and ignored outside of sprites
+    let brush = textureSample(brush_texture, brush_sampler, input.uv);
+    let color = input.color * select(vec4<f32>(1.0), brush, input.uv.x >= 0.0);
+    return vec4<f32>(color.rgb * color.a, color.a);
+}
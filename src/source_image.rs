@@ -0,0 +1,187 @@
+use crate::model::SimpleRect;
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum ImageFieldMode {
+    /// Perpendicular to the luminance gradient so particles trace edges
+    #[default]
+    AlongEdges,
+    AlongGradient,
+    HueAngle,
+}
+
+impl ImageFieldMode {
+    pub const ALL: [Self; 3] = [Self::AlongEdges, Self::AlongGradient, Self::HueAngle];
+}
+
+impl Display for ImageFieldMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AlongEdges => write!(f, "Along Edges"),
+            Self::AlongGradient => write!(f, "Along Gradient"),
+            Self::HueAngle => write!(f, "Hue as Angle"),
+        }
+    }
+}
+
+/// A photo or painting that the canvas can be seeded from
+pub struct SourceImage {
+    path: PathBuf,
+    image: image::Rgb32FImage,
+}
+
+impl SourceImage {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let image = image::open(path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            .into_rgb32f();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            image,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    /// Map a canvas position to pixel coordinates. The image is scaled to cover `rect` with its
+    /// aspect ratio preserved, cropping whichever side overflows.
+    fn to_pixel(&self, xy: Vec2, rect: &SimpleRect) -> Vec2 {
+        let (width, height) = self.image.dimensions();
+        let rect_width = rect.right - rect.left;
+        let rect_height = rect.top - rect.bottom;
+        let scale = (width as f32 / rect_width).min(height as f32 / rect_height);
+        let center = Vec2::new(width as f32, height as f32) / 2.0;
+        let rect_center = Vec2::new(rect.left + rect_width / 2.0, rect.bottom + rect_height / 2.0);
+        let offset = (xy - rect_center) * scale;
+        // Image rows run top to bottom while canvas y points up
+        Vec2::new(center.x + offset.x, center.y - offset.y)
+    }
+
+    fn pixel(&self, x: i64, y: i64) -> Vec3 {
+        let (width, height) = self.image.dimensions();
+        let x = x.clamp(0, width as i64 - 1) as u32;
+        let y = y.clamp(0, height as i64 - 1) as u32;
+        let [r, g, b] = self.image.get_pixel(x, y).0;
+        Vec3::new(r, g, b)
+    }
+
+    /// The bilinearly filtered color at a canvas position, with components from 0 to 1
+    pub fn sample(&self, xy: Vec2, rect: &SimpleRect) -> Vec3 {
+        let pixel = self.to_pixel(xy, rect) - Vec2::splat(0.5);
+        let x0 = pixel.x.floor();
+        let y0 = pixel.y.floor();
+        let tx = pixel.x - x0;
+        let ty = pixel.y - y0;
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.pixel(x0, y0).lerp(self.pixel(x0 + 1, y0), tx);
+        let bottom = self.pixel(x0, y0 + 1).lerp(self.pixel(x0 + 1, y0 + 1), tx);
+        top.lerp(bottom, ty)
+    }
+
+    pub fn luminance(&self, xy: Vec2, rect: &SimpleRect) -> f32 {
+        self.sample(xy, rect).dot(Vec3::new(0.2126, 0.7152, 0.0722))
+    }
+
+    /// The luminance gradient in canvas space, measured with a Sobel kernel whose taps are
+    /// `step` canvas units apart
+    pub fn luminance_gradient(&self, xy: Vec2, rect: &SimpleRect, step: f32) -> Vec2 {
+        let l = |dx: f32, dy: f32| self.luminance(xy + Vec2::new(dx, dy) * step, rect);
+        let gx = (l(1.0, 1.0) + 2.0 * l(1.0, 0.0) + l(1.0, -1.0))
+            - (l(-1.0, 1.0) + 2.0 * l(-1.0, 0.0) + l(-1.0, -1.0));
+        let gy = (l(-1.0, 1.0) + 2.0 * l(0.0, 1.0) + l(1.0, 1.0))
+            - (l(-1.0, -1.0) + 2.0 * l(0.0, -1.0) + l(1.0, -1.0));
+        Vec2::new(gx, gy)
+    }
+
+    /// Hue in degrees, or `None` for grays that don't have one
+    pub fn hue(&self, xy: Vec2, rect: &SimpleRect) -> Option<f32> {
+        let color = self.sample(xy, rect);
+        let max = color.max_element();
+        let min = color.min_element();
+        let chroma = max - min;
+        if chroma < 1e-4 {
+            return None;
+        }
+
+        let hue = if max == color.x {
+            ((color.y - color.z) / chroma).rem_euclid(6.0)
+        } else if max == color.y {
+            (color.z - color.x) / chroma + 2.0
+        } else {
+            (color.x - color.y) / chroma + 4.0
+        };
+        Some(hue * 60.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_vector::new_image_flow_vectors;
+    use crate::model::Model;
+
+    const WIDTH: u32 = 400;
+    const HEIGHT: u32 = 300;
+
+    // An image the size of the canvas whose pixels only change from left to right
+    fn horizontal_ramp(color: impl Fn(f32) -> [f32; 3]) -> SourceImage {
+        SourceImage {
+            path: PathBuf::from("ramp.png"),
+            image: image::Rgb32FImage::from_fn(WIDTH, HEIGHT, |x, _| {
+                image::Rgb(color((x as f32 + 0.5) / WIDTH as f32))
+            }),
+        }
+    }
+
+    fn headings(mode: ImageFieldMode, source_image: SourceImage) -> (Model, Vec<f32>) {
+        let mut model = Model::new(Vec2::new(WIDTH as f32, HEIGHT as f32));
+        model.image_field_mode = mode;
+        model.source_image = Some(source_image);
+        let headings = new_image_flow_vectors(&model).iter().map(|fv| fv.heading()).collect();
+        (model, headings)
+    }
+
+    #[test]
+    fn a_brightness_ramp_points_the_field_along_it() {
+        let gray = |t: f32| [t; 3];
+        let (_, along) = headings(ImageFieldMode::AlongGradient, horizontal_ramp(gray));
+        assert!(along.iter().all(|heading| heading.abs() < 1e-3), "{:?}", along);
+
+        // Edges run across the ramp, a quarter turn from the gradient
+        let (_, edges) = headings(ImageFieldMode::AlongEdges, horizontal_ramp(gray));
+        assert!(edges.iter().all(|heading| (heading - 90.0).abs() < 1e-3), "{:?}", edges);
+    }
+
+    #[test]
+    fn a_hue_ramp_turns_the_field_across_the_canvas() {
+        // Fully saturated, from red at the left edge through to magenta at the right
+        let rainbow = |t: f32| {
+            let hue = t * 300.0;
+            let channel = |n: f32| {
+                let k = (n + hue / 60.0).rem_euclid(6.0);
+                1.0 - k.min(4.0 - k).clamp(0.0, 1.0)
+            };
+            [channel(5.0), channel(3.0), channel(1.0)]
+        };
+        let (model, headings) = headings(ImageFieldMode::HueAngle, horizontal_ramp(rainbow));
+        for (fv, heading) in new_image_flow_vectors(&model).iter().zip(&headings) {
+            // Vectors hanging off the edge of the canvas see the outermost pixels
+            let half_pixel = 0.5 / WIDTH as f32;
+            let t = ((fv.position().x - model.window_rect.left) / WIDTH as f32).clamp(half_pixel, 1.0 - half_pixel);
+            let difference = (heading - t * 300.0 + 180.0).rem_euclid(360.0) - 180.0;
+            assert!(difference.abs() < 1.0, "{} at {} should be {}", heading, fv.position(), t * 300.0);
+        }
+    }
+}